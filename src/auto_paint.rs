@@ -0,0 +1,428 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::Matrix4;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+
+use crate::{
+    instancing::triangle_corners,
+    mesh::Mesh,
+    model::Model,
+    model_orca::OrcaModel,
+    paint_regions::refine_depth,
+    paint_tree::{interpolate, PaintTree, Repaint},
+    splitting::Vec3,
+    topology::Topology,
+};
+
+/// A range of world Z painted with one state, from `z_from` up to but not including `z_to`
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HeightBand {
+    pub z_from: f64,
+    pub z_to: f64,
+    pub state: u8,
+}
+
+impl HeightBand {
+    fn contains(&self, z: f64) -> bool {
+        z >= self.z_from && z < self.z_to
+    }
+
+    fn overlaps(&self, z_min: f64, z_max: f64) -> bool {
+        if z_min == z_max {
+            self.contains(z_min)
+        } else {
+            self.z_from < z_max && self.z_to > z_min
+        }
+    }
+}
+
+/// What an automatic paint operation changed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoPaintReport {
+    pub triangles: usize,
+    /// triangles painted at least in part
+    pub selected: usize,
+    /// triangles whose paint string changed
+    pub changed: usize,
+}
+
+impl std::ops::AddAssign for AutoPaintReport {
+    fn add_assign(&mut self, other: Self) {
+        self.triangles += other.triangles;
+        self.selected += other.selected;
+        self.changed += other.changed;
+    }
+}
+
+impl std::fmt::Display for AutoPaintReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} triangles selected, {} changed",
+            self.selected, self.triangles, self.changed
+        )
+    }
+}
+
+/// Selects triangles by the angle between their normal and a direction
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct OrientationFilter {
+    pub direction: [f64; 3],
+    /// in degrees
+    pub angle: f64,
+    /// select triangles turned further than `angle` from the direction instead
+    pub beyond: bool,
+}
+
+impl OrientationFilter {
+    /// Triangles facing up, within `angle` degrees of +Z
+    pub fn top(angle: f64) -> Self {
+        Self {
+            direction: [0., 0., 1.],
+            angle,
+            beyond: false,
+        }
+    }
+
+    pub fn selects(&self, normal: &Vec3) -> bool {
+        let direction = Vec3::from(self.direction);
+        if normal.norm() == 0. || direction.norm() == 0. {
+            return false;
+        }
+        let angle = normal.angle(&direction).to_degrees();
+        if self.beyond {
+            angle > self.angle
+        } else {
+            angle <= self.angle
+        }
+    }
+}
+
+/// Corners of every triangle after `transform`
+pub(crate) fn transformed_corners(mesh: &Mesh, transform: &Matrix4<f64>) -> Vec<[Vec3; 3]> {
+    (0..mesh.triangles.triangle.len())
+        .map(|i| triangle_corners(mesh, i).map(|p| transform.transform_point(&p.into()).coords))
+        .collect()
+}
+
+/// Unit normals of triangles after `transform`, zero for degenerate ones
+///
+/// A mirroring transform flips the winding, so the normals are flipped back.
+pub(crate) fn transformed_normals(corners: &[[Vec3; 3]], transform: &Matrix4<f64>) -> Vec<Vec3> {
+    let sign = transform.fixed_view::<3, 3>(0, 0).determinant().signum();
+    corners
+        .iter()
+        .map(|c| {
+            ((c[1] - c[0]).cross(&(c[2] - c[0])) * sign)
+                .try_normalize(f64::EPSILON)
+                .unwrap_or_else(Vec3::zeros)
+        })
+        .collect()
+}
+
+/// The state of the first band containing each Z, or keeps the paint outside all bands
+fn classify_bands(bands: &[HeightBand], p: &[Vec3; 3]) -> Repaint {
+    let z_min = p.iter().map(|p| p.z).fold(f64::INFINITY, f64::min);
+    let z_max = p.iter().map(|p| p.z).fold(f64::NEG_INFINITY, f64::max);
+
+    let Some(first) = bands.iter().find(|b| b.overlaps(z_min, z_max)) else {
+        return Repaint::Keep;
+    };
+    if first.z_from <= z_min && (z_max < first.z_to || (z_max == first.z_to && z_min < z_max)) {
+        return Repaint::Paint(first.state);
+    }
+
+    let z = (p[0].z + p[1].z + p[2].z) / 3.;
+    Repaint::Mixed(bands.iter().find(|b| b.contains(z)).map(|b| b.state))
+}
+
+impl Mesh {
+    /// Repaints the regions `f` selects on every triangle, see [`PaintTree::repaint`]
+    ///
+    /// `f` gets the triangle index and sub-triangles after `transform`. Partly selected leaves
+    /// are split until their edges are shorter than `resolution`. Nothing is written if any
+    /// paint can't be decoded.
+    pub(crate) fn repaint_with<F>(
+        &mut self,
+        transform: &Matrix4<f64>,
+        resolution: f64,
+        orca: bool,
+        f: F,
+    ) -> Result<AutoPaintReport>
+    where
+        F: Fn(usize, &[Vec3; 3]) -> Repaint + Sync,
+    {
+        let corners = transformed_corners(self, transform);
+
+        let repainted = self
+            .triangles
+            .triangle
+            .par_iter()
+            .zip(corners.par_iter())
+            .enumerate()
+            .map(|(i, (t, corners))| {
+                let mut report = AutoPaintReport {
+                    triangles: 1,
+                    ..Default::default()
+                };
+                if f(i, corners) == Repaint::Keep {
+                    return Ok((None, report));
+                }
+                report.selected = 1;
+
+                let tree = PaintTree::from_paint(t.paint())?;
+                let new = tree.repaint(refine_depth(corners, resolution), &mut |sub| {
+                    f(i, &sub.map(|c| interpolate(corners, &c)))
+                });
+
+                let paint = new.to_paint();
+                if paint.as_deref() == t.paint() {
+                    return Ok((None, report));
+                }
+                report.changed = 1;
+                Ok((Some(paint), report))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut out = AutoPaintReport::default();
+        for (t, (paint, r)) in self.triangles.triangle.iter_mut().zip(repainted) {
+            if let Some(paint) = paint {
+                t.set_paint(paint, orca);
+            }
+            out += r;
+        }
+        Ok(out)
+    }
+
+    /// Paints the selected triangles whole, keeping the paint of the others
+    fn paint_selected(&mut self, selected: &[bool], state: u8, orca: bool) -> AutoPaintReport {
+        let paint = PaintTree::Leaf(state).to_paint();
+        let mut out = AutoPaintReport::default();
+        for (t, selected) in self.triangles.triangle.iter_mut().zip(selected) {
+            out.triangles += 1;
+            if !selected {
+                continue;
+            }
+            out.selected += 1;
+            if t.paint() != paint.as_deref() {
+                t.set_paint(paint.clone(), orca);
+                out.changed += 1;
+            }
+        }
+        out
+    }
+
+    /// Paints every triangle whose normal after `transform` passes `filter`
+    pub fn paint_by_orientation(
+        &mut self,
+        transform: &Matrix4<f64>,
+        filter: &OrientationFilter,
+        state: u8,
+        orca: bool,
+    ) -> Result<AutoPaintReport> {
+        let corners = transformed_corners(self, transform);
+        let selected = transformed_normals(&corners, transform)
+            .iter()
+            .map(|n| filter.selects(n))
+            .collect::<Vec<_>>();
+
+        Ok(self.paint_selected(&selected, state, orca))
+    }
+
+    /// Paints the surface around `seed` up to edges sharper than `tolerance` degrees
+    ///
+    /// Like the slicers' smart fill, the fill spreads across every edge where the normals of
+    /// the two triangles differ by at most `tolerance`.
+    pub fn bucket_fill_paint(
+        &mut self,
+        transform: &Matrix4<f64>,
+        seed: usize,
+        tolerance: f64,
+        state: u8,
+        orca: bool,
+    ) -> Result<AutoPaintReport> {
+        ensure!(
+            seed < self.triangles.triangle.len(),
+            "Seed triangle {} out of range",
+            seed
+        );
+
+        let corners = transformed_corners(self, transform);
+        let normals = transformed_normals(&corners, transform);
+        let neighbors = Topology::new(self)?.all_neighbors();
+
+        let mut selected = vec![false; corners.len()];
+        selected[seed] = true;
+        let mut queue = VecDeque::from([seed]);
+        while let Some(t) = queue.pop_front() {
+            for &n in &neighbors[t] {
+                if !selected[n] && normals[t].angle(&normals[n]).to_degrees() <= tolerance {
+                    selected[n] = true;
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        Ok(self.paint_selected(&selected, state, orca))
+    }
+
+    /// Paints horizontal bands by the Z of each point after `transform`
+    ///
+    /// Where bands overlap the first one wins, paint outside all bands is kept. Triangles
+    /// crossing a band edge are subdivided until their edges are shorter than `resolution`.
+    pub fn paint_height_bands(
+        &mut self,
+        transform: &Matrix4<f64>,
+        bands: &[HeightBand],
+        resolution: f64,
+        orca: bool,
+    ) -> Result<AutoPaintReport> {
+        for b in bands {
+            ensure!(
+                b.z_from < b.z_to,
+                "Height band {} -> {} is empty",
+                b.z_from,
+                b.z_to
+            );
+        }
+        ensure!(resolution > 0., "Resolution must be positive");
+
+        self.repaint_with(transform, resolution, orca, |_, p| classify_bands(bands, p))
+    }
+}
+
+impl OrcaModel {
+    /// Paints height bands on the object at `index`, with Z measured on the build plate
+    pub fn paint_height_bands(
+        &mut self,
+        index: usize,
+        bands: &[HeightBand],
+        resolution: f64,
+    ) -> Result<AutoPaintReport> {
+        // every part is painted aside first, so a failure leaves the object as it was
+        let comps = self.object_components(index)?.clone();
+        let mut out = AutoPaintReport::default();
+        let mut painted = vec![];
+        for comp in comps.iter() {
+            let transform = self.component_world_transform(index, comp)?;
+            let mut mesh = self.component_mesh(comp)?.clone();
+            out += mesh.paint_height_bands(&transform, bands, resolution, true)?;
+            painted.push(mesh);
+        }
+        for (comp, mesh) in comps.iter().zip(painted) {
+            *self.component_mesh_mut(comp)? = mesh;
+        }
+
+        debug!("painted height bands on object {}: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl OrcaModel {
+    /// Paints the triangles of the object at `index` facing as `filter` selects on the build
+    /// plate
+    pub fn paint_by_orientation(
+        &mut self,
+        index: usize,
+        filter: &OrientationFilter,
+        state: u8,
+    ) -> Result<AutoPaintReport> {
+        // every part is painted aside first, so a failure leaves the object as it was
+        let comps = self.object_components(index)?.clone();
+        let mut out = AutoPaintReport::default();
+        let mut painted = vec![];
+        for comp in comps.iter() {
+            let transform = self.component_world_transform(index, comp)?;
+            let mut mesh = self.component_mesh(comp)?.clone();
+            out += mesh.paint_by_orientation(&transform, filter, state, true)?;
+            painted.push(mesh);
+        }
+        for (comp, mesh) in comps.iter().zip(painted) {
+            *self.component_mesh_mut(comp)? = mesh;
+        }
+
+        debug!("painted by orientation on object {}: {}", index, out);
+        Ok(out)
+    }
+
+    /// Bucket fills the object at `index` from triangle `triangle` of its part `part`
+    ///
+    /// The fill continues across touching parts.
+    pub fn bucket_fill_paint(
+        &mut self,
+        index: usize,
+        part: usize,
+        triangle: usize,
+        tolerance: f64,
+        state: u8,
+    ) -> Result<AutoPaintReport> {
+        let tris = self.world_triangles(index)?;
+        let seed = tris
+            .source
+            .iter()
+            .position(|s| *s == (part, triangle))
+            .with_context(|| format!("No triangle {} in part {}", triangle, part))?;
+
+        let mut soup = tris.soup_mesh();
+        let out = soup.bucket_fill_paint(&Matrix4::identity(), seed, tolerance, state, true)?;
+        self.write_soup_paint(index, &tris, soup)?;
+
+        debug!("bucket filled object {}: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Paints height bands on the object at `index` as PrusaSlicer `mmu_segmentation`
+    pub fn paint_height_bands(
+        &mut self,
+        index: usize,
+        bands: &[HeightBand],
+        resolution: f64,
+    ) -> Result<AutoPaintReport> {
+        let transform = self.item_transform(index)?;
+        let mesh = self.object_mesh_mut(index)?;
+
+        let out = mesh.paint_height_bands(&transform, bands, resolution, false)?;
+        debug!("painted height bands on object {}: {}", index, out);
+        Ok(out)
+    }
+
+    /// Paints the triangles of the object at `index` facing as `filter` selects, as PrusaSlicer
+    /// `mmu_segmentation`
+    pub fn paint_by_orientation(
+        &mut self,
+        index: usize,
+        filter: &OrientationFilter,
+        state: u8,
+    ) -> Result<AutoPaintReport> {
+        let transform = self.item_transform(index)?;
+        self.object_mesh_mut(index)?
+            .paint_by_orientation(&transform, filter, state, false)
+    }
+
+    /// Bucket fills the object at `index` from `seed`, as PrusaSlicer `mmu_segmentation`
+    pub fn bucket_fill_paint(
+        &mut self,
+        index: usize,
+        seed: usize,
+        tolerance: f64,
+        state: u8,
+    ) -> Result<AutoPaintReport> {
+        let transform = self.item_transform(index)?;
+        self.object_mesh_mut(index)?
+            .bucket_fill_paint(&transform, seed, tolerance, state, false)
+    }
+
+    pub(crate) fn object_mesh_mut(&mut self, index: usize) -> Result<&mut Mesh> {
+        self.resources
+            .object
+            .get_mut(index)
+            .ok_or_else(|| anyhow!("Object index {} out of range", index))?
+            .object
+            .get_mesh_mut()
+            .ok_or_else(|| anyhow!("Object {} has no mesh", index))
+    }
+}
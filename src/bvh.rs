@@ -0,0 +1,356 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::Matrix4;
+
+use crate::{
+    auto_paint::transformed_corners, mesh::Mesh, model::Model, model_orca::OrcaModel,
+    splitting::Vec3, utils::closest_point_on_triangle,
+};
+
+/// Most triangles in a leaf of the tree
+const LEAF_SIZE: usize = 4;
+
+/// Subtrees with more triangles than this are built in parallel
+const PARALLEL_BUILD: usize = 4096;
+
+/// Directions rays are cast in for inside tests, away from the axes so they rarely graze edges
+const RAY_DIRECTIONS: [[f64; 3]; 3] = [
+    [1., 0.3137, 0.1731],
+    [-0.2419, 1., 0.4142],
+    [0.2718, -0.1618, 1.],
+];
+
+/// An axis aligned box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        Self {
+            min: Vec3::repeat(f64::INFINITY),
+            max: Vec3::repeat(f64::NEG_INFINITY),
+        }
+    }
+
+    fn add(&mut self, p: &Vec3) {
+        self.min = self.min.inf(p);
+        self.max = self.max.sup(p);
+    }
+
+    fn merge(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    /// Squared distance from `p` to the box, 0 inside it
+    fn distance_squared(&self, p: &Vec3) -> f64 {
+        let d = (self.min - p).sup(&Vec3::zeros()).sup(&(p - self.max));
+        d.norm_squared()
+    }
+
+    /// Where a ray enters the box, if it does before `max_distance`
+    fn ray_entry(&self, origin: &Vec3, inverse: &Vec3, max_distance: f64) -> Option<f64> {
+        let mut near = 0f64;
+        let mut far = max_distance;
+        for k in 0..3 {
+            if inverse[k].is_infinite() {
+                // parallel to this pair of faces
+                if origin[k] < self.min[k] || origin[k] > self.max[k] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (self.min[k] - origin[k]) * inverse[k];
+            let b = (self.max[k] - origin[k]) * inverse[k];
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        (near <= far).then_some(near)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf {
+        bounds: Bounds,
+        triangles: Vec<usize>,
+    },
+    Split {
+        bounds: Bounds,
+        children: Box<[Node; 2]>,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Bounds {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Split { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Where a ray hits a triangle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub triangle: usize,
+    /// along the ray, in lengths of its direction
+    pub distance: f64,
+    pub point: Vec3,
+}
+
+/// The point of a surface closest to a query point
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClosestPoint {
+    pub triangle: usize,
+    pub point: Vec3,
+    pub distance: f64,
+}
+
+/// Distance along a ray to where it crosses a triangle, Möller-Trumbore
+pub fn ray_triangle(origin: &Vec3, direction: &Vec3, t: &[Vec3; 3]) -> Option<f64> {
+    let (e1, e2) = (t[1] - t[0], t[2] - t[0]);
+    let h = direction.cross(&e2);
+    let det = e1.dot(&h);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let s = (origin - t[0]) / det;
+    let u = s.dot(&h);
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&e1);
+    let v = direction.dot(&q);
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+    let distance = e2.dot(&q);
+    (distance > 0.).then_some(distance)
+}
+
+/// A bounding volume hierarchy over the triangles of a mesh in world space
+///
+/// Queries only borrow the tree, so they can run from many rayon tasks at once.
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    /// triangle corners, indexed like the triangles of the mesh
+    pub corners: Vec<[Vec3; 3]>,
+    root: Option<Node>,
+}
+
+impl Bvh {
+    /// The triangles of `mesh` placed with `transform`
+    pub fn new(mesh: &Mesh, transform: &Matrix4<f64>) -> Self {
+        Self::from_corners(transformed_corners(mesh, transform))
+    }
+
+    pub fn from_corners(corners: Vec<[Vec3; 3]>) -> Self {
+        let centroids = corners
+            .iter()
+            .map(|c| (c[0] + c[1] + c[2]) / 3.)
+            .collect::<Vec<_>>();
+        let triangles = (0..corners.len()).collect::<Vec<_>>();
+        let root = (!corners.is_empty()).then(|| build(&corners, &centroids, triangles));
+        Self { corners, root }
+    }
+
+    /// The box around every triangle
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.root.as_ref().map(|n| *n.bounds())
+    }
+
+    /// The first triangle a ray from `origin` hits, closer than `max_distance`
+    pub fn ray(&self, origin: &Vec3, direction: &Vec3, max_distance: f64) -> Option<RayHit> {
+        let inverse = direction.map(|d| 1. / d);
+        let mut best: Option<(usize, f64)> = None;
+        let mut stack = self.root.iter().collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            let limit = best.map_or(max_distance, |b| b.1);
+            if node.bounds().ray_entry(origin, &inverse, limit).is_none() {
+                continue;
+            }
+            match node {
+                Node::Leaf { triangles, .. } => {
+                    for &t in triangles {
+                        match ray_triangle(origin, direction, &self.corners[t]) {
+                            Some(d) if d < best.map_or(max_distance, |b| b.1) => {
+                                best = Some((t, d))
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Node::Split { children, .. } => stack.extend(children.iter()),
+            }
+        }
+        best.map(|(triangle, distance)| RayHit {
+            triangle,
+            distance,
+            point: origin + direction * distance,
+        })
+    }
+
+    /// How many triangles a ray from `origin` crosses
+    pub fn ray_crossings(&self, origin: &Vec3, direction: &Vec3) -> usize {
+        let inverse = direction.map(|d| 1. / d);
+        let mut count = 0;
+        let mut stack = self.root.iter().collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            if node
+                .bounds()
+                .ray_entry(origin, &inverse, f64::INFINITY)
+                .is_none()
+            {
+                continue;
+            }
+            match node {
+                Node::Leaf { triangles, .. } => {
+                    count += triangles
+                        .iter()
+                        .filter(|&&t| ray_triangle(origin, direction, &self.corners[t]).is_some())
+                        .count();
+                }
+                Node::Split { children, .. } => stack.extend(children.iter()),
+            }
+        }
+        count
+    }
+
+    /// The closest point of the surface to `p`
+    pub fn closest_point(&self, p: &Vec3) -> Option<ClosestPoint> {
+        let mut best: Option<(usize, Vec3, f64)> = None;
+        let mut stack = self.root.iter().collect::<Vec<_>>();
+        while let Some(node) = stack.pop() {
+            if best.is_some_and(|b| node.bounds().distance_squared(p) >= b.2) {
+                continue;
+            }
+            match node {
+                Node::Leaf { triangles, .. } => {
+                    for &t in triangles {
+                        let q = closest_point_on_triangle(p, &self.corners[t]);
+                        let d = (p - q).norm_squared();
+                        if best.is_none_or(|b| d < b.2) {
+                            best = Some((t, q, d));
+                        }
+                    }
+                }
+                Node::Split { children, .. } => {
+                    // nearer child last, so it is searched first
+                    let [a, b] = &**children;
+                    if a.bounds().distance_squared(p) < b.bounds().distance_squared(p) {
+                        stack.extend([b, a]);
+                    } else {
+                        stack.extend([a, b]);
+                    }
+                }
+            }
+        }
+        best.map(|(triangle, point, d)| ClosestPoint {
+            triangle,
+            point,
+            distance: d.sqrt(),
+        })
+    }
+
+    /// Whether `p` is inside the surface, which needs to be closed
+    ///
+    /// Rays are cast in three directions and the majority decides, so a ray grazing an edge
+    /// doesn't flip the answer.
+    pub fn contains(&self, p: &Vec3) -> bool {
+        if self.bounds().is_none_or(|b| b.distance_squared(p) > 0.) {
+            return false;
+        }
+        let inside = RAY_DIRECTIONS
+            .iter()
+            .filter(|d| self.ray_crossings(p, &Vec3::from(**d)) % 2 == 1)
+            .count();
+        inside * 2 > RAY_DIRECTIONS.len()
+    }
+}
+
+fn build(corners: &[[Vec3; 3]], centroids: &[Vec3], mut triangles: Vec<usize>) -> Node {
+    let mut bounds = Bounds::empty();
+    let mut centers = Bounds::empty();
+    for &t in triangles.iter() {
+        for p in corners[t].iter() {
+            bounds.add(p);
+        }
+        centers.add(&centroids[t]);
+    }
+    if triangles.len() <= LEAF_SIZE {
+        return Node::Leaf { bounds, triangles };
+    }
+
+    // median split along the longest side of the centroids
+    let axis = (centers.max - centers.min).imax();
+    let mid = triangles.len() / 2;
+    triangles.select_nth_unstable_by(mid, |&a, &b| {
+        centroids[a][axis].total_cmp(&centroids[b][axis])
+    });
+    let right = triangles.split_off(mid);
+    let (a, b) = if triangles.len() + right.len() > PARALLEL_BUILD {
+        rayon::join(
+            || build(corners, centroids, triangles),
+            || build(corners, centroids, right),
+        )
+    } else {
+        (
+            build(corners, centroids, triangles),
+            build(corners, centroids, right),
+        )
+    };
+    Node::Split {
+        bounds: a.bounds().merge(b.bounds()),
+        children: Box::new([a, b]),
+    }
+}
+
+impl OrcaModel {
+    /// A tree over the model parts of the object at `index` as placed on the build plate, with
+    /// the part each triangle came from and its index in that part
+    ///
+    /// Modifier and negative parts aren't printed, so they are left out.
+    pub fn object_bvh(&self, index: usize) -> Result<(Bvh, Vec<(usize, usize)>)> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let mut corners = vec![];
+        let mut origin = vec![];
+        for (c, comp) in self.object_components(index)?.iter().enumerate() {
+            if self
+                .part_subtype(object_id, comp.objectid)
+                .is_some_and(|s| s != "normal_part")
+            {
+                continue;
+            }
+            let transform = self.component_world_transform(index, comp)?;
+            let part = transformed_corners(self.component_mesh(comp)?, &transform);
+            origin.extend((0..part.len()).map(|t| (c, t)));
+            corners.extend(part);
+        }
+        Ok((Bvh::from_corners(corners), origin))
+    }
+}
+
+impl Model {
+    /// A tree over the object at `index` as placed on the build plate
+    pub fn object_bvh(&self, index: usize) -> Result<Bvh> {
+        let mesh = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .object
+            .get_mesh()
+            .context("expected mesh, got components")?;
+        Ok(Bvh::new(mesh, &self.item_transform(index)?))
+    }
+}
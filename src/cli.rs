@@ -0,0 +1,125 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    save_load::{load_3mf_orca_noconvert, load_3mf_ps, save_orca_3mf, save_ps_3mf},
+    transform::ObjectTransform,
+};
+
+/// Command line tools, the GUI runs when there are no arguments
+#[derive(Debug, Parser)]
+#[command(name = "unjosefizer", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Scales, rotates and moves objects in a project, keeping their paint
+    Transform(TransformArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct TransformArgs {
+    /// Orca, Bambu or PrusaSlicer project
+    pub input: PathBuf,
+    /// Where to save the result, in the input's format
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// Object indices, every object when left out
+    #[arg(long, value_delimiter = ',')]
+    pub objects: Vec<usize>,
+    /// One factor for all axes, or X,Y,Z
+    #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+    pub scale: Vec<f64>,
+    /// Degrees around X,Y,Z
+    #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+    pub rotate: Vec<f64>,
+    /// mm along X,Y,Z
+    #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+    pub translate: Vec<f64>,
+    /// Put the lowest point of each object at Z = 0
+    #[arg(long)]
+    pub drop_to_bed: bool,
+}
+
+/// Three values from an option, `default` when it is missing, a single value for every axis
+/// where `uniform`
+fn xyz(values: &[f64], default: f64, uniform: bool, name: &str) -> Result<[f64; 3]> {
+    match values {
+        [] => Ok([default; 3]),
+        [v] if uniform => Ok([*v; 3]),
+        [x, y, z] => Ok([*x, *y, *z]),
+        _ => bail!("--{} takes X,Y,Z", name),
+    }
+}
+
+/// Whether the project at `path` is an Orca or Bambu one rather than PrusaSlicer
+fn is_orca_project(path: &Path) -> Result<bool> {
+    let file = std::fs::File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let zip = zip::ZipArchive::new(file)?;
+    let orca = zip
+        .file_names()
+        .any(|n| n.ends_with("model_settings.config"));
+    Ok(orca)
+}
+
+pub fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Command::Transform(args) => transform(&args),
+    }
+}
+
+fn transform(args: &TransformArgs) -> Result<()> {
+    let transform = ObjectTransform {
+        scale: xyz(&args.scale, 1., true, "scale")?,
+        rotate: xyz(&args.rotate, 0., false, "rotate")?,
+        translate: xyz(&args.translate, 0., false, "translate")?,
+        drop_to_bed: args.drop_to_bed,
+    };
+    let selected = |count: usize| -> Result<Vec<usize>> {
+        if args.objects.is_empty() {
+            return Ok((0..count).collect());
+        }
+        for &i in args.objects.iter() {
+            ensure!(
+                i < count,
+                "No object at index {}, the project has {}",
+                i,
+                count
+            );
+        }
+        Ok(args.objects.clone())
+    };
+
+    let count = if is_orca_project(&args.input)? {
+        let mut model = load_3mf_orca_noconvert(&args.input)?;
+        let indices = selected(model.get_objects().len())?;
+        for &i in indices.iter() {
+            model.transform_object(i, &transform)?;
+        }
+        save_orca_3mf(&args.output, &model)?;
+        indices.len()
+    } else {
+        let (mut models, md) = load_3mf_ps(&args.input)?;
+        let model = models.first_mut().context("No model in the project")?;
+        let indices = selected(model.resources.object.len())?;
+        for &i in indices.iter() {
+            model.transform_object(i, md.as_ref(), &transform)?;
+        }
+        save_ps_3mf(&models, md.as_ref(), &args.output)?;
+        indices.len()
+    };
+
+    info!(
+        "transformed {} objects into {}",
+        count,
+        args.output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,1053 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::{Matrix4, Vector4};
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use crate::{
+    image_paint::{check_colors, nearest_color, QuantizedImage},
+    mesh::{Mesh, Triangle, Triangles, Vertex, Vertices},
+    metadata::{
+        orca_metadata as orca, orca_metadata::OrcaMetadata, ps_metadata as ps,
+        ps_metadata::PSMetadata,
+    },
+    model::{Build, Component, Item, Metadata, Model, Object, ObjectData, Resources},
+    model_orca::{OrcaModel, SubModel},
+    paint_regions::{refine_depth, state_name},
+    paint_tree::{interpolate, PaintTree, Repaint, Sample, Vec2},
+    save_load::{save_orca_3mf, save_ps_3mf},
+    splitting::Vec3,
+};
+
+/// Imported objects are put in the middle of a 256 mm bed
+const BED_CENTER: [f64; 2] = [128., 128.];
+
+/// Where the mesh of an imported object goes in an Orca project
+const ORCA_OBJECT_PATH: &str = "3D/Objects/object_1.model";
+
+const IDENTITY_MATRIX: &str = "1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1";
+
+/// Which slicer an imported project is made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ProjectFormat {
+    #[default]
+    Orca,
+    PrusaSlicer,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ColorImportOptions {
+    /// filament `i` has color `colors[i - 1]`, as in
+    /// [`crate::paint_convert::PaintConvertInfo::colors`]
+    pub colors: Vec<(u8, u8, u8)>,
+    /// edge length in mm triangles are split down to where colors change
+    pub resolution: f64,
+    /// applied after converting the model to mm
+    pub scale: f64,
+    pub format: ProjectFormat,
+}
+
+impl Default for ColorImportOptions {
+    fn default() -> Self {
+        Self {
+            colors: vec![],
+            resolution: 0.2,
+            scale: 1.,
+            format: ProjectFormat::default(),
+        }
+    }
+}
+
+/// A mesh with the colors it is drawn with, before they are reduced to filaments
+#[derive(Debug, Clone, Default)]
+pub struct ColoredMesh {
+    /// in mm, Z up
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<ColoredTriangle>,
+    pub materials: Vec<Material>,
+    pub textures: Vec<image::RgbaImage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColoredTriangle {
+    pub vertices: [usize; 3],
+    /// `None` is plain white
+    pub material: Option<usize>,
+    /// texture coordinates, with v going down the image
+    pub uvs: Option<[Vec2; 3]>,
+    /// sRGB, 0 to 1
+    pub colors: Option<[Vec3; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    /// sRGB, 0 to 1, multiplied with the texture and vertex colors
+    pub color: Vec3,
+    pub texture: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColorImportReport {
+    pub triangles: usize,
+    /// triangles covering more than one filament
+    pub split: usize,
+    /// painted area of each state in mm²
+    pub areas: Vec<f64>,
+}
+
+impl std::fmt::Display for ColorImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} triangles, {} split", self.triangles, self.split)?;
+        for (state, area) in self.areas.iter().enumerate() {
+            if *area > 0. {
+                write!(f, ", {}: {:.2} mm²", state_name(state as u8), area)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses filament colors written as `#rrggbb`, separated by commas or spaces
+pub fn parse_colors(s: &str) -> Result<Vec<(u8, u8, u8)>> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            let hex = c.trim_start_matches('#');
+            ensure!(hex.len() == 6 && hex.is_ascii(), "Invalid color: {:?}", c);
+            Ok((
+                u8::from_str_radix(&hex[0..2], 16)?,
+                u8::from_str_radix(&hex[2..4], 16)?,
+                u8::from_str_radix(&hex[4..6], 16)?,
+            ))
+        })
+        .collect()
+}
+
+fn linear_to_srgb(c: f32) -> f64 {
+    let c = c.clamp(0., 1.) as f64;
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn to_rgb8(c: &Vec3) -> (u8, u8, u8) {
+    let c = c.map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+    (c.x, c.y, c.z)
+}
+
+/// Converts a decoded glTF image, keeping the top 8 bits of deeper formats
+fn gltf_texture(data: &gltf::image::Data) -> Result<image::RgbaImage> {
+    use gltf::image::Format;
+
+    let (channels, bytes) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let value = |c: &[u8]| match bytes {
+        1 => c[0],
+        2 => (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8,
+        _ => (f32::from_ne_bytes([c[0], c[1], c[2], c[3]]).clamp(0., 1.) * 255.).round() as u8,
+    };
+
+    let mut pixels = Vec::with_capacity(data.width as usize * data.height as usize * 4);
+    for px in data.pixels.chunks_exact(channels * bytes) {
+        let v = px.chunks_exact(bytes).map(value).collect::<Vec<_>>();
+        pixels.extend_from_slice(&match channels {
+            1 => [v[0], v[0], v[0], 255],
+            2 => [v[0], v[0], v[0], v[1]],
+            3 => [v[0], v[1], v[2], 255],
+            _ => [v[0], v[1], v[2], v[3]],
+        });
+    }
+    image::RgbaImage::from_raw(data.width, data.height, pixels).context("Truncated glTF image")
+}
+
+impl ColoredMesh {
+    /// Loads a textured or vertex colored glTF, GLB, OBJ or PLY file
+    pub fn open(path: &Path) -> Result<Self> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let out = match ext.as_deref() {
+            Some("gltf") | Some("glb") => Self::open_gltf(path)?,
+            Some("obj") => Self::open_obj(path)?,
+            Some("ply") => Self::open_ply(path)?,
+            _ => bail!("Unsupported mesh format: {:?}", path),
+        };
+        ensure!(!out.triangles.is_empty(), "No triangles in {:?}", path);
+
+        debug!(
+            "loaded {:?}: {} vertices, {} triangles, {} materials, {} textures",
+            path,
+            out.vertices.len(),
+            out.triangles.len(),
+            out.materials.len(),
+            out.textures.len()
+        );
+        Ok(out)
+    }
+
+    /// glTF is in meters with Y up, nodes are flattened into one mesh
+    fn open_gltf(path: &Path) -> Result<Self> {
+        let (doc, buffers, images) =
+            gltf::import(path).with_context(|| format!("Failed to load glTF {:?}", path))?;
+
+        let mut out = Self {
+            textures: images.iter().map(gltf_texture).collect::<Result<_>>()?,
+            ..Default::default()
+        };
+
+        let mut tex_coords = vec![];
+        for material in doc.materials() {
+            let pbr = material.pbr_metallic_roughness();
+            let [r, g, b, _] = pbr.base_color_factor();
+            let texture = pbr.base_color_texture();
+            tex_coords.push(texture.as_ref().map_or(0, |t| t.tex_coord()));
+            out.materials.push(Material {
+                color: Vec3::new(linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)),
+                texture: texture.map(|t| t.texture().source().index()),
+            });
+        }
+
+        #[rustfmt::skip]
+        let root = Matrix4::new(
+            1000., 0., 0., 0.,
+            0., 0., -1000., 0.,
+            0., 1000., 0., 0.,
+            0., 0., 0., 1.,
+        );
+
+        let scene = doc
+            .default_scene()
+            .or_else(|| doc.scenes().next())
+            .context("No scene in glTF file")?;
+        let mut stack = scene.nodes().map(|n| (n, root)).collect::<Vec<_>>();
+        while let Some((node, parent)) = stack.pop() {
+            let transform = parent * Matrix4::from(node.transform().matrix()).cast::<f64>();
+            if let Some(mesh) = node.mesh() {
+                for primitive in mesh.primitives() {
+                    out.add_gltf_primitive(&primitive, &buffers, &transform, &tex_coords)?;
+                }
+            }
+            stack.extend(node.children().map(|c| (c, transform)));
+        }
+
+        Ok(out)
+    }
+
+    fn add_gltf_primitive(
+        &mut self,
+        primitive: &gltf::Primitive,
+        buffers: &[gltf::buffer::Data],
+        transform: &Matrix4<f64>,
+        tex_coords: &[u32],
+    ) -> Result<()> {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            warn!("skipping glTF primitive drawn as {:?}", primitive.mode());
+            return Ok(());
+        }
+
+        let reader = primitive.reader(|b| buffers.get(b.index()).map(|d| &d.0[..]));
+        let Some(positions) = reader.read_positions() else {
+            return Ok(());
+        };
+
+        let first = self.vertices.len();
+        self.vertices.extend(
+            positions.map(|[x, y, z]| {
+                (transform * Vector4::new(x as f64, y as f64, z as f64, 1.)).xyz()
+            }),
+        );
+        let count = self.vertices.len() - first;
+
+        let material = primitive.material().index();
+        let tex_coord = material.map_or(0, |m| tex_coords[m]);
+        let uvs = reader.read_tex_coords(tex_coord).map(|t| {
+            t.into_f32()
+                .map(|[u, v]| Vec2::new(u as f64, v as f64))
+                .collect::<Vec<_>>()
+        });
+        let colors = reader.read_colors(0).map(|c| {
+            c.into_rgb_f32()
+                .map(|[r, g, b]| Vec3::new(linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b)))
+                .collect::<Vec<_>>()
+        });
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..count).collect::<Vec<_>>(),
+        };
+
+        // mirrored nodes turn the triangles inside out
+        let flip = transform.fixed_view::<3, 3>(0, 0).determinant() < 0.;
+        for tri in indices.chunks_exact(3) {
+            let mut v = [tri[0], tri[1], tri[2]];
+            ensure!(v.iter().all(|&i| i < count), "glTF index out of range");
+            if flip {
+                v.swap(1, 2);
+            }
+            self.triangles.push(ColoredTriangle {
+                vertices: v.map(|i| first + i),
+                material,
+                uvs: uvs.as_ref().map(|uvs| v.map(|i| uvs[i])),
+                colors: colors.as_ref().map(|colors| v.map(|i| colors[i])),
+            });
+        }
+        Ok(())
+    }
+
+    /// Vertex colors are read from `v x y z r g b` lines, textures from `map_Kd`
+    fn open_obj(path: &Path) -> Result<Self> {
+        let options = tobj::LoadOptions {
+            triangulate: true,
+            ignore_points: true,
+            ignore_lines: true,
+            ..Default::default()
+        };
+        let (models, materials) = tobj::load_obj(path, &options)
+            .with_context(|| format!("Failed to load OBJ {:?}", path))?;
+        let materials = materials.unwrap_or_else(|e| {
+            warn!("no OBJ materials: {}", e);
+            vec![]
+        });
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut out = Self::default();
+        let mut textures = HashMap::new();
+        for material in materials.iter() {
+            let texture = material.diffuse_texture.as_ref().and_then(|name| {
+                // options before the file name aren't parsed by tobj
+                let file = dir.join(name.split_whitespace().last().unwrap_or(name));
+                if let Some(&i) = textures.get(&file) {
+                    return Some(i);
+                }
+                match image::open(&file) {
+                    Ok(image) => {
+                        out.textures.push(image.to_rgba8());
+                        textures.insert(file, out.textures.len() - 1);
+                        Some(out.textures.len() - 1)
+                    }
+                    Err(e) => {
+                        warn!("failed to open texture {:?}: {}", file, e);
+                        None
+                    }
+                }
+            });
+            let [r, g, b] = material.diffuse.unwrap_or([1.; 3]);
+            out.materials.push(Material {
+                color: Vec3::new(r as f64, g as f64, b as f64),
+                texture,
+            });
+        }
+
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let first = out.vertices.len();
+            out.vertices.extend(
+                mesh.positions
+                    .chunks_exact(3)
+                    .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64)),
+            );
+
+            // some exporters write 0 to 255 instead of 0 to 1
+            let scale = if mesh.vertex_color.iter().any(|&c| c > 1.) {
+                255.
+            } else {
+                1.
+            };
+            let colors = mesh
+                .vertex_color
+                .chunks_exact(3)
+                .map(|c| Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64) / scale)
+                .collect::<Vec<_>>();
+            let uvs = mesh
+                .texcoords
+                .chunks_exact(2)
+                .map(|t| Vec2::new(t[0] as f64, 1. - t[1] as f64))
+                .collect::<Vec<_>>();
+            let material = mesh.material_id.filter(|&m| m < out.materials.len());
+
+            for (f, tri) in mesh.indices.chunks_exact(3).enumerate() {
+                let v = [tri[0], tri[1], tri[2]].map(|i| i as usize);
+                ensure!(
+                    v.iter().all(|&i| first + i < out.vertices.len()),
+                    "OBJ index out of range"
+                );
+                let uv_index = mesh.texcoord_indices.get(f * 3..f * 3 + 3);
+                out.triangles.push(ColoredTriangle {
+                    vertices: v.map(|i| first + i),
+                    material,
+                    uvs: uv_index
+                        .filter(|t| t.iter().all(|&t| (t as usize) < uvs.len()))
+                        .map(|t| [0, 1, 2].map(|k| uvs[t[k] as usize])),
+                    colors: (colors.len() * 3 == mesh.positions.len())
+                        .then(|| v.map(|i| colors[i])),
+                });
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// ASCII or binary PLY, with colors per vertex or per face
+    fn open_ply(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read PLY {:?}", path))?;
+        let header_len = data
+            .windows(10)
+            .position(|w| w == b"end_header")
+            .and_then(|p| {
+                data[p..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map(|n| p + n + 1)
+            })
+            .context("PLY header not found")?;
+        let header = std::str::from_utf8(&data[..header_len])?;
+
+        let mut format = None;
+        let mut elements: Vec<PlyElement> = vec![];
+        for line in header.lines() {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                ["format", f, ..] => format = Some(f.to_string()),
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count.parse()?,
+                    properties: vec![],
+                }),
+                ["property", "list", count, item, name] => elements
+                    .last_mut()
+                    .context("PLY property before any element")?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        ty: PlyType::parse(item)?,
+                        list: Some(PlyType::parse(count)?),
+                    }),
+                ["property", ty, name] => elements
+                    .last_mut()
+                    .context("PLY property before any element")?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        ty: PlyType::parse(ty)?,
+                        list: None,
+                    }),
+                _ => {}
+            }
+        }
+
+        let body = &data[header_len..];
+        let mut reader = match format.as_deref() {
+            Some("ascii") => PlyReader::Ascii(std::str::from_utf8(body)?.split_ascii_whitespace()),
+            Some("binary_little_endian") => PlyReader::Binary {
+                data: body,
+                pos: 0,
+                big_endian: false,
+            },
+            Some("binary_big_endian") => PlyReader::Binary {
+                data: body,
+                pos: 0,
+                big_endian: true,
+            },
+            f => bail!("Unknown PLY format: {:?}", f),
+        };
+
+        let mut out = Self::default();
+        let mut vertex_colors = vec![];
+        for element in elements.iter() {
+            for _ in 0..element.count {
+                let mut values = HashMap::new();
+                let mut indices = vec![];
+                for p in element.properties.iter() {
+                    if let Some(count) = p.list {
+                        let n = reader.read(count)? as usize;
+                        let items = (0..n)
+                            .map(|_| reader.read(p.ty))
+                            .collect::<Result<Vec<_>>>()?;
+                        if p.name == "vertex_indices" || p.name == "vertex_index" {
+                            indices = items.iter().map(|&i| i as usize).collect();
+                        }
+                    } else {
+                        let mut value = reader.read(p.ty)?;
+                        if PLY_COLOR_CHANNELS
+                            .iter()
+                            .any(|c| c.contains(&p.name.as_str()))
+                        {
+                            value /= p.ty.color_scale();
+                        }
+                        values.insert(p.name.as_str(), value);
+                    }
+                }
+                let color = element.color(&values);
+
+                match element.name.as_str() {
+                    "vertex" => {
+                        let get = |k| values.get(k).copied().unwrap_or(0.);
+                        out.vertices.push(Vec3::new(get("x"), get("y"), get("z")));
+                        vertex_colors.push(color);
+                    }
+                    "face" => {
+                        for k in 1..indices.len().saturating_sub(1) {
+                            let v = [indices[0], indices[k], indices[k + 1]];
+                            ensure!(
+                                v.iter().all(|&i| i < out.vertices.len()),
+                                "PLY index out of range"
+                            );
+                            let colors = match color {
+                                Some(c) => Some([c; 3]),
+                                None => v
+                                    .iter()
+                                    .map(|&i| vertex_colors[i])
+                                    .collect::<Option<Vec<_>>>()
+                                    .map(|c| [c[0], c[1], c[2]]),
+                            };
+                            out.triangles.push(ColoredTriangle {
+                                vertices: v,
+                                material: None,
+                                uvs: None,
+                                colors,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        for v in self.vertices.iter_mut() {
+            *v *= factor;
+        }
+    }
+
+    /// Quantizes the colors to filaments, splitting triangles where the filament changes
+    ///
+    /// Textured triangles use the texture tinted by the material color, others their vertex
+    /// colors, or the plain material color.
+    pub fn to_painted_mesh(
+        &self,
+        colors: &[(u8, u8, u8)],
+        resolution: f64,
+        orca: bool,
+    ) -> Result<(Mesh, ColorImportReport)> {
+        ensure!(resolution > 0., "Resolution must be positive");
+        check_colors(colors)?;
+
+        let images = self
+            .materials
+            .iter()
+            .map(|m| {
+                let Some(texture) = m.texture.and_then(|t| self.textures.get(t)) else {
+                    return Ok(None);
+                };
+                // transparent pixels still cover the surface
+                let mut image = texture.clone();
+                for p in image.pixels_mut() {
+                    for c in 0..3 {
+                        p.0[c] = (p.0[c] as f64 * m.color[c]).round() as u8;
+                    }
+                    p.0[3] = 255;
+                }
+                QuantizedImage::new(&image, colors).map(Some)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let quantize = |c: &Vec3| nearest_color(colors, to_rgb8(c)) as u8 + 1;
+
+        let results = self
+            .triangles
+            .par_iter()
+            .map(|t| {
+                let corners = t.vertices.map(|v| self.vertices[v]);
+                let depth = refine_depth(&corners, resolution);
+                let material = t.material.and_then(|m| self.materials.get(m));
+                let tint = material.map_or(Vec3::repeat(1.), |m| m.color);
+                let image = t
+                    .material
+                    .and_then(|m| images.get(m))
+                    .and_then(Option::as_ref);
+
+                let tree = match (image, t.uvs, t.colors) {
+                    (Some(image), Some(uvs), _) => {
+                        let size = Vec2::new(image.width as f64, image.height as f64);
+                        PaintTree::build(depth, &mut |sub| {
+                            let uv = sub.map(|c| interpolate(&uvs, &c));
+                            // the texture repeats, each piece is looked up in its own tile
+                            let tile = uv
+                                .iter()
+                                .fold(Vec2::repeat(f64::INFINITY), |a, b| a.inf(b))
+                                .map(f64::floor);
+                            let centroid = ((uv[0] + uv[1] + uv[2]) / 3.).map(|x| x.rem_euclid(1.));
+                            match image.classify(
+                                &uv.map(|p| (p - tile).component_mul(&size)),
+                                &centroid.component_mul(&size),
+                            ) {
+                                Repaint::Paint(s) => Sample::Uniform(s),
+                                Repaint::Mixed(s) => Sample::Mixed(s.unwrap_or(0)),
+                                Repaint::Keep => Sample::Uniform(0),
+                            }
+                        })
+                    }
+                    (_, _, Some(vertex_colors)) => PaintTree::build(depth, &mut |sub| {
+                        let color = |p: &Vec2| {
+                            quantize(&interpolate(&vertex_colors, p).component_mul(&tint))
+                        };
+                        // colors change linearly, the edge midpoints catch most bands in between
+                        let centroid = color(&((sub[0] + sub[1] + sub[2]) / 3.));
+                        let uniform = (0..3).all(|k| {
+                            color(&sub[k]) == centroid
+                                && color(&((sub[k] + sub[(k + 1) % 3]) / 2.)) == centroid
+                        });
+                        if uniform {
+                            Sample::Uniform(centroid)
+                        } else {
+                            Sample::Mixed(centroid)
+                        }
+                    }),
+                    _ => PaintTree::Leaf(quantize(&tint)),
+                };
+
+                let area = (corners[1] - corners[0])
+                    .cross(&(corners[2] - corners[0]))
+                    .norm()
+                    / 2.;
+                let areas = tree
+                    .state_areas()
+                    .iter()
+                    .map(|a| a * area)
+                    .collect::<Vec<_>>();
+                let split = !matches!(tree, PaintTree::Leaf(_));
+
+                let mut triangle = Triangle {
+                    v1: t.vertices[0],
+                    v2: t.vertices[1],
+                    v3: t.vertices[2],
+                    mmu_ps: None,
+                    mmu_orca: None,
+                };
+                triangle.set_paint(tree.to_paint(), orca);
+                (triangle, split, areas)
+            })
+            .collect::<Vec<_>>();
+
+        let mut report = ColorImportReport::default();
+        let mut triangles = Vec::with_capacity(results.len());
+        for (triangle, split, areas) in results {
+            report.triangles += 1;
+            report.split += split as usize;
+            if report.areas.len() < areas.len() {
+                report.areas.resize(areas.len(), 0.);
+            }
+            for (sum, a) in report.areas.iter_mut().zip(areas) {
+                *sum += a;
+            }
+            triangles.push(triangle);
+        }
+
+        let mesh = Mesh {
+            vertices: Vertices {
+                vertex: self
+                    .vertices
+                    .iter()
+                    .map(|v| Vertex {
+                        x: v.x,
+                        y: v.y,
+                        z: v.z,
+                    })
+                    .collect(),
+            },
+            triangles: Triangles {
+                triangle: triangles,
+            },
+        };
+        Ok((mesh, report))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => bail!("Unknown PLY type: {}", s),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// Brings integer colors to 0 to 1, only used for color properties
+    fn color_scale(self) -> f64 {
+        match self {
+            Self::U8 => 255.,
+            Self::U16 => 65535.,
+            _ => 1.,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    ty: PlyType,
+    /// type of the item count of list properties
+    list: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    fn color(&self, values: &HashMap<&str, f64>) -> Option<Vec3> {
+        let [r, g, b] =
+            PLY_COLOR_CHANNELS.map(|names| names.iter().find_map(|n| values.get(n).copied()));
+        Some(Vec3::new(r?, g?, b?))
+    }
+}
+
+/// Property names used for the red, green and blue channels
+const PLY_COLOR_CHANNELS: [&[&str]; 3] = [
+    &["red", "r", "diffuse_red"],
+    &["green", "g", "diffuse_green"],
+    &["blue", "b", "diffuse_blue"],
+];
+
+enum PlyReader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        pos: usize,
+        big_endian: bool,
+    },
+}
+
+impl PlyReader<'_> {
+    fn read(&mut self, ty: PlyType) -> Result<f64> {
+        match self {
+            Self::Ascii(words) => Ok(words.next().context("PLY data ends early")?.parse()?),
+            Self::Binary {
+                data,
+                pos,
+                big_endian,
+            } => {
+                let size = ty.size();
+                let bytes = data.get(*pos..*pos + size).context("PLY data ends early")?;
+                *pos += size;
+
+                let mut b = [0u8; 8];
+                b[..size].copy_from_slice(bytes);
+                if *big_endian {
+                    b[..size].reverse();
+                }
+                Ok(match ty {
+                    PlyType::I8 => b[0] as i8 as f64,
+                    PlyType::U8 => b[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    PlyType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    PlyType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+}
+
+/// Moves a mesh so it is centered on the origin and rests on Z = 0
+fn center_on_plate(mesh: &mut Mesh) {
+    let Some((min, max)) = mesh.bounding_box() else {
+        return;
+    };
+    let offset = [(min[0] + max[0]) / 2., (min[1] + max[1]) / 2., min[2]];
+    for v in mesh.vertices.vertex.iter_mut() {
+        v.x -= offset[0];
+        v.y -= offset[1];
+        v.z -= offset[2];
+    }
+}
+
+fn bed_transform() -> [f64; 12] {
+    [
+        1.,
+        0.,
+        0.,
+        0.,
+        1.,
+        0.,
+        0.,
+        0.,
+        1.,
+        BED_CENTER[0],
+        BED_CENTER[1],
+        0.,
+    ]
+}
+
+/// A new PrusaSlicer project holding `mesh` as a single object
+///
+/// Filament colors aren't stored, filament `i` is expected to be loaded with color `i`.
+pub fn new_ps_project(name: &str, mut mesh: Mesh) -> (Model, PSMetadata) {
+    center_on_plate(&mut mesh);
+    let lastid = mesh.triangles.triangle.len().saturating_sub(1);
+
+    let model = Model {
+        metadata: vec![Metadata {
+            name: "slic3rpe:Version3mf".to_string(),
+            value: Some("1".to_string()),
+        }],
+        resources: Resources {
+            object: vec![Object {
+                id: 1,
+                partnumber: None,
+                name: Some(name.to_string()),
+                uuid: None,
+                pid: None,
+                ty: Some("model".to_string()),
+                object: ObjectData::Mesh(mesh),
+            }],
+            basematerials: None,
+        },
+        build: Build {
+            item: vec![Item {
+                objectid: 1,
+                transform: Some(bed_transform()),
+                partnumber: None,
+            }],
+        },
+        ..Default::default()
+    };
+
+    let name_md = |ty: &str| ps::Metadata {
+        ty: ty.to_string(),
+        key: Some("name".to_string()),
+        value: Some(name.to_string()),
+    };
+    let md = PSMetadata {
+        object: vec![ps::Object {
+            id: 1,
+            instances_count: 1,
+            metadata: vec![name_md("object")],
+            volume: vec![ps::Volume {
+                firstid: 0,
+                lastid,
+                metadata: vec![name_md("volume")],
+                mesh: ps::Mesh {
+                    edges_fixed: 0,
+                    degenerate_facets: 0,
+                    facets_removed: 0,
+                    facets_reversed: 0,
+                    backwards_edges: 0,
+                },
+            }],
+        }],
+    };
+
+    (model, md)
+}
+
+/// A new Orca project holding `mesh` as a single object, with filament colors `colors`
+///
+/// Only the filament colors are set in the project settings, the slicer fills in the rest
+/// from its presets.
+pub fn new_orca_project(name: &str, mut mesh: Mesh, colors: &[(u8, u8, u8)]) -> Result<OrcaModel> {
+    center_on_plate(&mut mesh);
+
+    let sub_model = Model {
+        bambustudio: "http://schemas.bambulab.com/package/2021".to_string(),
+        p: "http://schemas.microsoft.com/3dmanufacturing/production/2015/06".to_string(),
+        requiredextensions: "p".to_string(),
+        metadata: vec![Metadata {
+            name: "BambuStudio:3mfVersion".to_string(),
+            value: Some("1".to_string()),
+        }],
+        resources: Resources {
+            object: vec![Object {
+                id: 1,
+                partnumber: None,
+                name: None,
+                uuid: None,
+                pid: None,
+                ty: Some("model".to_string()),
+                object: ObjectData::Mesh(mesh),
+            }],
+            basematerials: None,
+        },
+        ..Default::default()
+    };
+
+    let components = vec![Component {
+        objectid: 1,
+        transform: Some([1., 0., 0., 0., 1., 0., 0., 0., 1., 0., 0., 0.]),
+        uuid: None,
+        path: Some(format!("/{}", ORCA_OBJECT_PATH)),
+    }];
+    let model = Model {
+        requiredextensions: "p".to_string(),
+        metadata: vec![
+            Metadata {
+                name: "Application".to_string(),
+                value: Some(format!("unjosefizer-{}", env!("CARGO_PKG_VERSION"))),
+            },
+            // marks the Bambu project layout, with the object settings in model_settings.config
+            Metadata {
+                name: "BambuStudio:3mfVersion".to_string(),
+                value: Some("1".to_string()),
+            },
+        ],
+        resources: Resources {
+            object: vec![Object {
+                id: 2,
+                partnumber: None,
+                name: None,
+                uuid: None,
+                pid: None,
+                ty: Some("model".to_string()),
+                object: ObjectData::Components {
+                    component: components.clone(),
+                },
+            }],
+            basematerials: None,
+        },
+        build: Build {
+            item: vec![Item {
+                objectid: 2,
+                transform: Some(bed_transform()),
+                partnumber: None,
+            }],
+        },
+        ..Default::default()
+    };
+
+    let md_entry = |key: &str, value: &str| orca::Metadata {
+        key: Some(key.to_string()),
+        value: Some(value.to_string()),
+    };
+    let md = OrcaMetadata {
+        object: vec![orca::Object {
+            id: 2,
+            metadata: vec![md_entry("name", name), md_entry("extruder", "1")],
+            part: vec![orca::Part {
+                id: 1,
+                subtype: "normal_part".to_string(),
+                metadata: vec![md_entry("name", name), md_entry("matrix", IDENTITY_MATRIX)],
+                mesh_stat: orca::MeshStat {
+                    edges_fixed: 0,
+                    degenerate_facets: 0,
+                    facets_removed: 0,
+                    facets_reversed: 0,
+                    backwards_edges: 0,
+                },
+            }],
+        }],
+        assemble: vec![orca::Assemble {}],
+    };
+
+    let slice_cfg = serde_json::to_string_pretty(&serde_json::json!({
+        "filament_colour": colors
+            .iter()
+            .map(|(r, g, b)| format!("#{:02X}{:02X}{:02X}", r, g, b))
+            .collect::<Vec<_>>(),
+    }))?;
+
+    let rels = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+ <Relationship Target="/{}" Id="rel-1" Type="http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel"/>
+</Relationships>"#,
+        ORCA_OBJECT_PATH
+    );
+
+    Ok(OrcaModel::new(
+        model,
+        slice_cfg,
+        md,
+        HashMap::from([(
+            ORCA_OBJECT_PATH.to_string(),
+            SubModel {
+                id: 2,
+                model: sub_model,
+            },
+        )]),
+        vec![ORCA_OBJECT_PATH.to_string()],
+        HashSet::new(),
+        vec![(2, components)],
+        HashMap::from([(2, true)]),
+        rels,
+    ))
+}
+
+/// Loads a colored mesh and saves it as a new project, painted with the closest filaments
+pub fn import_colored_mesh(
+    input: &Path,
+    output: &Path,
+    options: &ColorImportOptions,
+) -> Result<ColorImportReport> {
+    ensure!(options.scale > 0., "Scale must be positive");
+
+    let mut colored = ColoredMesh::open(input)?;
+    colored.scale(options.scale);
+
+    let orca = options.format == ProjectFormat::Orca;
+    let (mesh, report) = colored.to_painted_mesh(&options.colors, options.resolution, orca)?;
+
+    let name = input
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("imported");
+    match options.format {
+        ProjectFormat::Orca => {
+            save_orca_3mf(output, &new_orca_project(name, mesh, &options.colors)?)?
+        }
+        ProjectFormat::PrusaSlicer => {
+            let (model, md) = new_ps_project(name, mesh);
+            save_ps_3mf(&[model], Some(&md), output)?
+        }
+    }
+
+    info!("imported {:?} to {:?}: {}", input, output, report);
+    Ok(report)
+}
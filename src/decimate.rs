@@ -0,0 +1,538 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+};
+
+use nalgebra::{Matrix3, Matrix4, Vector4};
+use rayon::prelude::*;
+
+use crate::{
+    bvh::Bvh,
+    mesh::{Mesh, Triangle, Vertex, Vertices},
+    metadata::ps_metadata::PSMetadata,
+    model::Model,
+    model_orca::OrcaModel,
+    paint_regions::refine_depth,
+    paint_tree::{interpolate, root_corners, PaintTree, Sample, Vec2},
+    splitting::Vec3,
+    topology::Topology,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DecimateOptions {
+    /// triangles to stop at
+    pub target_triangles: Option<usize>,
+    /// mm, how far collapses may move the surface
+    pub max_error: Option<f64>,
+    /// mm, shortest edge sub-triangles are split to when paint is resampled
+    pub resolution: f64,
+}
+
+impl Default for DecimateOptions {
+    fn default() -> Self {
+        Self {
+            target_triangles: None,
+            max_error: Some(0.05),
+            resolution: 0.2,
+        }
+    }
+}
+
+impl DecimateOptions {
+    /// The options for a part with `count` of the object's `total` triangles
+    fn share(&self, count: usize, total: usize) -> Self {
+        Self {
+            target_triangles: self
+                .target_triangles
+                .map(|t| (t * count).div_ceil(total.max(1))),
+            ..*self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DecimateReport {
+    pub triangles_before: usize,
+    pub triangles_after: usize,
+    pub collapses: usize,
+    /// triangles whose paint was sampled again from the original surface
+    pub resampled: usize,
+}
+
+impl std::ops::AddAssign for DecimateReport {
+    fn add_assign(&mut self, other: Self) {
+        self.triangles_before += other.triangles_before;
+        self.triangles_after += other.triangles_after;
+        self.collapses += other.collapses;
+        self.resampled += other.resampled;
+    }
+}
+
+impl std::fmt::Display for DecimateReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} triangles, {} collapses, {} resampled",
+            self.triangles_before, self.triangles_after, self.collapses, self.resampled
+        )
+    }
+}
+
+/// An edge collapse, removing `from` into `to` moved to `position`
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    position: Vec3,
+    /// versions of both vertices when the collapse was worked out
+    versions: [usize; 2],
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // cheapest first out of the max-heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+fn plane_quadric(p: &[Vec3; 3]) -> Matrix4<f64> {
+    let normal = (p[1] - p[0]).cross(&(p[2] - p[0]));
+    let Some(normal) = normal.try_normalize(0.) else {
+        return Matrix4::zeros();
+    };
+    let plane = Vector4::new(normal.x, normal.y, normal.z, -normal.dot(&p[0]));
+    plane * plane.transpose()
+}
+
+fn quadric_cost(q: &Matrix4<f64>, p: &Vec3) -> f64 {
+    let p = Vector4::new(p.x, p.y, p.z, 1.);
+    (p.transpose() * q * p)[0].max(0.)
+}
+
+/// Where a collapse moves the remaining vertex: the quadric minimum, or the better of the
+/// ends and the middle where that isn't well defined
+fn best_position(q: &Matrix4<f64>, a: &Vec3, b: &Vec3) -> Vec3 {
+    let m: Matrix3<f64> = q.fixed_view::<3, 3>(0, 0).into();
+    if m.determinant().abs() > 1e-12 {
+        if let Some(inverse) = m.try_inverse() {
+            return -(inverse * q.fixed_view::<3, 1>(0, 3));
+        }
+    }
+    [*a, *b, (a + b) / 2.]
+        .into_iter()
+        .min_by(|x, y| quadric_cost(q, x).total_cmp(&quadric_cost(q, y)))
+        .unwrap()
+}
+
+/// The point of a triangle's root frame closest to `p`, which lies on the triangle
+fn root_point(corners: &[Vec3; 3], p: &Vec3) -> Vec2 {
+    let (e1, e2, w) = (
+        corners[1] - corners[0],
+        corners[2] - corners[0],
+        p - corners[0],
+    );
+    let (d11, d12, d22) = (e1.dot(&e1), e1.dot(&e2), e2.dot(&e2));
+    let denominator = d11 * d22 - d12 * d12;
+    if denominator.abs() < f64::EPSILON {
+        return Vec2::new(1. / 3., 1. / 3.);
+    }
+    let (dw1, dw2) = (w.dot(&e1), w.dot(&e2));
+    Vec2::new(
+        (d22 * dw1 - d12 * dw2) / denominator,
+        (d11 * dw2 - d12 * dw1) / denominator,
+    )
+}
+
+/// Working state of a decimation, on welded vertices
+struct Decimation {
+    position: Vec<Vec3>,
+    quadric: Vec<Matrix4<f64>>,
+    locked: Vec<bool>,
+    version: Vec<usize>,
+    vertex_triangles: Vec<Vec<usize>>,
+    triangles: Vec<[usize; 3]>,
+    alive: Vec<bool>,
+    /// triangles with a corner that moved
+    touched: Vec<bool>,
+}
+
+impl Decimation {
+    fn corners(&self, t: usize) -> [Vec3; 3] {
+        self.triangles[t].map(|v| self.position[v])
+    }
+
+    fn neighbors(&self, v: usize) -> HashSet<usize> {
+        self.vertex_triangles[v]
+            .iter()
+            .flat_map(|&t| self.triangles[t])
+            .filter(|&w| w != v)
+            .collect()
+    }
+
+    fn candidate(&self, a: usize, b: usize) -> Option<Collapse> {
+        let (from, to) = match (self.locked[a], self.locked[b]) {
+            (true, true) => return None,
+            (false, true) => (a, b),
+            _ => (b, a),
+        };
+        let q = self.quadric[a] + self.quadric[b];
+        let position = if self.locked[to] {
+            self.position[to]
+        } else {
+            best_position(&q, &self.position[a], &self.position[b])
+        };
+        Some(Collapse {
+            cost: quadric_cost(&q, &position),
+            from,
+            to,
+            position,
+            versions: [self.version[from], self.version[to]],
+        })
+    }
+
+    /// Whether the collapse keeps the surface manifold without turning any triangle over
+    fn valid(&self, c: &Collapse) -> bool {
+        let shared = self.vertex_triangles[c.from]
+            .iter()
+            .filter(|&&t| self.triangles[t].contains(&c.to))
+            .count();
+        let common = self
+            .neighbors(c.from)
+            .intersection(&self.neighbors(c.to))
+            .count();
+        if shared != 2 || common != 2 {
+            return false;
+        }
+
+        [c.from, c.to].iter().all(|&v| {
+            self.vertex_triangles[v].iter().all(|&t| {
+                if self.triangles[t].contains(&c.from) && self.triangles[t].contains(&c.to) {
+                    return true;
+                }
+                let old = self.corners(t);
+                let new = self.triangles[t].map(|w| {
+                    if w == c.from || w == c.to {
+                        c.position
+                    } else {
+                        self.position[w]
+                    }
+                });
+                let old = (old[1] - old[0]).cross(&(old[2] - old[0]));
+                let new = (new[1] - new[0]).cross(&(new[2] - new[0]));
+                old.dot(&new) > 0. && new.norm() > f64::EPSILON * old.norm()
+            })
+        })
+    }
+
+    /// Applies a collapse, returning the triangles it removed
+    fn collapse(&mut self, c: &Collapse) -> usize {
+        let mut removed = 0;
+        for t in std::mem::take(&mut self.vertex_triangles[c.from]) {
+            if self.triangles[t].contains(&c.to) {
+                self.alive[t] = false;
+                removed += 1;
+                for w in self.triangles[t] {
+                    self.vertex_triangles[w].retain(|&x| x != t);
+                }
+            } else {
+                for w in self.triangles[t].iter_mut() {
+                    if *w == c.from {
+                        *w = c.to;
+                    }
+                }
+                self.vertex_triangles[c.to].push(t);
+            }
+        }
+        self.position[c.to] = c.position;
+        let q = self.quadric[c.from];
+        self.quadric[c.to] += q;
+        self.version[c.to] += 1;
+        self.version[c.from] += 1;
+        for &t in self.vertex_triangles[c.to].iter() {
+            self.touched[t] = true;
+        }
+        removed
+    }
+}
+
+impl Mesh {
+    /// Simplifies the mesh by quadric error edge collapses until it is down to the target
+    /// number of triangles or the next collapse would move the surface further than the
+    /// maximum error
+    ///
+    /// Vertices where the paint changes and those on open edges stay in place, so collapses
+    /// never cross a boundary between states. The paint of triangles that changed is sampled
+    /// again from the original surface, keeping detail that was inside removed triangles.
+    pub fn decimate(&mut self, options: &DecimateOptions, orca: bool) -> Result<DecimateReport> {
+        ensure!(
+            options.target_triangles.is_some() || options.max_error.is_some(),
+            "Set a target triangle count or a maximum error"
+        );
+        ensure!(options.resolution > 0., "Resolution must be positive");
+        let topology = Topology::new(self)?;
+        let count = self.triangles.triangle.len();
+        let mut out = DecimateReport {
+            triangles_before: count,
+            triangles_after: count,
+            ..Default::default()
+        };
+
+        let position = self
+            .vertices
+            .vertex
+            .iter()
+            .map(|v| Vec3::new(v.x, v.y, v.z))
+            .collect::<Vec<_>>();
+        let triangles = (0..count)
+            .map(|t| topology.triangle_vertices(self, t))
+            .collect::<Vec<_>>();
+        let trees = self
+            .triangles
+            .triangle
+            .iter()
+            .map(|t| PaintTree::from_paint(t.paint()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let n = position.len();
+        let mut d = Decimation {
+            position,
+            quadric: vec![Matrix4::zeros(); n],
+            locked: vec![false; n],
+            version: vec![0; n],
+            vertex_triangles: vec![vec![]; n],
+            triangles,
+            alive: vec![true; count],
+            touched: vec![false; count],
+        };
+
+        // a vertex between triangles of different states, or where the state at the corners
+        // differs, is on a boundary
+        let corner_points = root_corners().map(|c| c + (Vec2::repeat(1. / 3.) - c) * 1e-3);
+        let majority = trees.iter().map(PaintTree::majority).collect::<Vec<_>>();
+        let mut states: Vec<Option<[u8; 2]>> = vec![None; n];
+        for t in 0..count {
+            let q = plane_quadric(&d.corners(t));
+            for (k, v) in d.triangles[t].into_iter().enumerate() {
+                d.quadric[v] += q;
+                d.vertex_triangles[v].push(t);
+                let state = [majority[t], trees[t].state_at(&corner_points[k])];
+                if states[v].is_some_and(|s| s != state) {
+                    d.locked[v] = true;
+                }
+                states[v] = Some(state);
+            }
+            if topology.triangle_edges[t].contains(&None) {
+                for v in d.triangles[t] {
+                    d.locked[v] = true;
+                }
+            }
+        }
+        for edge in topology.edges.iter().filter(|e| e.triangles.len() != 2) {
+            for v in edge.vertices {
+                d.locked[v] = true;
+            }
+        }
+
+        let mut heap = topology
+            .edges
+            .iter()
+            .filter_map(|e| d.candidate(e.vertices[0], e.vertices[1]))
+            .collect::<BinaryHeap<_>>();
+        let max_cost = options.max_error.map_or(f64::INFINITY, |e| e * e);
+        // a tetrahedron is as far as a closed surface goes
+        let target = options.target_triangles.unwrap_or(0).max(4);
+        while let Some(c) = heap.pop() {
+            if out.triangles_after <= target || c.cost > max_cost {
+                break;
+            }
+            if c.versions != [d.version[c.from], d.version[c.to]] || !d.valid(&c) {
+                continue;
+            }
+            out.triangles_after -= d.collapse(&c);
+            out.collapses += 1;
+            for w in d.neighbors(c.to) {
+                heap.extend(d.candidate(c.to, w));
+            }
+        }
+
+        // paint of the triangles that moved, from where they are closest to the old surface
+        let painted = trees.iter().any(|t| *t != PaintTree::Leaf(0));
+        let original = Bvh::new(self, &Matrix4::identity());
+        let resampled = (0..count)
+            .into_par_iter()
+            .map(|t| {
+                if !painted || !d.alive[t] || !d.touched[t] {
+                    return None;
+                }
+                let corners = d.corners(t);
+                let state = |p: &Vec2| {
+                    let p = interpolate(&corners, p);
+                    original.closest_point(&p).map_or(0, |c| {
+                        let frame = root_point(&original.corners[c.triangle], &c.point);
+                        trees[c.triangle].state_at(&frame)
+                    })
+                };
+                let tree =
+                    PaintTree::build(refine_depth(&corners, options.resolution), &mut |sub| {
+                        let centroid = (sub[0] + sub[1] + sub[2]) / 3.;
+                        let centroid_state = state(&centroid);
+                        let uniform = (0..3).all(|k| {
+                            state(&(sub[k] + (centroid - sub[k]) * 1e-3)) == centroid_state
+                                && state(&((sub[k] + sub[(k + 1) % 3]) / 2.)) == centroid_state
+                        });
+                        if uniform {
+                            Sample::Uniform(centroid_state)
+                        } else {
+                            Sample::Mixed(centroid_state)
+                        }
+                    });
+                Some(tree)
+            })
+            .collect::<Vec<_>>();
+
+        let mut remap = vec![usize::MAX; n];
+        let mut vertices = vec![];
+        let mut kept = vec![];
+        for (t, resampled) in resampled.into_iter().enumerate() {
+            if !d.alive[t] {
+                continue;
+            }
+            let [v1, v2, v3] = d.triangles[t].map(|v| {
+                if remap[v] == usize::MAX {
+                    remap[v] = vertices.len();
+                    let p = d.position[v];
+                    vertices.push(Vertex {
+                        x: p.x,
+                        y: p.y,
+                        z: p.z,
+                    });
+                }
+                remap[v]
+            });
+            let mut triangle = Triangle {
+                v1,
+                v2,
+                v3,
+                ..self.triangles.triangle[t].clone()
+            };
+            if let Some(tree) = resampled {
+                triangle.set_paint(tree.to_paint(), orca);
+                out.resampled += 1;
+            }
+            kept.push(triangle);
+        }
+        self.vertices = Vertices { vertex: vertices };
+        self.triangles.triangle = kept;
+
+        debug!("decimated mesh: {}", out);
+        Ok(out)
+    }
+}
+
+impl OrcaModel {
+    /// Decimates every part of the object at `index`, sharing the target triangle count
+    /// between them by their size
+    pub fn decimate(&mut self, index: usize, options: &DecimateOptions) -> Result<DecimateReport> {
+        let comps = self.object_components(index)?.clone();
+        let total = comps
+            .iter()
+            .map(|c| Ok(self.component_mesh(c)?.triangles.triangle.len()))
+            .sum::<Result<usize>>()?;
+
+        // every part is decimated aside first, so a failure leaves the object as it was
+        let mut out = DecimateReport::default();
+        let mut decimated = vec![];
+        for comp in comps.iter() {
+            let mut mesh = self.component_mesh(comp)?.clone();
+            let options = options.share(mesh.triangles.triangle.len(), total);
+            out += mesh.decimate(&options, true)?;
+            decimated.push(mesh);
+        }
+        for (comp, mesh) in comps.iter().zip(decimated) {
+            *self.component_mesh_mut(comp)? = mesh;
+        }
+
+        debug!("decimated object {}: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Decimates the object at `index`, each volume on its own when `md` has them
+    ///
+    /// See [`OrcaModel::decimate`].
+    pub fn decimate(
+        &mut self,
+        index: usize,
+        md: Option<&mut PSMetadata>,
+        options: &DecimateOptions,
+    ) -> Result<DecimateReport> {
+        let object_id = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let mesh = self.object_mesh_mut(index)?;
+
+        let md_object = md.and_then(|md| md.object.iter_mut().find(|o| o.id == object_id));
+        let Some(md_object) = md_object.filter(|o| !o.volume.is_empty()) else {
+            let mut decimated = mesh.clone();
+            let out = decimated.decimate(options, false)?;
+            *mesh = decimated;
+            debug!("decimated object {}: {}", index, out);
+            return Ok(out);
+        };
+        let total = mesh.triangles.triangle.len();
+        ensure!(
+            md_object
+                .volume
+                .iter()
+                .all(|v| v.firstid <= v.lastid && v.lastid < total),
+            "Volume ends past the last triangle"
+        );
+
+        // built aside, so a failure leaves the object as it was
+        let mut new_mesh = Mesh {
+            vertices: Vertices { vertex: vec![] },
+            triangles: crate::mesh::Triangles { triangle: vec![] },
+        };
+        let mut new_volumes = md_object.volume.clone();
+        let mut out = DecimateReport::default();
+        for volume in new_volumes.iter_mut() {
+            let mut part = mesh.sub_mesh(volume.firstid..=volume.lastid);
+            let options = options.share(part.triangles.triangle.len(), total);
+            out += part.decimate(&options, false)?;
+            ensure!(
+                !part.triangles.triangle.is_empty(),
+                "Nothing left of a volume after decimation"
+            );
+            volume.firstid = new_mesh.triangles.triangle.len();
+            new_mesh.merge(&part);
+            volume.lastid = new_mesh.triangles.triangle.len() - 1;
+        }
+        *mesh = new_mesh;
+        md_object.volume = new_volumes;
+
+        debug!("decimated object {}: {}", index, out);
+        Ok(out)
+    }
+}
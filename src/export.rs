@@ -0,0 +1,432 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::{Matrix4, Vector4};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    path::Path,
+};
+
+use crate::{
+    instancing::quantize,
+    mesh::Mesh,
+    metadata::ps_metadata::PSMetadata,
+    model::Model,
+    model_orca::OrcaModel,
+    paint_tree::{interpolate, PaintTree},
+    part_paint::ps_modifier,
+    splitting::Vec3,
+};
+
+/// Color of filaments missing from the project's list
+const UNKNOWN_COLOR: (u8, u8, u8) = (128, 128, 128);
+
+/// How paint finer than a triangle is exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum PaintDetail {
+    /// Each leaf of the paint becomes its own triangle
+    #[default]
+    Bake,
+    /// Each triangle keeps the color covering most of it
+    Majority,
+}
+
+/// Triangles with a single filament each, the way formats with per-face color store them
+#[derive(Debug, Clone, Default)]
+pub struct ColoredFaces {
+    /// world space, mm
+    pub vertices: Vec<Vec3>,
+    pub faces: Vec<[usize; 3]>,
+    /// filament of each face, starting at 1
+    pub filaments: Vec<u8>,
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl ColoredFaces {
+    /// Adds a mesh placed with `transform`, unpainted areas getting `default_filament(triangle)`
+    pub fn add_mesh<F: Fn(usize) -> u8>(
+        &mut self,
+        mesh: &Mesh,
+        transform: &Matrix4<f64>,
+        detail: PaintDetail,
+        default_filament: F,
+    ) -> Result<()> {
+        // baked, every leaf is a triangle of its own and matches its neighbors
+        let baked;
+        let (mesh, origin): (&Mesh, Vec<usize>) = match detail {
+            PaintDetail::Bake => {
+                let mut m = mesh.clone();
+                let (_, counts) = m.bake(true)?;
+                baked = m;
+                let origin = counts
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, &n)| std::iter::repeat_n(i, n))
+                    .collect();
+                (&baked, origin)
+            }
+            PaintDetail::Majority => (mesh, (0..mesh.triangles.triangle.len()).collect()),
+        };
+
+        let first = self.vertices.len();
+        self.vertices.extend(
+            mesh.vertices
+                .vertex
+                .iter()
+                .map(|v| (transform * Vector4::new(v.x, v.y, v.z, 1.)).xyz()),
+        );
+        let flip = transform.fixed_view::<3, 3>(0, 0).determinant() < 0.;
+
+        for (i, t) in mesh.triangles.triangle.iter().enumerate() {
+            let mut v = [t.v1, t.v2, t.v3].map(|v| first + v);
+            ensure!(
+                v.iter().all(|&v| v < self.vertices.len()),
+                "Triangle {} has a vertex out of range",
+                i
+            );
+            if flip {
+                v.swap(1, 2);
+            }
+            self.faces.push(v);
+            self.filaments
+                .push(match PaintTree::from_paint(t.paint())?.majority() {
+                    0 => default_filament(origin[i]),
+                    s => s,
+                });
+        }
+        Ok(())
+    }
+
+    /// Writes OBJ with an MTL next to it, binary PLY, glTF or GLB, picked by extension
+    pub fn save(&self, path: &Path, colors: &[(u8, u8, u8)]) -> Result<()> {
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("obj") => self.write_obj(path, colors)?,
+            Some("ply") => self.write_ply(path, colors)?,
+            Some("gltf") => self.write_gltf(path, colors, false)?,
+            Some("glb") => self.write_gltf(path, colors, true)?,
+            _ => bail!("Unsupported export format: {:?}", path),
+        }
+        debug!(
+            "exported {} faces, {} vertices to {:?}",
+            self.faces.len(),
+            self.vertices.len(),
+            path
+        );
+        Ok(())
+    }
+
+    fn color(colors: &[(u8, u8, u8)], filament: u8) -> (u8, u8, u8) {
+        colors
+            .get((filament as usize).wrapping_sub(1))
+            .copied()
+            .unwrap_or(UNKNOWN_COLOR)
+    }
+
+    /// One material per filament
+    fn write_obj(&self, path: &Path, colors: &[(u8, u8, u8)]) -> Result<()> {
+        let mtl_path = path.with_extension("mtl");
+        let mtl_name = mtl_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid file name")?;
+
+        let mut by_filament: BTreeMap<u8, Vec<&[usize; 3]>> = BTreeMap::new();
+        for (face, f) in self.faces.iter().zip(&self.filaments) {
+            by_filament.entry(*f).or_default().push(face);
+        }
+
+        let mut mtl = std::io::BufWriter::new(std::fs::File::create(&mtl_path)?);
+        for f in by_filament.keys() {
+            let (r, g, b) = Self::color(colors, *f);
+            writeln!(mtl, "newmtl filament_{}", f)?;
+            writeln!(
+                mtl,
+                "Kd {:.4} {:.4} {:.4}\n",
+                r as f64 / 255.,
+                g as f64 / 255.,
+                b as f64 / 255.
+            )?;
+        }
+        mtl.flush()?;
+
+        let mut obj = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(obj, "mtllib {}", mtl_name)?;
+        for v in self.vertices.iter() {
+            writeln!(obj, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        for (f, faces) in by_filament.iter() {
+            writeln!(obj, "usemtl filament_{}", f)?;
+            for face in faces {
+                writeln!(obj, "f {} {} {}", face[0] + 1, face[1] + 1, face[2] + 1)?;
+            }
+        }
+        obj.flush()?;
+        Ok(())
+    }
+
+    /// Binary little endian, with face colors
+    fn write_ply(&self, path: &Path, colors: &[(u8, u8, u8)]) -> Result<()> {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write!(
+            out,
+            "ply\nformat binary_little_endian 1.0\nelement vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n\
+             element face {}\nproperty list uchar int vertex_indices\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\nend_header\n",
+            self.vertices.len(),
+            self.faces.len()
+        )?;
+        for v in self.vertices.iter() {
+            for c in [v.x, v.y, v.z] {
+                out.write_all(&(c as f32).to_le_bytes())?;
+            }
+        }
+        for (face, f) in self.faces.iter().zip(&self.filaments) {
+            out.write_all(&[3])?;
+            for v in face {
+                out.write_all(&(*v as i32).to_le_bytes())?;
+            }
+            let (r, g, b) = Self::color(colors, *f);
+            out.write_all(&[r, g, b])?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Vertex colors, with vertices unshared so each face keeps its own color
+    ///
+    /// glTF is in meters with Y up. A `.gltf` gets its buffer in a `.bin` next to it.
+    fn write_gltf(&self, path: &Path, colors: &[(u8, u8, u8)], binary: bool) -> Result<()> {
+        let count = self.faces.len() * 3;
+        let mut positions = Vec::with_capacity(count * 12);
+        let mut vertex_colors = Vec::with_capacity(count * 12);
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+
+        for (face, f) in self.faces.iter().zip(&self.filaments) {
+            let (r, g, b) = Self::color(colors, *f);
+            let color = [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)];
+            for v in face.iter().map(|&v| self.vertices[v]) {
+                let p = [v.x / 1000., v.z / 1000., -v.y / 1000.].map(|c| c as f32);
+                for k in 0..3 {
+                    min[k] = min[k].min(p[k]);
+                    max[k] = max[k].max(p[k]);
+                    positions.extend_from_slice(&p[k].to_le_bytes());
+                    vertex_colors.extend_from_slice(&color[k].to_le_bytes());
+                }
+            }
+        }
+
+        let mut buffer = positions;
+        let colors_offset = buffer.len();
+        buffer.extend_from_slice(&vertex_colors);
+
+        let bin_path = path.with_extension("bin");
+        let mut gltf_buffer = serde_json::json!({ "byteLength": buffer.len() });
+        if !binary {
+            gltf_buffer["uri"] = bin_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .context("Invalid file name")?
+                .into();
+        }
+
+        let json = serde_json::json!({
+            "asset": { "version": "2.0", "generator": "unjosefizer" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{
+                "primitives": [{
+                    "attributes": { "POSITION": 0, "COLOR_0": 1 },
+                    "material": 0,
+                }],
+            }],
+            "materials": [{
+                "pbrMetallicRoughness": { "metallicFactor": 0.0, "roughnessFactor": 1.0 },
+            }],
+            "buffers": [gltf_buffer],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": colors_offset, "target": 34962 },
+                {
+                    "buffer": 0,
+                    "byteOffset": colors_offset,
+                    "byteLength": buffer.len() - colors_offset,
+                    "target": 34962,
+                },
+            ],
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": count,
+                    "type": "VEC3",
+                    "min": min,
+                    "max": max,
+                },
+                { "bufferView": 1, "componentType": 5126, "count": count, "type": "VEC3" },
+            ],
+        });
+
+        if !binary {
+            std::fs::write(&bin_path, &buffer)?;
+            std::fs::write(path, serde_json::to_string_pretty(&json)?)?;
+            return Ok(());
+        }
+
+        // chunks are padded to 4 bytes, JSON with spaces
+        let mut json = serde_json::to_vec(&json)?;
+        json.resize(json.len().next_multiple_of(4), b' ');
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        out.write_all(b"glTF")?;
+        out.write_all(&2u32.to_le_bytes())?;
+        out.write_all(&((12 + 8 + json.len() + 8 + buffer.len()) as u32).to_le_bytes())?;
+        out.write_all(&(json.len() as u32).to_le_bytes())?;
+        out.write_all(b"JSON")?;
+        out.write_all(&json)?;
+        out.write_all(&(buffer.len() as u32).to_le_bytes())?;
+        out.write_all(b"BIN\0")?;
+        out.write_all(&buffer)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Filament colors of a PrusaSlicer project, from `Metadata/Slic3r_PE.config`
+///
+/// Extruder colors override the filament colors where they are set.
+pub fn load_ps_filament_colors(path: &Path) -> Result<Vec<(u8, u8, u8)>> {
+    let file = std::fs::read(path)?;
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(&file))?;
+    let mut s = String::new();
+    zip.by_name("Metadata/Slic3r_PE.config")?
+        .read_to_string(&mut s)?;
+
+    let option = |key: &str| {
+        s.lines()
+            .filter_map(|l| l.strip_prefix("; ")?.split_once(" = "))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.split(';').map(str::to_string).collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    let filament = option("filament_colour");
+    let extruder = option("extruder_colour");
+
+    let colors = (0..filament.len().max(extruder.len()))
+        .map(|i| {
+            extruder
+                .get(i)
+                .filter(|c| !c.is_empty())
+                .or(filament.get(i))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    ensure!(!colors.is_empty(), "No filament colors in {:?}", path);
+    crate::color_import::parse_colors(&colors.join(","))
+}
+
+impl OrcaModel {
+    /// Exports the model parts of the object at `index` as placed on the build plate, colored
+    /// with the project's filaments
+    pub fn export_colored(&self, index: usize, path: &Path, detail: PaintDetail) -> Result<()> {
+        let colors = self.filament_colors()?;
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+
+        let mut out = ColoredFaces::default();
+        for comp in self.object_components(index)?.iter() {
+            if self
+                .part_subtype(object_id, comp.objectid)
+                .is_some_and(|s| s != "normal_part")
+            {
+                continue;
+            }
+            let transform = self.component_world_transform(index, comp)?;
+            let extruder = self.part_extruder(object_id, comp.objectid);
+            out.add_mesh(self.component_mesh(comp)?, &transform, detail, |_| extruder)?;
+        }
+        out.save(path, &colors)
+    }
+}
+
+impl Model {
+    /// Exports the object at `index` as placed on the build plate, leaving out the modifier
+    /// volumes in `md`
+    ///
+    /// Unpainted triangles take the extruder of their volume, or of the object, from `md`.
+    pub fn export_colored(
+        &self,
+        index: usize,
+        md: Option<&PSMetadata>,
+        colors: &[(u8, u8, u8)],
+        path: &Path,
+        detail: PaintDetail,
+    ) -> Result<()> {
+        let object = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?;
+        let mesh = object
+            .object
+            .get_mesh()
+            .context("expected mesh, got components")?;
+
+        let extruder = |md: &[crate::metadata::ps_metadata::Metadata]| {
+            md.iter()
+                .find(|m| m.key.as_deref() == Some("extruder"))
+                .and_then(|m| m.value.as_ref()?.parse::<u8>().ok())
+                .filter(|&e| e > 0)
+        };
+        let md_object = md.and_then(|md| md.get_object_by_id(object.id));
+        let object_extruder = md_object.and_then(|o| extruder(&o.metadata)).unwrap_or(1);
+        let default_filament = |triangle: usize| {
+            md_object
+                .and_then(|o| {
+                    o.volume
+                        .iter()
+                        .find(|v| v.firstid <= triangle && triangle <= v.lastid)
+                })
+                .and_then(|v| extruder(&v.metadata))
+                .unwrap_or(object_extruder)
+        };
+
+        let transform = self.item_transform(index)?;
+        let mut out = ColoredFaces::default();
+        match md_object.filter(|o| !o.volume.is_empty()) {
+            Some(o) => {
+                for v in o.volume.iter().filter(|v| !ps_modifier(v)) {
+                    ensure!(
+                        v.firstid <= v.lastid && v.lastid < mesh.triangles.triangle.len(),
+                        "Volume ends past the last triangle"
+                    );
+                    let part = mesh.sub_mesh(v.firstid..=v.lastid);
+                    out.add_mesh(&part, &transform, detail, |t| {
+                        default_filament(v.firstid + t)
+                    })?;
+                }
+            }
+            None => out.add_mesh(mesh, &transform, detail, default_filament)?,
+        }
+        out.save(path, colors)
+    }
+}
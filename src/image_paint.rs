@@ -0,0 +1,377 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::Matrix4;
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    auto_paint::{transformed_corners, transformed_normals, AutoPaintReport},
+    bvh::Bvh,
+    instancing::MATCH_TOLERANCE,
+    mesh::Mesh,
+    model::Model,
+    model_orca::OrcaModel,
+    paint_tree::{contains, Repaint, Vec2},
+    splitting::Vec3,
+};
+
+/// Sub-triangles covering more pixels than this are split without looking at every pixel
+const MAX_SCAN_PIXELS: f64 = 4096.;
+
+/// Pixels more transparent than this leave the paint under them as it is
+const MIN_ALPHA: u8 = 128;
+
+/// How an image is laid onto a model, in world space
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Projection {
+    /// Straight onto the faces turned towards `normal`, with the image centered on `center`
+    Planar {
+        center: [f64; 3],
+        normal: [f64; 3],
+        up: [f64; 3],
+        /// size of the image on the model, in mm
+        width: f64,
+        height: f64,
+    },
+    /// Wrapped around the axis through `center`, with the middle of the image towards `front`
+    Cylindrical {
+        center: [f64; 3],
+        axis: [f64; 3],
+        front: [f64; 3],
+        /// arc covered by the width of the image, in degrees
+        angle: f64,
+        /// size of the image along the axis, in mm
+        height: f64,
+    },
+}
+
+/// A [`Projection`] with orthonormal axes
+struct Projector {
+    cylindrical: bool,
+    center: Vec3,
+    /// the normal of a planar projection, the front of a cylindrical one
+    forward: Vec3,
+    up: Vec3,
+    right: Vec3,
+    /// mm, or degrees around a cylinder
+    width: f64,
+    height: f64,
+}
+
+impl Projector {
+    fn new(projection: &Projection) -> Result<Self> {
+        let (cylindrical, center, forward, up, width, height) = match *projection {
+            Projection::Planar {
+                center,
+                normal,
+                up,
+                width,
+                height,
+            } => (false, center, normal, up, width, height),
+            Projection::Cylindrical {
+                center,
+                axis,
+                front,
+                angle,
+                height,
+            } => (true, center, front, axis, angle, height),
+        };
+        ensure!(
+            width > 0. && height > 0.,
+            "Projection size must be positive"
+        );
+
+        let (forward, up) = if cylindrical {
+            // the axis is kept, the front is made perpendicular to it
+            let up = Vec3::from(up)
+                .try_normalize(f64::EPSILON)
+                .context("Cylinder axis is zero")?;
+            let forward = Vec3::from(forward);
+            let forward = (forward - up * forward.dot(&up))
+                .try_normalize(1e-9)
+                .context("Projection front is parallel to the axis")?;
+            (forward, up)
+        } else {
+            let forward = Vec3::from(forward)
+                .try_normalize(f64::EPSILON)
+                .context("Projection normal is zero")?;
+            let up = Vec3::from(up);
+            let up = (up - forward * up.dot(&forward))
+                .try_normalize(1e-9)
+                .context("Projection up is parallel to the normal")?;
+            (forward, up)
+        };
+
+        Ok(Self {
+            cylindrical,
+            center: Vec3::from(center),
+            forward,
+            up,
+            // to the right when looking at the image from outside
+            right: up.cross(&forward),
+            width,
+            height,
+        })
+    }
+
+    /// Image coordinates of a point, 0 to 1 from left to right and top to bottom
+    fn map(&self, p: &Vec3) -> Vec2 {
+        let q = p - self.center;
+        let h = q.dot(&self.up);
+        let x = if self.cylindrical {
+            q.dot(&self.right).atan2(q.dot(&self.forward)).to_degrees()
+        } else {
+            q.dot(&self.right)
+        };
+        Vec2::new(0.5 + x / self.width, 0.5 - h / self.height)
+    }
+
+    /// The direction from `p` towards the projection
+    fn towards(&self, p: &Vec3) -> Vec3 {
+        if self.cylindrical {
+            let q = p - self.center;
+            (q - self.up * q.dot(&self.up))
+                .try_normalize(f64::EPSILON)
+                .unwrap_or(self.forward)
+        } else {
+            self.forward
+        }
+    }
+
+    /// Whether a face with `normal` around `p` is turned towards the projection
+    fn faces(&self, normal: &Vec3, p: &Vec3) -> bool {
+        normal.dot(&self.towards(p)) > 0.
+    }
+}
+
+/// An image reduced to paint states, the closest filament color for each pixel
+#[derive(Debug, Clone)]
+pub struct QuantizedImage {
+    pub width: u32,
+    pub height: u32,
+    /// row by row, `None` for transparent pixels
+    pub states: Vec<Option<u8>>,
+}
+
+/// Checks that `colors` can be quantized to, each filament fitting in a paint string
+pub(crate) fn check_colors(colors: &[(u8, u8, u8)]) -> Result<()> {
+    ensure!(!colors.is_empty(), "No filament colors to quantize to");
+    // the highest state a paint string can hold
+    ensure!(
+        colors.len() <= 18,
+        "Too many filament colors: {}",
+        colors.len()
+    );
+    Ok(())
+}
+
+impl QuantizedImage {
+    /// Quantizes to `colors`, filament `i` having color `colors[i - 1]` as in
+    /// [`crate::paint_convert::PaintConvertInfo::colors`]
+    pub fn new(image: &image::RgbaImage, colors: &[(u8, u8, u8)]) -> Result<Self> {
+        check_colors(colors)?;
+
+        let mut cache = HashMap::new();
+        let states = image
+            .pixels()
+            .map(|p| {
+                let [r, g, b, a] = p.0;
+                if a < MIN_ALPHA {
+                    return None;
+                }
+                let state = *cache
+                    .entry((r, g, b))
+                    .or_insert_with(|| nearest_color(colors, (r, g, b)) as u8 + 1);
+                Some(state)
+            })
+            .collect();
+
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            states,
+        })
+    }
+
+    pub fn open(path: &Path, colors: &[(u8, u8, u8)]) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to open image {:?}", path))?
+            .to_rgba8();
+        Self::new(&image, colors)
+    }
+
+    /// State of the pixel at pixel coordinates, `None` outside the image
+    fn state_at(&self, p: &Vec2) -> Option<u8> {
+        if p.x < 0. || p.y < 0. || p.x >= self.width as f64 || p.y >= self.height as f64 {
+            return None;
+        }
+        self.states[p.y as usize * self.width as usize + p.x as usize]
+    }
+
+    /// Whether a triangle in pixel coordinates covers a single state
+    ///
+    /// The centroid is mapped separately, averaging the corners breaks across the seam of a
+    /// cylindrical projection.
+    pub(crate) fn classify(&self, p: &[Vec2; 3], centroid: &Vec2) -> Repaint {
+        let (w, h) = (self.width as f64, self.height as f64);
+        let min = p.iter().fold(Vec2::repeat(f64::INFINITY), |a, b| a.inf(b));
+        let max = p
+            .iter()
+            .fold(Vec2::repeat(f64::NEG_INFINITY), |a, b| a.sup(b));
+        if max.x < 0. || max.y < 0. || min.x >= w || min.y >= h {
+            return Repaint::Keep;
+        }
+
+        let centroid = self.state_at(centroid);
+        let pixels = (max.x.floor() - min.x.floor() + 1.) * (max.y.floor() - min.y.floor() + 1.);
+        if min.x < 0. || min.y < 0. || max.x > w || max.y > h || pixels > MAX_SCAN_PIXELS {
+            return Repaint::Mixed(centroid);
+        }
+
+        for y in min.y.floor() as u32..=(max.y.floor() as u32).min(self.height - 1) {
+            for x in min.x.floor() as u32..=(max.x.floor() as u32).min(self.width - 1) {
+                let center = Vec2::new(x as f64 + 0.5, y as f64 + 0.5);
+                if contains(p, &center) && self.state_at(&center) != centroid {
+                    return Repaint::Mixed(centroid);
+                }
+            }
+        }
+
+        match centroid {
+            Some(state) => Repaint::Paint(state),
+            None => Repaint::Keep,
+        }
+    }
+}
+
+pub(crate) fn nearest_color(colors: &[(u8, u8, u8)], c: (u8, u8, u8)) -> usize {
+    let distance = |o: &(u8, u8, u8)| {
+        let d = [
+            c.0 as i32 - o.0 as i32,
+            c.1 as i32 - o.1 as i32,
+            c.2 as i32 - o.2 as i32,
+        ];
+        d[0] * d[0] + d[1] * d[1] + d[2] * d[2]
+    };
+    colors
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, o)| distance(o))
+        .map_or(0, |(i, _)| i)
+}
+
+impl Mesh {
+    /// Projects an image onto the faces turned towards it, after `transform`
+    ///
+    /// Triangles are split until each piece covers one color of the image, or its edges are
+    /// shorter than `resolution`. Paint outside the image or under transparent pixels is kept,
+    /// as is paint hidden from the projection behind faces of `occluder`, which is in the same
+    /// space as the mesh after `transform` and usually holds the whole object.
+    pub fn paint_image(
+        &mut self,
+        transform: &Matrix4<f64>,
+        occluder: &Bvh,
+        image: &QuantizedImage,
+        projection: &Projection,
+        resolution: f64,
+        orca: bool,
+    ) -> Result<AutoPaintReport> {
+        ensure!(resolution > 0., "Resolution must be positive");
+        let projector = Projector::new(projection)?;
+
+        let corners = transformed_corners(self, transform);
+        let facing = transformed_normals(&corners, transform)
+            .iter()
+            .zip(&corners)
+            .map(|(n, c)| projector.faces(n, &((c[0] + c[1] + c[2]) / 3.)))
+            .collect::<Vec<_>>();
+
+        let visible = |p: &Vec3| {
+            let towards = projector.towards(p);
+            let origin = p + towards * MATCH_TOLERANCE;
+            occluder.ray(&origin, &towards, f64::INFINITY).is_none()
+        };
+
+        let size = Vec2::new(image.width as f64, image.height as f64);
+        self.repaint_with(transform, resolution, orca, |i, p| {
+            if !facing[i] {
+                return Repaint::Keep;
+            }
+            let centroid = (p[0] + p[1] + p[2]) / 3.;
+            let shown = visible(&centroid);
+            let hidden = p.iter().filter(|p| !visible(p)).count() + !shown as usize;
+            if hidden == 4 {
+                return Repaint::Keep;
+            }
+
+            let to_pixels = |p: &Vec3| projector.map(p).component_mul(&size);
+            let out = image.classify(&p.map(|p| to_pixels(&p)), &to_pixels(&centroid));
+            match (out, hidden) {
+                (out, 0) | (out @ Repaint::Keep, _) => out,
+                // partly hidden, split further and paint what can be seen
+                (Repaint::Paint(state), _) => Repaint::Mixed(Some(state).filter(|_| shown)),
+                (Repaint::Mixed(state), _) => Repaint::Mixed(state.filter(|_| shown)),
+            }
+        })
+    }
+}
+
+impl OrcaModel {
+    /// Projects an image onto the model parts of the object at `index` as placed on the build
+    /// plate, with every model part hiding what is behind it
+    pub fn paint_image(
+        &mut self,
+        index: usize,
+        image: &QuantizedImage,
+        projection: &Projection,
+        resolution: f64,
+    ) -> Result<AutoPaintReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let (occluder, _) = self.object_bvh(index)?;
+
+        // every part is painted aside first, so a failure leaves the object as it was
+        let mut out = AutoPaintReport::default();
+        let mut painted = vec![];
+        for comp in self.object_components(index)?.clone() {
+            if self
+                .part_subtype(object_id, comp.objectid)
+                .is_some_and(|s| s != "normal_part")
+            {
+                continue;
+            }
+            let transform = self.component_world_transform(index, &comp)?;
+            let mut mesh = self.component_mesh(&comp)?.clone();
+            out += mesh.paint_image(&transform, &occluder, image, projection, resolution, true)?;
+            painted.push((comp, mesh));
+        }
+        for (comp, mesh) in painted {
+            *self.component_mesh_mut(&comp)? = mesh;
+        }
+
+        debug!("projected image onto object {}: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Projects an image onto the object at `index`, as PrusaSlicer `mmu_segmentation`
+    pub fn paint_image(
+        &mut self,
+        index: usize,
+        image: &QuantizedImage,
+        projection: &Projection,
+        resolution: f64,
+    ) -> Result<AutoPaintReport> {
+        let transform = self.item_transform(index)?;
+        let occluder = self.object_bvh(index)?;
+        let out = self
+            .object_mesh_mut(index)?
+            .paint_image(&transform, &occluder, image, projection, resolution, false)?;
+        debug!("projected image onto object {}: {}", index, out);
+        Ok(out)
+    }
+}
@@ -1,159 +1,180 @@
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use tracing::{debug, error, info, trace, warn};
 
-use crate::{mesh::Mesh, model::Object, model_orca::OrcaModel};
-
-impl OrcaModel {
-    pub fn copy_paint(&mut self, from: usize, to: usize) -> Result<()> {
-        let from_comps = match &self.get_objects()[from].object {
-            crate::model::ObjectData::Components { component } => component.clone(),
-            _ => bail!("Object at index {} is not a component", from),
-        };
-
-        let to_comps = match &self.get_objects()[to].object {
-            crate::model::ObjectData::Components { component } => component.clone(),
-            _ => bail!("Object at index {} is not a component", to),
-        };
-
-        if from_comps.len() != to_comps.len() {
-            bail!(
-                "Component count mismatch: {} components in object {} and {} components in object {}",
-                from_comps.len(),
-                from,
-                to_comps.len(),
-                to
-            );
-        }
-
-        for i in 0..from_comps.len() {
-            let from_comp_path = &from_comps[i].path.as_ref().unwrap()[1..];
-            let to_comp_path = &to_comps[i].path.as_ref().unwrap()[1..];
-
-            let from_comp_id = from_comps[i].objectid;
-            let to_comp_id = to_comps[i].objectid;
-
-            // debug!("Copying paint from {} to {}", from_comp_path, to_comp_path);
-            debug!(
-                "Copying paint from {} : {} to {} : {}",
-                from_comp_path, from_comp_id, to_comp_path, to_comp_id
-            );
+use rstar::RTree;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{
+    mesh::Mesh,
+    model::{Component, Object},
+    model_orca::OrcaModel,
+    paint_tree::PaintTree,
+    splitting::{rvec3::RVec3, Vec3},
+};
+
+/// Identifies a mesh by its shape, independent of vertex and triangle order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MeshFingerprint {
+    pub vertices: usize,
+    pub triangles: usize,
+    /// FNV-1a hash of the sorted, quantized triangle corners
+    pub hash: u64,
+}
 
-            let from_sub_object = self
-                .sub_models()
-                .get(from_comp_path)
-                .context("From Sub-model not found in sub-models")?
-                .clone();
-
-            let to_sub_object = self
-                .sub_models_mut()
-                .get_mut(to_comp_path)
-                .context("To Sub-model not found in sub-models")?;
-
-            let from_object = from_sub_object
-                .model
-                .resources
-                .object
-                .iter()
-                .find(|o| o.id == from_comp_id)
-                .context("Object not found in source sub-model")?;
-
-            let to_object = to_sub_object
-                .model
-                .resources
-                .object
-                .iter_mut()
-                .find(|o| o.id == to_comp_id)
-                .context("Object not found in destination sub-model")?;
-
-            match (&from_object.object, &mut to_object.object) {
-                (
-                    crate::model::ObjectData::Mesh(from_mesh),
-                    crate::model::ObjectData::Mesh(to_mesh),
-                ) => {
-                    copy_paint_mesh(from_mesh, to_mesh)?;
-                }
-                _ => bail!("Unsupported object types for paint copy"),
+impl Mesh {
+    pub fn fingerprint(&self) -> MeshFingerprint {
+        let mut keys = (0..self.triangles.triangle.len())
+            .map(|i| triangle_key(&triangle_corners(self, i)))
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for x in keys.iter().flatten().flatten() {
+            for b in x.to_le_bytes() {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
             }
+        }
 
-            //
+        MeshFingerprint {
+            vertices: self.vertices.vertex.len(),
+            triangles: self.triangles.triangle.len(),
+            hash,
         }
+    }
+}
 
-        #[cfg(feature = "nope")]
-        for i in 0..from_comps.len() {
-            let from_comp = &from_comps[i].path.as_ref().unwrap()[1..];
-            // let to_comp = &to_comps[i].path.as_ref().unwrap()[1..];
+/// One part of the source object paired with a part of the destination object
+#[derive(Debug, Clone)]
+pub struct ComponentPair {
+    /// component indices within each object
+    pub from: usize,
+    pub to: usize,
+    pub from_name: Option<String>,
+    pub to_name: Option<String>,
+    /// paired by part name as well as shape
+    pub by_name: bool,
+}
 
-            let from_comp_id = from_comps[i].objectid;
-            // to_comp_id = to_comps[i].objectid;
+/// How the parts of two objects line up, shown before any paint is copied
+#[derive(Debug, Clone, Default)]
+pub struct ComponentPairing {
+    pub pairs: Vec<ComponentPair>,
+    pub unmatched_from: Vec<(usize, Option<String>)>,
+    pub unmatched_to: Vec<(usize, Option<String>)>,
+}
+
+impl std::fmt::Display for ComponentPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) -> {} ({}), by {}",
+            self.from_name.as_deref().unwrap_or("unnamed"),
+            self.from,
+            self.to_name.as_deref().unwrap_or("unnamed"),
+            self.to,
+            if self.by_name {
+                "name and shape"
+            } else {
+                "shape"
+            },
+        )
+    }
+}
 
-            debug!("Copying paint from {} : {}", from_comp, from_comp_id);
+impl OrcaModel {
+    /// Pairs the parts of two objects by mesh fingerprint
+    ///
+    /// When several parts share a shape, the part name from `model_settings.config` decides,
+    /// then the order the parts are listed in.
+    pub fn pair_components(&self, from: usize, to: usize) -> Result<ComponentPairing> {
+        let object_id = |index: usize| -> Result<usize> {
+            Ok(self
+                .get_objects()
+                .get(index)
+                .with_context(|| format!("No object at index {}", index))?
+                .id)
+        };
+        let from_id = object_id(from)?;
+        let to_id = object_id(to)?;
 
-            let _to_comp = to_comps
+        let describe = |object_id: usize, comps: &[Component]| -> Result<Vec<_>> {
+            comps
                 .iter()
-                .find(|c| c.objectid == from_comp_id)
-                .context("Component not found in destination object")?;
+                .map(|c| {
+                    let fingerprint = self.component_mesh(c)?.fingerprint();
+                    Ok((fingerprint, self.part_name(object_id, c.objectid)))
+                })
+                .collect()
+        };
 
-            let to_comp = &_to_comp.path.as_ref().unwrap()[1..];
+        let from_parts = describe(from_id, self.object_components(from)?)?;
+        let to_parts = describe(to_id, self.object_components(to)?)?;
 
-            debug!(
-                "Copying paint from {} : {} to {} : {}",
-                from_comp, from_comp_id, to_comp, _to_comp.objectid
-            );
+        Ok(pair_parts(&from_parts, &to_parts))
+    }
 
-            let Some(from_sub_object) = self.sub_models().get(from_comp).cloned() else {
-                bail!("From Sub-model {} not found in sub-models", from_comp)
-            };
+    pub fn copy_paint(&mut self, from: usize, to: usize) -> Result<()> {
+        let pairing = self.pair_components(from, to)?;
+        self.copy_paint_paired(from, to, &pairing)
+    }
 
-            let Some(to_sub_object) = self.sub_models_mut().get_mut(to_comp) else {
-                bail!("To Sub-model {} not found in sub-models", to_comp)
-            };
+    /// Copies paint between the parts paired by [`OrcaModel::pair_components`]
+    pub fn copy_paint_paired(
+        &mut self,
+        from: usize,
+        to: usize,
+        pairing: &ComponentPairing,
+    ) -> Result<()> {
+        if pairing.pairs.is_empty() {
+            bail!("No matching parts between objects {} and {}", from, to);
+        }
+        for (i, name) in pairing.unmatched_to.iter() {
+            warn!(
+                "No source part for part {} ({}) of object {}",
+                i,
+                name.as_deref().unwrap_or("unnamed"),
+                to
+            );
+        }
+        for (i, name) in pairing.unmatched_from.iter() {
+            warn!(
+                "No target part for part {} ({}) of object {}",
+                i,
+                name.as_deref().unwrap_or("unnamed"),
+                from
+            );
+        }
 
-            let from_object = from_sub_object
-                .model
-                .resources
-                .object
-                .iter()
-                .find(|o| o.id == from_comp_id)
-                .context("Object not found in source sub-model")?;
-
-            let to_object = to_sub_object
-                .model
-                .resources
-                .object
-                .iter_mut()
-                .find(|o| o.id == from_comp_id)
-                .context("Object not found in destination sub-model")?;
-
-            match (&from_object.object, &mut to_object.object) {
-                (
-                    crate::model::ObjectData::Mesh(from_mesh),
-                    crate::model::ObjectData::Mesh(to_mesh),
-                ) => {
-                    copy_paint_mesh(from_mesh, to_mesh)?;
-                }
-                _ => bail!("Unsupported object types for paint copy"),
-            }
+        let from_comps = self.object_components(from)?.clone();
+        let to_comps = self.object_components(to)?.clone();
+
+        // every pair is matched before anything is written, so a failure leaves `to` as it was
+        let mut updates = vec![];
+        for pair in pairing.pairs.iter() {
+            debug!("Copying paint: {}", pair);
+
+            let from_comp = from_comps
+                .get(pair.from)
+                .with_context(|| format!("No part {} in object {}", pair.from, from))?;
+            let to_comp = to_comps
+                .get(pair.to)
+                .with_context(|| format!("No part {} in object {}", pair.to, to))?;
+            let (paint, report) = mesh_paint(
+                self.component_mesh(from_comp)?,
+                self.component_mesh(to_comp)?,
+            )
+            .with_context(|| format!("Copying paint for {}", pair))?;
+            debug!("matched paint: {}", report);
+            updates.push((to_comp.clone(), paint));
+        }
 
-            #[cfg(feature = "nope")]
-            for (i, from_object) in from_sub_object.model.resources.object.iter().enumerate() {
-                let to_object = to_sub_object
-                    .model
-                    .resources
-                    .object
-                    .get_mut(i)
-                    .context("Object index out of bounds")?;
-                match (&from_object.object, &mut to_object.object) {
-                    (
-                        crate::model::ObjectData::Mesh(from_mesh),
-                        crate::model::ObjectData::Mesh(to_mesh),
-                    ) => {
-                        copy_paint_mesh(from_mesh, to_mesh)?;
-                    }
-                    _ => bail!("Unsupported object types for paint copy"),
-                }
+        for (comp, paint) in updates {
+            let to_mesh = self.component_mesh_mut(&comp)?;
+            for (t, p) in paint {
+                to_mesh.triangles.triangle[t].set_paint(p, true);
             }
-
-            //
         }
 
         Ok(())
@@ -163,12 +184,22 @@ impl OrcaModel {
     pub fn copy_paint(&mut self, from: usize, to: usize) -> Result<()> {
         // let objects = self.get_objects();
 
-        let from_comps = match &self.get_objects()[from].object {
+        let from_comps = match &self
+            .get_objects()
+            .get(from)
+            .with_context(|| format!("No object at index {}", from))?
+            .object
+        {
             crate::model::ObjectData::Components { component } => component,
             _ => bail!("Object at index {} is not a component", from),
         };
 
-        let to_comps = match &self.get_objects()[to].object {
+        let to_comps = match &self
+            .get_objects()
+            .get(to)
+            .with_context(|| format!("No object at index {}", to))?
+            .object
+        {
             crate::model::ObjectData::Components { component } => component,
             _ => bail!("Object at index {} is not a component", to),
         };
@@ -202,40 +233,385 @@ impl OrcaModel {
     }
 }
 
-pub fn copy_paint_mesh(from: &Mesh, to: &mut Mesh) -> Result<()> {
-    _copy_paint_mesh(from, to, true)
+/// A part's shape and name, as used for pairing
+pub type PartInfo = (MeshFingerprint, Option<String>);
+
+/// Pairs parts by fingerprint, preferring equal names, then equal positions in the list
+pub fn pair_parts(from_parts: &[PartInfo], to_parts: &[PartInfo]) -> ComponentPairing {
+    let mut pairs: Vec<Option<ComponentPair>> = vec![None; from_parts.len()];
+    let mut claimed = vec![false; to_parts.len()];
+
+    // names first, so a shared shape doesn't steal a later part's partner
+    for by_name in [true, false] {
+        for (i, (fp, name)) in from_parts.iter().enumerate() {
+            if pairs[i].is_some() {
+                continue;
+            }
+            let candidates = to_parts
+                .iter()
+                .enumerate()
+                .filter(|(j, (fp2, name2))| {
+                    !claimed[*j] && fp == fp2 && (!by_name || (name.is_some() && name == name2))
+                })
+                .map(|(j, _)| j)
+                .collect::<Vec<_>>();
+
+            let Some(&j) = candidates
+                .iter()
+                .find(|&&j| j == i)
+                .or_else(|| candidates.first())
+            else {
+                continue;
+            };
+
+            claimed[j] = true;
+            pairs[i] = Some(ComponentPair {
+                from: i,
+                to: j,
+                from_name: name.clone(),
+                to_name: to_parts[j].1.clone(),
+                by_name,
+            });
+        }
+    }
+
+    let mut out = ComponentPairing::default();
+    for (i, pair) in pairs.into_iter().enumerate() {
+        match pair {
+            Some(pair) => out.pairs.push(pair),
+            None => out.unmatched_from.push((i, from_parts[i].1.clone())),
+        }
+    }
+    for (j, c) in claimed.iter().enumerate() {
+        if !c {
+            out.unmatched_to.push((j, to_parts[j].1.clone()));
+        }
+    }
+
+    out
+}
+
+/// Distance under which two vertices are considered the same point
+pub const MATCH_TOLERANCE: f64 = 1e-3;
+
+/// How the triangles of one mesh were matched onto another
+#[derive(Debug, Clone, Default)]
+pub struct PaintMatchReport {
+    pub from_vertices: usize,
+    pub to_vertices: usize,
+    pub from_triangles: usize,
+    pub to_triangles: usize,
+    /// matched by hashing the sorted vertex positions
+    pub matched_exact: usize,
+    /// matched by the nearest triangle centroid
+    pub matched_nearest: usize,
+    /// matched triangles whose corners are listed in a different order
+    pub reordered: usize,
+    /// source triangles with no partner
+    pub unmatched: Vec<usize>,
+    /// destination triangles claimed by more than one source triangle
+    pub duplicates: Vec<usize>,
+}
+
+impl PaintMatchReport {
+    pub fn is_complete(&self) -> bool {
+        self.unmatched.is_empty() && self.duplicates.is_empty()
+    }
 }
 
-pub fn _copy_paint_mesh(from: &Mesh, to: &mut Mesh, orca: bool) -> Result<()> {
-    /// Ensure the two meshes have the same number of vertices
-    assert_eq!(
-        from.vertices.vertex.len(),
-        to.vertices.vertex.len(),
-        "Vertices count mismatch"
-    );
-    /// Ensure the two meshes have the same number of triangles
-    assert_eq!(
-        from.triangles.triangle.len(),
-        to.triangles.triangle.len(),
-        "Triangles count mismatch"
-    );
-
-    /// Copy the paint data from the source mesh to the destination mesh
-    for (from_triangle, to_triangle) in from
-        .triangles
-        .triangle
+impl std::fmt::Display for PaintMatchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} vertices, {} -> {} triangles, {} exact, {} nearest, {} reordered",
+            self.from_vertices,
+            self.to_vertices,
+            self.from_triangles,
+            self.to_triangles,
+            self.matched_exact,
+            self.matched_nearest,
+            self.reordered,
+        )?;
+        if !self.unmatched.is_empty() {
+            write!(
+                f,
+                ", {} unmatched (first: {:?})",
+                self.unmatched.len(),
+                &self.unmatched[..self.unmatched.len().min(10)]
+            )?;
+        }
+        if !self.duplicates.is_empty() {
+            write!(
+                f,
+                ", {} matched more than once (first: {:?})",
+                self.duplicates.len(),
+                &self.duplicates[..self.duplicates.len().min(10)]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A source triangle's partner in the destination mesh
+#[derive(Debug, Clone, Copy)]
+pub struct TriangleMatch {
+    pub to: usize,
+    /// `corner_map[i]` is the source corner at the destination's corner `i`
+    pub corner_map: [usize; 3],
+}
+
+pub(crate) fn quantize(p: &Vec3) -> [i64; 3] {
+    [
+        (p.x / MATCH_TOLERANCE).round() as i64,
+        (p.y / MATCH_TOLERANCE).round() as i64,
+        (p.z / MATCH_TOLERANCE).round() as i64,
+    ]
+}
+
+fn triangle_key(corners: &[Vec3; 3]) -> [[i64; 3]; 3] {
+    let mut key = [
+        quantize(&corners[0]),
+        quantize(&corners[1]),
+        quantize(&corners[2]),
+    ];
+    key.sort();
+    key
+}
+
+pub fn triangle_corners(mesh: &Mesh, i: usize) -> [Vec3; 3] {
+    let t = &mesh.triangles.triangle[i];
+    let v = |i: usize| {
+        let v = &mesh.vertices.vertex[i];
+        Vec3::new(v.x, v.y, v.z)
+    };
+    [v(t.v1), v(t.v2), v(t.v3)]
+}
+
+/// Which source corner sits at each destination corner, if all three line up
+fn corner_map(from: &[Vec3; 3], to: &[Vec3; 3]) -> Option<[usize; 3]> {
+    let mut out = [0; 3];
+    for (i, p) in to.iter().enumerate() {
+        out[i] = from
+            .iter()
+            .position(|q| (p - q).norm() <= MATCH_TOLERANCE)?;
+    }
+    let mut check = out;
+    check.sort();
+    (check == [0, 1, 2]).then_some(out)
+}
+
+/// Matches triangles by position in the mesh's local frame
+///
+/// Each source triangle is looked up by the hash of its sorted vertex positions, falling
+/// back to the destination triangle with the nearest centroid.
+pub fn match_triangles(
+    from: &[[Vec3; 3]],
+    to: &Mesh,
+) -> (Vec<Option<TriangleMatch>>, PaintMatchReport) {
+    let to_corners = (0..to.triangles.triangle.len())
+        .map(|i| triangle_corners(to, i))
+        .collect::<Vec<_>>();
+
+    let mut by_key: HashMap<[[i64; 3]; 3], usize> = HashMap::new();
+    for (i, c) in to_corners.iter().enumerate() {
+        by_key.entry(triangle_key(c)).or_insert(i);
+    }
+
+    let centroids = to_corners
         .iter()
-        .zip(to.triangles.triangle.iter_mut())
-    {
-        if orca {
-            assert!(from_triangle.mmu_ps.is_none());
-            to_triangle.mmu_orca = from_triangle.mmu_orca.clone();
-        } else {
-            assert!(from_triangle.mmu_orca.is_none());
-            // to_triangle.mmu_orca = None;
-            to_triangle.mmu_ps = from_triangle.mmu_ps.clone();
+        .enumerate()
+        .map(|(i, c)| RVec3::new(i, (c[0] + c[1] + c[2]) / 3.))
+        .collect::<Vec<_>>();
+    let tree = RTree::bulk_load(centroids);
+
+    let mut report = PaintMatchReport {
+        to_vertices: to.vertices.vertex.len(),
+        from_triangles: from.len(),
+        to_triangles: to_corners.len(),
+        ..Default::default()
+    };
+
+    let mut claimed = vec![false; to_corners.len()];
+    let mut matches = vec![];
+
+    for (i, corners) in from.iter().enumerate() {
+        let exact = by_key
+            .get(&triangle_key(corners))
+            .and_then(|&t| corner_map(corners, &to_corners[t]).map(|m| (t, m)));
+
+        let found = match exact {
+            Some(m) => {
+                report.matched_exact += 1;
+                Some(m)
+            }
+            None => {
+                let c = (corners[0] + corners[1] + corners[2]) / 3.;
+                let nearest = tree
+                    .nearest_neighbor(&[c.x, c.y, c.z])
+                    .and_then(|n| corner_map(corners, &to_corners[n.index]).map(|m| (n.index, m)));
+                if nearest.is_some() {
+                    report.matched_nearest += 1;
+                }
+                nearest
+            }
+        };
+
+        match found {
+            Some((t, corner_map)) => {
+                if claimed[t] {
+                    report.duplicates.push(t);
+                }
+                claimed[t] = true;
+                if corner_map != [0, 1, 2] {
+                    report.reordered += 1;
+                }
+                matches.push(Some(TriangleMatch { to: t, corner_map }));
+            }
+            None => {
+                report.unmatched.push(i);
+                matches.push(None);
+            }
         }
     }
 
+    (matches, report)
+}
+
+/// New paint for destination triangles, by triangle index
+pub type MatchedPaint = Vec<(usize, Option<String>)>;
+
+/// The paint of each matched destination triangle, re-expressed for reordered corners
+pub fn matched_paint<'a>(
+    paint: impl Iterator<Item = Option<&'a str>>,
+    matches: &[Option<TriangleMatch>],
+) -> Result<MatchedPaint> {
+    let mut out = vec![];
+    for (p, m) in paint.zip(matches.iter()) {
+        let Some(m) = m else {
+            continue;
+        };
+        let p = match p {
+            Some(p) if m.corner_map != [0, 1, 2] => {
+                PaintTree::decode(p)?.remap_corners(m.corner_map).to_paint()
+            }
+            p => p.map(|p| p.to_string()),
+        };
+        out.push((m.to, p));
+    }
+    Ok(out)
+}
+
+/// Copies paint onto matched triangles, re-expressing it for reordered corners
+///
+/// Nothing is written if any of the paint can't be decoded.
+pub fn apply_matched_paint<'a>(
+    paint: impl Iterator<Item = Option<&'a str>>,
+    matches: &[Option<TriangleMatch>],
+    to: &mut Mesh,
+    orca: bool,
+) -> Result<()> {
+    for (t, p) in matched_paint(paint, matches)? {
+        to.triangles.triangle[t].set_paint(p, orca);
+    }
     Ok(())
 }
+
+pub fn copy_paint_mesh(from: &Mesh, to: &mut Mesh) -> Result<PaintMatchReport> {
+    _copy_paint_mesh(from, to, true)
+}
+
+/// Copies paint between two copies of the same mesh
+///
+/// Triangles are matched by geometry, so the two meshes may list their vertices and
+/// triangles in a different order. Nothing is written unless every triangle finds a
+/// partner.
+pub fn _copy_paint_mesh(from: &Mesh, to: &mut Mesh, orca: bool) -> Result<PaintMatchReport> {
+    let (paint, report) = mesh_paint(from, to)?;
+    for (t, p) in paint {
+        to.triangles.triangle[t].set_paint(p, orca);
+    }
+
+    debug!("copied paint: {}", report);
+
+    Ok(report)
+}
+
+/// The paint `from` would give each triangle of `to`, failing unless every triangle finds
+/// a partner
+pub fn mesh_paint(from: &Mesh, to: &Mesh) -> Result<(MatchedPaint, PaintMatchReport)> {
+    let from_corners = (0..from.triangles.triangle.len())
+        .map(|i| triangle_corners(from, i))
+        .collect::<Vec<_>>();
+
+    let (matches, mut report) = match_triangles(&from_corners, to);
+    report.from_vertices = from.vertices.vertex.len();
+
+    if report.from_triangles != report.to_triangles {
+        bail!("Triangles count mismatch: {}", report);
+    }
+    if !report.is_complete() {
+        bail!("Meshes don't match: {}", report);
+    }
+
+    let paint = matched_paint(from.triangles.triangle.iter().map(|t| t.paint()), &matches)?;
+    Ok((paint, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::{Triangle, Triangles, Vertex, Vertices};
+
+    fn mesh(vertices: &[[f64; 3]], triangles: &[[usize; 3]]) -> Mesh {
+        Mesh {
+            vertices: Vertices {
+                vertex: vertices
+                    .iter()
+                    .map(|&[x, y, z]| Vertex { x, y, z })
+                    .collect(),
+            },
+            triangles: Triangles {
+                triangle: triangles
+                    .iter()
+                    .map(|&[v1, v2, v3]| Triangle {
+                        v1,
+                        v2,
+                        v3,
+                        mmu_ps: None,
+                        mmu_orca: None,
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    #[test]
+    fn matches_triangles_by_position() {
+        let points = [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.], [1., 1., 0.]];
+        let from = mesh(&points, &[[0, 1, 2], [1, 3, 2]]);
+        // the same triangles in the other order, the second one rotated
+        let to = mesh(&points, &[[3, 2, 1], [0, 1, 2]]);
+        let corners = (0..2)
+            .map(|i| triangle_corners(&from, i))
+            .collect::<Vec<_>>();
+
+        let (matches, report) = match_triangles(&corners, &to);
+        assert_eq!(report.matched_exact, 2);
+        assert_eq!(report.reordered, 1);
+        assert!(report.unmatched.is_empty());
+        let first = matches[0].unwrap();
+        assert_eq!((first.to, first.corner_map), (1, [0, 1, 2]));
+        let second = matches[1].unwrap();
+        assert_eq!((second.to, second.corner_map), (0, [1, 2, 0]));
+    }
+
+    #[test]
+    fn leaves_moved_triangles_unmatched() {
+        let from = mesh(&[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]], &[[0, 1, 2]]);
+        let to = mesh(&[[5., 0., 0.], [6., 0., 0.], [5., 1., 0.]], &[[0, 1, 2]]);
+
+        let (matches, report) = match_triangles(&[triangle_corners(&from, 0)], &to);
+        assert!(matches[0].is_none());
+        assert_eq!(report.unmatched, vec![0]);
+    }
+}
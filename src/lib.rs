@@ -6,17 +6,38 @@
 #![allow(unused_labels)]
 #![allow(unexpected_cfgs)]
 
+pub mod auto_paint;
+pub mod bvh;
+pub mod cli;
+pub mod color_import;
+pub mod decimate;
+pub mod export;
+pub mod image_paint;
 pub mod instancing;
 pub mod logging;
 pub mod mesh;
 pub mod metadata;
+pub mod mirror;
 pub mod model;
 pub mod model_2d_display;
 pub mod model_orca;
+pub mod modifier_paint;
+pub mod object_merge;
+pub mod paint_bake;
 pub mod paint_convert;
+pub mod paint_regions;
 pub mod paint_sharing;
+pub mod paint_simplify;
+pub mod paint_solids;
+pub mod paint_tree;
+pub mod plane_cut;
+pub mod part_paint;
+pub mod repair;
 pub mod save_load;
+pub mod shell_split;
 pub mod splitting;
+pub mod topology;
+pub mod transform;
 pub mod ui;
 pub mod utils;
 
@@ -11,5 +11,14 @@ use unjosefizer_lib::{logging::init_logs, *};
 
 fn main() {
     // test_main().unwrap();
+    if std::env::args().len() > 1 {
+        use clap::Parser;
+        init_logs();
+        if let Err(e) = cli::run(cli::Cli::parse()) {
+            tracing::error!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     ui::run_eframe().unwrap();
 }
@@ -47,6 +47,55 @@ impl Mesh {
         offset
     }
 
+    /// The triangles in `range` with only the vertices they use, paint included
+    pub fn sub_mesh(&self, range: std::ops::RangeInclusive<usize>) -> Mesh {
+        self.select(range)
+    }
+
+    /// The given triangles in that order with only the vertices they use, paint included
+    pub fn select(&self, triangles: impl IntoIterator<Item = usize>) -> Mesh {
+        let mut remap = std::collections::HashMap::new();
+        let mut vertex = vec![];
+        let triangle = triangles
+            .into_iter()
+            .map(|t| {
+                let t = &self.triangles.triangle[t];
+                let mut v = |i: usize| {
+                    *remap.entry(i).or_insert_with(|| {
+                        vertex.push(self.vertices.vertex[i]);
+                        vertex.len() - 1
+                    })
+                };
+                Triangle {
+                    v1: v(t.v1),
+                    v2: v(t.v2),
+                    v3: v(t.v3),
+                    ..t.clone()
+                }
+            })
+            .collect();
+        Mesh {
+            vertices: Vertices { vertex },
+            triangles: Triangles { triangle },
+        }
+    }
+
+    /// Min and max corners of the axis-aligned bounding box, `None` for an empty mesh
+    pub fn bounding_box(&self) -> Option<([f64; 3], [f64; 3])> {
+        let first = self.vertices.vertex.first()?;
+
+        let mut min = [first.x, first.y, first.z];
+        let mut max = min;
+        for v in self.vertices.vertex.iter() {
+            for (i, x) in [v.x, v.y, v.z].into_iter().enumerate() {
+                min[i] = min[i].min(x);
+                max[i] = max[i].max(x);
+            }
+        }
+
+        Some((min, max))
+    }
+
     pub fn to_ps(&mut self) {
         for t in self.triangles.triangle.iter_mut() {
             if let Some(mmu) = t.mmu_orca.take() {
@@ -213,3 +262,21 @@ pub struct Triangle {
     #[serde(rename = "@paint_color", skip_serializing_if = "Option::is_none")]
     pub mmu_orca: Option<String>,
 }
+
+impl Triangle {
+    /// The paint of this triangle, in whichever slicer format it was loaded
+    pub fn paint(&self) -> Option<&str> {
+        self.mmu_orca.as_deref().or(self.mmu_ps.as_deref())
+    }
+
+    /// Sets the paint as Orca `paint_color` or PrusaSlicer `mmu_segmentation`
+    pub fn set_paint(&mut self, paint: Option<String>, orca: bool) {
+        if orca {
+            self.mmu_ps = None;
+            self.mmu_orca = paint;
+        } else {
+            self.mmu_orca = None;
+            self.mmu_ps = paint;
+        }
+    }
+}
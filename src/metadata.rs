@@ -59,7 +59,7 @@ pub mod ps_metadata {
         pub mesh: Mesh,
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
     #[serde(rename_all = "lowercase")]
     pub struct Mesh {
         #[serde(rename = "@edges_fixed")]
@@ -132,7 +132,16 @@ pub mod orca_metadata {
         pub mesh_stat: MeshStat,
     }
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
+    impl Part {
+        pub fn get_name(&self) -> Option<String> {
+            self.metadata
+                .iter()
+                .find(|m| m.key.as_deref() == Some("name"))
+                .and_then(|m| m.value.clone())
+        }
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
     #[serde(rename = "mesh_stat")]
     pub struct MeshStat {
         #[serde(rename = "@edges_fixed")]
@@ -0,0 +1,598 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::Matrix3;
+use rstar::RTree;
+
+use crate::{
+    instancing::{
+        apply_matched_paint, match_triangles, triangle_corners, PaintMatchReport, MATCH_TOLERANCE,
+    },
+    mesh::{Mesh, Triangle, Triangles, Vertex, Vertices},
+    model_orca::OrcaModel,
+    splitting::{rvec3::RVec3, Vec3},
+};
+
+const AXES: [&str; 3] = ["X", "Y", "Z"];
+
+/// A mirror plane, given by its normal
+///
+/// Mirrored objects are lined up on their centroids before matching, so only the orientation
+/// of the plane matters.
+#[derive(Debug, Clone, Copy)]
+pub struct MirrorPlane {
+    pub normal: Vec3,
+}
+
+impl MirrorPlane {
+    /// The plane normal to the X, Y or Z axis
+    pub fn axis(axis: usize) -> Self {
+        let mut normal = Vec3::zeros();
+        normal[axis] = 1.;
+        Self { normal }
+    }
+
+    pub fn reflection(&self) -> Matrix3<f64> {
+        let n = self.normal.normalize();
+        Matrix3::identity() - 2. * n * n.transpose()
+    }
+}
+
+/// How the paint of one object was mirrored onto another
+#[derive(Debug, Clone)]
+pub struct MirrorReport {
+    /// which mirror lined the objects up
+    pub mirror: String,
+    pub matches: PaintMatchReport,
+}
+
+impl std::fmt::Display for MirrorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "mirrored by {}: {}", self.mirror, self.matches)
+    }
+}
+
+/// Every triangle of an object in world space
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WorldTriangles {
+    pub corners: Vec<[Vec3; 3]>,
+    /// component and triangle index each triangle came from
+    pub source: Vec<(usize, usize)>,
+    pub paint: Vec<Option<String>>,
+    /// vertices of the component meshes, for reports
+    pub vertices: usize,
+}
+
+impl WorldTriangles {
+    /// Mean of all triangle corners, moves with the mesh under any affine map
+    pub fn centroid(&self) -> Vec3 {
+        let sum = self
+            .corners
+            .iter()
+            .flatten()
+            .fold(Vec3::zeros(), |acc, p| acc + p);
+        sum / (self.corners.len() * 3).max(1) as f64
+    }
+
+    /// A mesh with three unshared vertices per triangle and the paint as Orca `paint_color`
+    pub fn soup_mesh(&self) -> Mesh {
+        Mesh {
+            vertices: Vertices {
+                vertex: self
+                    .corners
+                    .iter()
+                    .flatten()
+                    .map(|p| Vertex {
+                        x: p.x,
+                        y: p.y,
+                        z: p.z,
+                    })
+                    .collect(),
+            },
+            triangles: Triangles {
+                triangle: (0..self.corners.len())
+                    .map(|i| Triangle {
+                        v1: i * 3,
+                        v2: i * 3 + 1,
+                        v3: i * 3 + 2,
+                        mmu_ps: None,
+                        mmu_orca: self.paint[i].clone(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+impl OrcaModel {
+    pub(crate) fn world_triangles(&self, index: usize) -> Result<WorldTriangles> {
+        self.collect_triangles(index, true)
+    }
+
+    /// Like [`OrcaModel::world_triangles`], without the build item transform
+    pub(crate) fn object_triangles(&self, index: usize) -> Result<WorldTriangles> {
+        self.collect_triangles(index, false)
+    }
+
+    fn collect_triangles(&self, index: usize, world: bool) -> Result<WorldTriangles> {
+        let mut out = WorldTriangles::default();
+
+        for (c, comp) in self.object_components(index)?.iter().enumerate() {
+            let transform = if world {
+                self.component_world_transform(index, comp)?
+            } else {
+                comp.transform
+                    .as_ref()
+                    .map(crate::utils::transform_3mf)
+                    .unwrap_or_else(nalgebra::Matrix4::identity)
+            };
+            let mesh = self.component_mesh(comp)?;
+            out.vertices += mesh.vertices.vertex.len();
+
+            for (t, tri) in mesh.triangles.triangle.iter().enumerate() {
+                let corners =
+                    triangle_corners(mesh, t).map(|p| transform.transform_point(&p.into()).coords);
+                out.corners.push(corners);
+                out.source.push((c, t));
+                out.paint.push(tri.paint().map(str::to_string));
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Writes the paint of a mesh from [`WorldTriangles::soup_mesh`] back to the object
+    pub(crate) fn write_soup_paint(
+        &mut self,
+        index: usize,
+        tris: &WorldTriangles,
+        soup: Mesh,
+    ) -> Result<()> {
+        let comps = self.object_components(index)?.clone();
+        for ((c, t), tri) in tris.source.iter().zip(soup.triangles.triangle) {
+            self.component_mesh_mut(&comps[*c])?.triangles.triangle[*t]
+                .set_paint(tri.mmu_orca, true);
+        }
+        Ok(())
+    }
+
+    /// Copies paint onto a mirrored copy of an object
+    ///
+    /// Triangles are matched in world space after mirroring, and the paint inside each
+    /// triangle is mirrored with it. Without a plane, the mirror is read from the two objects'
+    /// transforms, then tried along each axis of the source object in case the mesh itself was
+    /// mirrored.
+    pub fn mirror_paint(
+        &mut self,
+        from: usize,
+        to: usize,
+        plane: Option<MirrorPlane>,
+    ) -> Result<MirrorReport> {
+        let from_tris = self.world_triangles(from)?;
+        let to_tris = self.world_triangles(to)?;
+
+        if from_tris.corners.len() != to_tris.corners.len() {
+            bail!(
+                "Triangle count mismatch: {} in object {} and {} in object {}",
+                from_tris.corners.len(),
+                from,
+                to_tris.corners.len(),
+                to
+            );
+        }
+
+        let from_center = from_tris.centroid();
+        let to_center = to_tris.centroid();
+        let mut to_mesh = to_tris.soup_mesh();
+
+        let mut best: Option<(String, Vec<_>, PaintMatchReport)> = None;
+        for (name, m) in self.mirror_candidates(from, to, plane)? {
+            let mapped = from_tris
+                .corners
+                .iter()
+                .map(|c| c.map(|p| m * (p - from_center) + to_center))
+                .collect::<Vec<_>>();
+
+            let (matches, mut report) = match_triangles(&mapped, &to_mesh);
+            report.from_vertices = from_tris.vertices;
+            report.to_vertices = to_tris.vertices;
+            debug!("mirror candidate {}: {}", name, report);
+
+            let complete = report.is_complete();
+            if best
+                .as_ref()
+                .is_none_or(|b| complete || report.unmatched.len() < b.2.unmatched.len())
+            {
+                best = Some((name, matches, report));
+            }
+            if complete {
+                break;
+            }
+        }
+
+        let (mirror, matches, report) = best.context("No mirror to try")?;
+        if !report.is_complete() {
+            bail!(
+                "Objects {} and {} don't line up, closest was {}: {}",
+                from,
+                to,
+                mirror,
+                report
+            );
+        }
+
+        apply_matched_paint(
+            from_tris.paint.iter().map(|p| p.as_deref()),
+            &matches,
+            &mut to_mesh,
+            true,
+        )?;
+
+        self.write_soup_paint(to, &to_tris, to_mesh)?;
+
+        let report = MirrorReport {
+            mirror,
+            matches: report,
+        };
+        debug!("mirrored paint: {}", report);
+
+        Ok(report)
+    }
+
+    /// Linear maps taking the source object onto the mirrored one, most likely first
+    fn mirror_candidates(
+        &self,
+        from: usize,
+        to: usize,
+        plane: Option<MirrorPlane>,
+    ) -> Result<Vec<(String, Matrix3<f64>)>> {
+        if let Some(plane) = plane {
+            let n = plane.normal;
+            return Ok(vec![(
+                format!("plane ({:.3}, {:.3}, {:.3})", n.x, n.y, n.z),
+                plane.reflection(),
+            )]);
+        }
+
+        let a = self
+            .item_transform(from)?
+            .fixed_view::<3, 3>(0, 0)
+            .into_owned();
+        let b = self
+            .item_transform(to)?
+            .fixed_view::<3, 3>(0, 0)
+            .into_owned();
+        let a_inv = a
+            .try_inverse()
+            .ok_or_else(|| anyhow!("Transform of object {} is not invertible", from))?;
+
+        let mut out = vec![];
+
+        let m = b * a_inv;
+        if m.determinant() < 0. {
+            out.push(("object transforms".to_string(), m));
+        }
+        for (axis, name) in AXES.iter().enumerate() {
+            let h = MirrorPlane::axis(axis).reflection();
+            out.push((format!("mesh mirrored along {}", name), b * h * a_inv));
+        }
+
+        Ok(out)
+    }
+}
+
+/// A symmetry plane within one mesh
+#[derive(Debug, Clone, Copy)]
+pub struct SymmetryPlane {
+    pub point: Vec3,
+    /// unit length
+    pub normal: Vec3,
+}
+
+impl SymmetryPlane {
+    pub fn new(point: Vec3, normal: Vec3) -> Self {
+        Self {
+            point,
+            normal: normal.normalize(),
+        }
+    }
+
+    /// The plane normal to the X, Y or Z axis through the center of the bounding box
+    pub fn axis(mesh: &Mesh, axis: usize) -> Self {
+        let center = mesh
+            .bounding_box()
+            .map(|(min, max)| (Vec3::from(min) + Vec3::from(max)) / 2.)
+            .unwrap_or_else(Vec3::zeros);
+        Self::new(center, MirrorPlane::axis(axis).normal)
+    }
+
+    pub fn signed_distance(&self, p: &Vec3) -> f64 {
+        (p - self.point).dot(&self.normal)
+    }
+
+    pub fn reflect(&self, p: &Vec3) -> Vec3 {
+        p - 2. * self.signed_distance(p) * self.normal
+    }
+
+    /// Fits a plane the mesh is symmetric about, with the fraction of vertices it maps onto
+    /// another vertex
+    ///
+    /// Tries the coordinate axes and the principal axes of the surface through its area
+    /// centroid, keeps the best, then refines it from the matched vertex pairs.
+    pub fn fit(mesh: &Mesh) -> Result<(Self, f64)> {
+        let n = mesh.triangles.triangle.len();
+        ensure!(n > 0, "Can't fit a symmetry plane to an empty mesh");
+
+        let mut total_area = 0.;
+        let mut centroid = Vec3::zeros();
+        let mut weighted = vec![];
+        for i in 0..n {
+            let [a, b, c] = triangle_corners(mesh, i);
+            let area = (b - a).cross(&(c - a)).norm() / 2.;
+            let g = (a + b + c) / 3.;
+            total_area += area;
+            centroid += g * area;
+            weighted.push((g, area));
+        }
+        ensure!(total_area > 0., "Mesh has no area");
+        centroid /= total_area;
+
+        let covariance = weighted.iter().fold(Matrix3::zeros(), |acc, (g, area)| {
+            acc + (g - centroid) * (g - centroid).transpose() * *area
+        });
+        let eigen = covariance.symmetric_eigen();
+
+        let vertices = mesh
+            .vertices
+            .vertex
+            .iter()
+            .map(|v| Vec3::new(v.x, v.y, v.z))
+            .collect::<Vec<_>>();
+        let tree = RTree::bulk_load(
+            vertices
+                .iter()
+                .enumerate()
+                .map(|(i, p)| RVec3::new(i, *p))
+                .collect::<Vec<_>>(),
+        );
+
+        let candidates = (0..3)
+            .map(|axis| MirrorPlane::axis(axis).normal)
+            .chain(eigen.eigenvectors.column_iter().map(|c| c.into_owned()))
+            .map(|normal| Self::new(centroid, normal));
+
+        let mut best: Option<(Self, Vec<(Vec3, Vec3)>)> = None;
+        for plane in candidates {
+            let pairs = plane.vertex_pairs(&vertices, &tree);
+            trace!(
+                "symmetry candidate {:?}: {} pairs",
+                plane.normal,
+                pairs.len()
+            );
+            if best.as_ref().is_none_or(|b| pairs.len() > b.1.len()) {
+                best = Some((plane, pairs));
+            }
+        }
+        let (mut plane, pairs) = best.context("No symmetry plane to try")?;
+
+        /// the midpoints of mirrored vertex pairs lie on the plane
+        let off_plane = pairs
+            .iter()
+            .filter(|(p, q)| (p - q).norm() > MATCH_TOLERANCE)
+            .collect::<Vec<_>>();
+        if !off_plane.is_empty() {
+            let point = off_plane
+                .iter()
+                .fold(Vec3::zeros(), |acc, (p, q)| acc + (p + q) / 2.)
+                / off_plane.len() as f64;
+            let normal = off_plane.iter().fold(Vec3::zeros(), |acc, (p, q)| {
+                let d = (p - q).normalize();
+                acc + if d.dot(&plane.normal) < 0. { -d } else { d }
+            });
+            let refined = Self::new(point, normal);
+            if refined.vertex_pairs(&vertices, &tree).len() >= pairs.len() {
+                plane = refined;
+            }
+        }
+
+        /// make the side choice repeatable: the normal's largest component is positive
+        let largest = plane.normal.iamax();
+        if plane.normal[largest] < 0. {
+            plane.normal = -plane.normal;
+        }
+
+        let score = plane.vertex_pairs(&vertices, &tree).len() as f64 / vertices.len() as f64;
+        debug!(
+            "fitted symmetry plane through {:?} normal {:?}, {:.1}% of vertices mirrored",
+            plane.point,
+            plane.normal,
+            score * 100.
+        );
+
+        Ok((plane, score))
+    }
+
+    /// Vertices whose reflection lands on another vertex
+    fn vertex_pairs(&self, vertices: &[Vec3], tree: &RTree<RVec3>) -> Vec<(Vec3, Vec3)> {
+        vertices
+            .iter()
+            .filter_map(|p| {
+                let r = self.reflect(p);
+                let nearest = tree.nearest_neighbor(&[r.x, r.y, r.z])?;
+                ((nearest.pos - r).norm() <= MATCH_TOLERANCE).then_some((*p, nearest.pos))
+            })
+            .collect()
+    }
+}
+
+/// How paint was mirrored across a symmetry plane
+#[derive(Debug, Clone)]
+pub struct SymmetryReport {
+    pub plane: SymmetryPlane,
+    /// fraction of vertices mirrored onto another vertex, for fitted planes
+    pub fit_score: Option<f64>,
+    /// the source half is the one the normal points to
+    pub from_positive: bool,
+    /// painted triangles on the source half
+    pub painted: usize,
+    pub mirrored: usize,
+    /// painted source triangles whose reflection matches no triangle
+    pub unpartnered: Vec<usize>,
+    /// painted triangles centered on the plane, left as they are
+    pub on_plane: Vec<usize>,
+}
+
+impl std::fmt::Display for SymmetryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (p, n) = (self.plane.point, self.plane.normal);
+        write!(
+            f,
+            "plane through ({:.3}, {:.3}, {:.3}) normal ({:.3}, {:.3}, {:.3})",
+            p.x, p.y, p.z, n.x, n.y, n.z
+        )?;
+        if let Some(score) = self.fit_score {
+            write!(f, " (fitted, {:.1}% of vertices mirrored)", score * 100.)?;
+        }
+        write!(
+            f,
+            ", {} side: {} of {} painted triangles mirrored",
+            if self.from_positive {
+                "positive"
+            } else {
+                "negative"
+            },
+            self.mirrored,
+            self.painted
+        )?;
+        if !self.unpartnered.is_empty() {
+            write!(
+                f,
+                ", {} without a partner (first: {:?})",
+                self.unpartnered.len(),
+                &self.unpartnered[..self.unpartnered.len().min(10)]
+            )?;
+        }
+        if !self.on_plane.is_empty() {
+            write!(f, ", {} on the plane", self.on_plane.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Mirrors the paint of one half of a mesh onto the other half
+///
+/// Without a plane, one is fitted with [`SymmetryPlane::fit`]. Without a side, the half with
+/// more painted triangles is the source. Triangles on the other half are found through the
+/// same centroid index as [`match_triangles`], and get the reflected paint tree, or lose their
+/// paint when their source triangle has none.
+pub fn mirror_paint_symmetric(
+    mesh: &mut Mesh,
+    plane: Option<SymmetryPlane>,
+    from_positive: Option<bool>,
+    orca: bool,
+) -> Result<SymmetryReport> {
+    let (plane, fit_score) = match plane {
+        Some(plane) => (plane, None),
+        None => {
+            let (plane, score) = SymmetryPlane::fit(mesh)?;
+            (plane, Some(score))
+        }
+    };
+
+    let n = mesh.triangles.triangle.len();
+    let corners = (0..n)
+        .map(|i| triangle_corners(mesh, i))
+        .collect::<Vec<_>>();
+
+    let painted = mesh
+        .triangles
+        .triangle
+        .iter()
+        .map(|t| t.paint().is_some())
+        .collect::<Vec<_>>();
+
+    let mut on_plane = vec![];
+    let mut positive = vec![];
+    let mut negative = vec![];
+    for (i, c) in corners.iter().enumerate() {
+        let d = plane.signed_distance(&((c[0] + c[1] + c[2]) / 3.));
+        if d.abs() <= MATCH_TOLERANCE {
+            if painted[i] {
+                on_plane.push(i);
+            }
+        } else if d > 0. {
+            positive.push(i);
+        } else {
+            negative.push(i);
+        }
+    }
+
+    let count_painted = |side: &[usize]| side.iter().filter(|&&i| painted[i]).count();
+    let from_positive =
+        from_positive.unwrap_or(count_painted(&positive) >= count_painted(&negative));
+    // unpainted source triangles are mirrored too, clearing their partners
+    let sources = if from_positive { positive } else { negative };
+
+    let reflected = sources
+        .iter()
+        .map(|&i| corners[i].map(|p| plane.reflect(&p)))
+        .collect::<Vec<_>>();
+    let (matches, report) = match_triangles(&reflected, mesh);
+    trace!("symmetry matches: {}", report);
+
+    let unpartnered = sources
+        .iter()
+        .zip(matches.iter())
+        .filter(|(&i, m)| m.is_none() && painted[i])
+        .map(|(&i, _)| i)
+        .collect::<Vec<_>>();
+    let painted_sources = count_painted(&sources);
+
+    let paint = sources
+        .iter()
+        .map(|&i| mesh.triangles.triangle[i].paint().map(str::to_string))
+        .collect::<Vec<_>>();
+    apply_matched_paint(paint.iter().map(|p| p.as_deref()), &matches, mesh, orca)?;
+
+    let report = SymmetryReport {
+        plane,
+        fit_score,
+        from_positive,
+        painted: painted_sources,
+        mirrored: painted_sources - unpartnered.len(),
+        unpartnered,
+        on_plane,
+    };
+    debug!("symmetric paint: {}", report);
+
+    Ok(report)
+}
+
+impl OrcaModel {
+    /// Mirrors paint across a symmetry plane of the object at `index`, see
+    /// [`mirror_paint_symmetric`]
+    ///
+    /// Works on all parts together in object space, so paint can cross from one part to its
+    /// mirrored twin. An axis gives a plane through the center of the object.
+    pub fn mirror_paint_symmetric(
+        &mut self,
+        index: usize,
+        axis: Option<usize>,
+        from_positive: Option<bool>,
+    ) -> Result<SymmetryReport> {
+        let tris = self.object_triangles(index)?;
+        let mut soup = tris.soup_mesh();
+
+        let plane = axis.map(|axis| SymmetryPlane::axis(&soup, axis));
+        let report = mirror_paint_symmetric(&mut soup, plane, from_positive, true)?;
+
+        for &i in report.unpartnered.iter().take(10) {
+            let (c, t) = tris.source[i];
+            warn!("No mirror partner for part {} triangle {}", c, t);
+        }
+
+        self.write_soup_paint(index, &tris, soup)?;
+
+        Ok(report)
+    }
+}
@@ -1,4 +1,5 @@
 use super::mesh::*;
+use crate::metadata::ps_metadata::{self as ps, PSMetadata};
 
 use serde::{Deserialize, Serialize};
 
@@ -153,6 +154,125 @@ impl Item {
     }
 }
 
+impl Model {
+    /// The build item transform of the object at `index`, identity if there is none
+    pub fn item_transform(&self, index: usize) -> anyhow::Result<nalgebra::Matrix4<f64>> {
+        let object = self
+            .resources
+            .object
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("Object index {} out of range", index))?;
+
+        Ok(self
+            .build
+            .get_item_by_id(object.id)
+            .and_then(|item| item.transform.as_ref())
+            .map(crate::utils::transform_3mf)
+            .unwrap_or_else(nalgebra::Matrix4::identity))
+    }
+
+    /// Sets the build item transform of the object at `index`
+    pub fn set_item_transform(
+        &mut self,
+        index: usize,
+        transform: &nalgebra::Matrix4<f64>,
+    ) -> anyhow::Result<()> {
+        let object = self
+            .resources
+            .object
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("Object index {} out of range", index))?;
+        let object_id = object.id;
+        let item = self
+            .build
+            .item
+            .iter_mut()
+            .find(|i| i.objectid == object_id)
+            .ok_or_else(|| anyhow::anyhow!("Object {} has no build item", object_id))?;
+        item.transform = Some(crate::utils::to_transform_3mf(transform));
+        Ok(())
+    }
+
+    /// Copies the object at `index` under a new id, with its volumes in `md`, returning the
+    /// index of the copy
+    ///
+    /// The copy has the same placement and settings, with `name`.
+    pub fn duplicate_object(
+        &mut self,
+        index: usize,
+        md: Option<&mut PSMetadata>,
+        name: &str,
+    ) -> anyhow::Result<usize> {
+        let object = self
+            .resources
+            .object
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("Object index {} out of range", index))?
+            .clone();
+        let id = self
+            .resources
+            .object
+            .iter()
+            .map(|o| o.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        let mut md = md;
+        if let Some(md) = md.as_deref_mut() {
+            if let Some(md_object) = md.get_object_by_id(object.id).cloned() {
+                md.object.push(ps::Object { id, ..md_object });
+            }
+        }
+        if let Some(item) = self.build.get_item_by_id(object.id).cloned() {
+            self.build.item.push(Item {
+                objectid: id,
+                ..item
+            });
+        }
+        self.resources.object.push(Object {
+            id,
+            uuid: None,
+            ..object
+        });
+
+        let copy = self.resources.object.len() - 1;
+        self.rename_object(copy, md, name)?;
+        Ok(copy)
+    }
+
+    /// Renames the object at `index`, and in `md`
+    pub fn rename_object(
+        &mut self,
+        index: usize,
+        md: Option<&mut PSMetadata>,
+        name: &str,
+    ) -> anyhow::Result<()> {
+        let object = self
+            .resources
+            .object
+            .get_mut(index)
+            .ok_or_else(|| anyhow::anyhow!("Object index {} out of range", index))?;
+        object.name = Some(name.to_string());
+
+        let md_object = md.and_then(|md| md.object.iter_mut().find(|o| o.id == object.id));
+        if let Some(md_object) = md_object {
+            md_object
+                .metadata
+                .retain(|m| m.key.as_deref() != Some("name"));
+            md_object.metadata.insert(
+                0,
+                ps::Metadata {
+                    ty: "object".to_string(),
+                    key: Some("name".to_string()),
+                    value: Some(name.to_string()),
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
 impl Default for Model {
     fn default() -> Self {
         Self {
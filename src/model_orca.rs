@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     mesh::Mesh,
-    metadata::orca_metadata::OrcaMetadata,
+    metadata::{orca_metadata as orca, orca_metadata::OrcaMetadata},
     model::{Component, Model, Object},
 };
 
@@ -31,6 +31,14 @@ pub struct OrcaModel {
     pub preview_size: u32,
 }
 
+/// A part for [`OrcaModel::replace_part`]
+#[derive(Debug, Clone)]
+pub struct NewPart {
+    pub name: String,
+    pub mesh: Mesh,
+    pub extruder: u8,
+}
+
 #[derive(Debug, Clone)]
 pub struct SubModel {
     pub id: usize,
@@ -281,6 +289,533 @@ impl OrcaModel {
         out
     }
 
+    /// The mesh a component points to, in its sub-model file
+    pub fn component_mesh(&self, comp: &Component) -> Result<&Mesh> {
+        let path = comp.path.as_ref().context("Component has no path")?;
+        let sub_model = self
+            .sub_models
+            .get(&path[1..])
+            .with_context(|| format!("Sub-model {} not found in sub-models", path))?;
+
+        sub_model
+            .model
+            .resources
+            .object
+            .iter()
+            .find(|o| o.id == comp.objectid)
+            .with_context(|| format!("Object {} not found in sub-model {}", comp.objectid, path))?
+            .object
+            .get_mesh()
+            .context("expected mesh, got nested component")
+    }
+
+    pub fn component_mesh_mut(&mut self, comp: &Component) -> Result<&mut Mesh> {
+        let path = comp.path.as_ref().context("Component has no path")?;
+        let sub_model = self
+            .sub_models
+            .get_mut(&path[1..])
+            .with_context(|| format!("Sub-model {} not found in sub-models", path))?;
+
+        sub_model
+            .model
+            .resources
+            .object
+            .iter_mut()
+            .find(|o| o.id == comp.objectid)
+            .with_context(|| format!("Object {} not found in sub-model {}", comp.objectid, path))?
+            .object
+            .get_mesh_mut()
+            .context("expected mesh, got nested component")
+    }
+
+    /// The components of the object at `index`
+    pub fn object_components(&self, index: usize) -> Result<&Vec<Component>> {
+        self.get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .object
+            .get_components()
+            .with_context(|| format!("Object at index {} is not a component", index))
+    }
+
+    /// The build item transform of the object at `index`, identity when there is none
+    pub fn item_transform(&self, index: usize) -> Result<nalgebra::Matrix4<f64>> {
+        let object = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?;
+
+        Ok(self
+            .model
+            .build
+            .get_item_by_id(object.id)
+            .and_then(|item| item.transform.as_ref())
+            .map(crate::utils::transform_3mf)
+            .unwrap_or_else(nalgebra::Matrix4::identity))
+    }
+
+    /// Takes a component's mesh of the object at `index` to world space
+    pub fn component_world_transform(
+        &self,
+        index: usize,
+        comp: &Component,
+    ) -> Result<nalgebra::Matrix4<f64>> {
+        let comp_transform = comp
+            .transform
+            .as_ref()
+            .map(crate::utils::transform_3mf)
+            .unwrap_or_else(nalgebra::Matrix4::identity);
+
+        Ok(self.item_transform(index)? * comp_transform)
+    }
+
+    /// Sets the build item transform of the object at `index`
+    pub fn set_item_transform(
+        &mut self,
+        index: usize,
+        transform: &nalgebra::Matrix4<f64>,
+    ) -> Result<()> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let item = self
+            .model
+            .build
+            .item
+            .iter_mut()
+            .find(|i| i.objectid == object_id)
+            .with_context(|| format!("Object {} has no build item", object_id))?;
+        item.transform = Some(crate::utils::to_transform_3mf(transform));
+        Ok(())
+    }
+
+    /// Sets the transform of component `comp` of the object at `index`, and the part matrix
+    /// in `model_settings.config` with it
+    pub fn set_component_transform(
+        &mut self,
+        index: usize,
+        comp: usize,
+        transform: &nalgebra::Matrix4<f64>,
+    ) -> Result<()> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let object_components = self.model.resources.object[index]
+            .object
+            .get_components_mut()
+            .context("Object is not a component")?;
+        let component = object_components
+            .get_mut(comp)
+            .with_context(|| format!("No component {} in object {}", comp, index))?;
+        component.transform = Some(crate::utils::to_transform_3mf(transform));
+        let part_id = component.objectid;
+        if let Some((_, c)) = self.sub_objects.iter_mut().find(|(id, _)| *id == object_id) {
+            *c = object_components.clone();
+        }
+
+        let part = self
+            .md
+            .object
+            .iter_mut()
+            .find(|o| o.id == object_id)
+            .and_then(|o| o.part.iter_mut().find(|p| p.id == part_id));
+        let Some(part) = part else {
+            warn!("Part {} not found in metadata", part_id);
+            return Ok(());
+        };
+        let matrix = Some(crate::utils::matrix_string(transform));
+        match part
+            .metadata
+            .iter_mut()
+            .find(|m| m.key.as_deref() == Some("matrix"))
+        {
+            Some(m) => m.value = matrix,
+            None => part.metadata.push(orca::Metadata {
+                key: Some("matrix".to_string()),
+                value: matrix,
+            }),
+        }
+        Ok(())
+    }
+
+    /// The part name from `model_settings.config`
+    pub fn part_name(&self, object_id: usize, part_id: usize) -> Option<String> {
+        self.md
+            .get_object_by_id(object_id)?
+            .part
+            .iter()
+            .find(|p| p.id == part_id)?
+            .get_name()
+    }
+
+    /// The part subtype from `model_settings.config`, like `normal_part` or `modifier_part`
+    pub fn part_subtype(&self, object_id: usize, part_id: usize) -> Option<String> {
+        self.md
+            .get_object_by_id(object_id)?
+            .part
+            .iter()
+            .find(|p| p.id == part_id)
+            .map(|p| p.subtype.clone())
+    }
+
+    /// The extruder a part prints with when unpainted, set on the part or its object
+    pub fn part_extruder(&self, object_id: usize, part_id: usize) -> u8 {
+        let extruder = |md: &[crate::metadata::orca_metadata::Metadata]| {
+            md.iter()
+                .find(|m| m.key.as_deref() == Some("extruder"))
+                .and_then(|m| m.value.as_ref()?.parse::<u8>().ok())
+                .filter(|&e| e > 0)
+        };
+        let Some(object) = self.md.get_object_by_id(object_id) else {
+            return 1;
+        };
+        object
+            .part
+            .iter()
+            .find(|p| p.id == part_id)
+            .and_then(|p| extruder(&p.metadata))
+            .or_else(|| extruder(&object.metadata))
+            .unwrap_or(1)
+    }
+
+    /// Filament colors from the project settings, filament `i` at `i - 1`
+    pub fn filament_colors(&self) -> Result<Vec<(u8, u8, u8)>> {
+        let cfg: serde_json::Value = serde_json::from_str(&self.slice_cfg)?;
+        let colors = cfg["filament_colour"]
+            .as_array()
+            .context("filament_colour not found")?
+            .iter()
+            .map(|c| c.as_str().context("color not a string"))
+            .collect::<Result<Vec<_>>>()?;
+        crate::color_import::parse_colors(&colors.join(","))
+    }
+
+    /// An object id not used by the main model or any sub-model
+    pub fn next_object_id(&self) -> usize {
+        self.model
+            .resources
+            .object
+            .iter()
+            .chain(
+                self.sub_models
+                    .values()
+                    .flat_map(|s| s.model.resources.object.iter()),
+            )
+            .map(|o| o.id)
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    /// Replaces the part behind component `comp` of the object at `index` with `parts`
+    ///
+    /// The new parts keep the transform and settings of the old one, with their own name and
+    /// extruder. The first one takes over the old part's id. Without `parts` the old part is
+    /// removed, as long as the object keeps another one.
+    pub fn replace_part(&mut self, index: usize, comp: usize, parts: Vec<NewPart>) -> Result<()> {
+        ensure!(
+            !parts.is_empty() || self.object_components(index)?.len() > 1,
+            "Can't remove the last part of object {}",
+            index
+        );
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let old = self
+            .object_components(index)?
+            .get(comp)
+            .with_context(|| format!("No component {} in object {}", comp, index))?
+            .clone();
+        let path = old.path.clone().context("Component has no path")?;
+
+        let next = self.next_object_id();
+        let mut ids = vec![old.objectid];
+        ids.extend((next..).take(parts.len().saturating_sub(1)));
+
+        let md_position = self
+            .md
+            .object
+            .iter()
+            .position(|o| o.id == object_id)
+            .with_context(|| format!("Object {} not found in metadata", object_id))?;
+        let md_index = self.md.object[md_position]
+            .part
+            .iter()
+            .position(|p| p.id == old.objectid)
+            .with_context(|| format!("Part {} not found in metadata", old.objectid))?;
+        let sub_model = self
+            .sub_models
+            .get_mut(&path[1..])
+            .with_context(|| format!("Sub-model {} not found in sub-models", path))?;
+        let sub_objects = &mut sub_model.model.resources.object;
+        let sub_index = sub_objects
+            .iter()
+            .position(|o| o.id == old.objectid)
+            .with_context(|| format!("Object {} not found in sub-model {}", old.objectid, path))?;
+
+        // everything is found, so the old part goes from both places or neither
+        let md_object = &mut self.md.object[md_position];
+        let md_old = md_object.part.remove(md_index);
+        let sub_old = sub_objects.remove(sub_index);
+
+        let mut components = vec![];
+        let mut painted = false;
+        for (k, (part, id)) in parts.into_iter().zip(ids).enumerate() {
+            painted |= part
+                .mesh
+                .triangles
+                .triangle
+                .iter()
+                .any(|t| t.paint().is_some());
+
+            let mut md = md_old.clone();
+            md.id = id;
+            md.metadata
+                .retain(|m| !matches!(m.key.as_deref(), Some("name") | Some("extruder")));
+            md.metadata.insert(
+                0,
+                orca::Metadata {
+                    key: Some("name".to_string()),
+                    value: Some(part.name),
+                },
+            );
+            md.metadata.push(orca::Metadata {
+                key: Some("extruder".to_string()),
+                value: Some(part.extruder.to_string()),
+            });
+            md_object.part.insert(md_index + k, md);
+
+            sub_objects.insert(
+                sub_index + k,
+                Object {
+                    id,
+                    // UUIDs have to stay unique
+                    uuid: sub_old.uuid.clone().filter(|_| k == 0),
+                    object: crate::model::ObjectData::Mesh(part.mesh),
+                    ..sub_old.clone()
+                },
+            );
+
+            components.push(Component {
+                objectid: id,
+                uuid: old.uuid.clone().filter(|_| k == 0),
+                ..old.clone()
+            });
+        }
+
+        let object_components = self.model.resources.object[index]
+            .object
+            .get_components_mut()
+            .context("Object is not a component")?;
+        object_components.splice(comp..=comp, components.iter().cloned());
+        if let Some((_, c)) = self.sub_objects.iter_mut().find(|(id, _)| *id == object_id) {
+            *c = object_components.clone();
+        }
+        if painted {
+            self.painted.insert(object_id, true);
+        }
+        Ok(())
+    }
+
+    /// Adds a part to the object at `index`, placed and set up like its first part
+    ///
+    /// `part.mesh` is in the first part's space.
+    pub fn add_part(&mut self, index: usize, part: NewPart, subtype: &str) -> Result<()> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let first = self
+            .object_components(index)?
+            .first()
+            .with_context(|| format!("Object {} has no parts", index))?
+            .clone();
+        let path = first.path.clone().context("Component has no path")?;
+        let id = self.next_object_id();
+        let painted = part
+            .mesh
+            .triangles
+            .triangle
+            .iter()
+            .any(|t| t.paint().is_some());
+
+        let md_object = self
+            .md
+            .object
+            .iter_mut()
+            .find(|o| o.id == object_id)
+            .with_context(|| format!("Object {} not found in metadata", object_id))?;
+        let mut md = md_object
+            .part
+            .iter()
+            .find(|p| p.id == first.objectid)
+            .with_context(|| format!("Part {} not found in metadata", first.objectid))?
+            .clone();
+        md.id = id;
+        md.subtype = subtype.to_string();
+        md.mesh_stat = orca::MeshStat::default();
+        md.metadata
+            .retain(|m| !matches!(m.key.as_deref(), Some("name") | Some("extruder")));
+        md.metadata.insert(
+            0,
+            orca::Metadata {
+                key: Some("name".to_string()),
+                value: Some(part.name),
+            },
+        );
+        md.metadata.push(orca::Metadata {
+            key: Some("extruder".to_string()),
+            value: Some(part.extruder.to_string()),
+        });
+        md_object.part.push(md);
+
+        let sub_model = self
+            .sub_models
+            .get_mut(&path[1..])
+            .with_context(|| format!("Sub-model {} not found in sub-models", path))?;
+        let sub_first = sub_model
+            .model
+            .resources
+            .object
+            .iter()
+            .find(|o| o.id == first.objectid)
+            .with_context(|| format!("Object {} not found in sub-model {}", first.objectid, path))?
+            .clone();
+        sub_model.model.resources.object.push(Object {
+            id,
+            uuid: None,
+            object: crate::model::ObjectData::Mesh(part.mesh),
+            ..sub_first
+        });
+
+        let object_components = self.model.resources.object[index]
+            .object
+            .get_components_mut()
+            .context("Object is not a component")?;
+        object_components.push(Component {
+            objectid: id,
+            uuid: None,
+            ..first
+        });
+        if let Some((_, c)) = self.sub_objects.iter_mut().find(|(id, _)| *id == object_id) {
+            *c = object_components.clone();
+        }
+        if painted {
+            self.painted.insert(object_id, true);
+        }
+        Ok(())
+    }
+
+    /// Copies the object at `index` and its parts under new ids, returning the index of the
+    /// copy
+    ///
+    /// The copy has the same placement and settings, with `name`.
+    pub fn duplicate_object(&mut self, index: usize, name: &str) -> Result<usize> {
+        let object = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .clone();
+        let mut md_object = self
+            .md
+            .get_object_by_id(object.id)
+            .with_context(|| format!("Object {} not found in metadata", object.id))?
+            .clone();
+
+        let mut next = self.next_object_id();
+        let mut components = vec![];
+        for comp in self.object_components(index)?.clone() {
+            let path = comp.path.clone().context("Component has no path")?;
+            let sub_model = self
+                .sub_models
+                .get_mut(&path[1..])
+                .with_context(|| format!("Sub-model {} not found in sub-models", path))?;
+            let sub_object = sub_model
+                .model
+                .resources
+                .object
+                .iter()
+                .find(|o| o.id == comp.objectid)
+                .with_context(|| {
+                    format!("Object {} not found in sub-model {}", comp.objectid, path)
+                })?
+                .clone();
+            sub_model.model.resources.object.push(Object {
+                id: next,
+                uuid: None,
+                ..sub_object
+            });
+            // new ids are above every old one, so a renamed part is never found again
+            if let Some(part) = md_object.part.iter_mut().find(|p| p.id == comp.objectid) {
+                part.id = next;
+            }
+            components.push(Component {
+                objectid: next,
+                uuid: None,
+                ..comp
+            });
+            next += 1;
+        }
+
+        md_object.id = next;
+        self.md.object.push(md_object);
+
+        if let Some(item) = self.model.build.get_item_by_id(object.id).cloned() {
+            self.model.build.item.push(crate::model::Item {
+                objectid: next,
+                ..item
+            });
+        }
+        self.model.resources.object.push(Object {
+            id: next,
+            uuid: None,
+            object: crate::model::ObjectData::Components {
+                component: components.clone(),
+            },
+            ..object
+        });
+        self.sub_objects.push((next, components));
+        if let Some(&painted) = self.painted.get(&object.id) {
+            self.painted.insert(next, painted);
+        }
+        let copy = self.model.resources.object.len() - 1;
+        self.rename_object(copy, name)?;
+        Ok(copy)
+    }
+
+    /// Renames the object at `index` in `model_settings.config`
+    pub fn rename_object(&mut self, index: usize, name: &str) -> Result<()> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let md_object = self
+            .md
+            .object
+            .iter_mut()
+            .find(|o| o.id == object_id)
+            .with_context(|| format!("Object {} not found in metadata", object_id))?;
+        md_object
+            .metadata
+            .retain(|m| m.key.as_deref() != Some("name"));
+        md_object.metadata.insert(
+            0,
+            orca::Metadata {
+                key: Some("name".to_string()),
+                value: Some(name.to_string()),
+            },
+        );
+        Ok(())
+    }
+
     pub fn sub_models(&self) -> &HashMap<String, SubModel> {
         &self.sub_models
     }
@@ -0,0 +1,233 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::{
+    auto_paint::AutoPaintReport,
+    bvh::Bvh,
+    instancing::MATCH_TOLERANCE,
+    metadata::ps_metadata::{self as ps, PSMetadata},
+    model::Model,
+    model_orca::OrcaModel,
+    part_paint::{ps_extruder, ps_modifier, PartPaintOptions},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModifierPaintOptions {
+    /// mm, shortest edge sub-triangles are split to along the surface of a modifier
+    pub resolution: f64,
+    /// remove the modifiers that were painted from
+    pub remove: bool,
+}
+
+impl Default for ModifierPaintOptions {
+    fn default() -> Self {
+        Self {
+            resolution: 0.2,
+            remove: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModifierPaintReport {
+    /// modifiers with an extruder set
+    pub modifiers: usize,
+    pub removed: usize,
+    pub paint: AutoPaintReport,
+}
+
+impl std::fmt::Display for ModifierPaintReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} modifiers, {} removed, {}",
+            self.modifiers, self.removed, self.paint
+        )
+    }
+}
+
+impl ModifierPaintOptions {
+    fn part_options(&self) -> PartPaintOptions {
+        PartPaintOptions {
+            primary: None,
+            tolerance: MATCH_TOLERANCE,
+            resolution: self.resolution,
+        }
+    }
+}
+
+impl OrcaModel {
+    /// Paints the surface of the object at `index` inside each modifier that sets an extruder
+    /// with that extruder
+    ///
+    /// Where modifiers overlap, the last one wins as it does in the slicer.
+    pub fn paint_from_modifiers(
+        &mut self,
+        index: usize,
+        options: &ModifierPaintOptions,
+    ) -> Result<ModifierPaintReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let comps = self.object_components(index)?.clone();
+
+        let mut modifiers = vec![];
+        let mut surfaces = vec![];
+        for (c, comp) in comps.iter().enumerate() {
+            if self.part_subtype(object_id, comp.objectid).as_deref() != Some("modifier_part") {
+                continue;
+            }
+            let extruder = self
+                .md
+                .get_object_by_id(object_id)
+                .and_then(|o| o.part.iter().find(|p| p.id == comp.objectid))
+                .and_then(|p| {
+                    p.metadata
+                        .iter()
+                        .find(|m| m.key.as_deref() == Some("extruder"))
+                })
+                .and_then(|m| m.value.as_ref()?.parse::<u8>().ok())
+                .filter(|&e| e > 0);
+            let Some(extruder) = extruder else {
+                continue;
+            };
+            let transform = self.component_world_transform(index, comp)?;
+            surfaces.push((extruder, Bvh::new(self.component_mesh(comp)?, &transform)));
+            modifiers.push(c);
+        }
+        // the first surface covering a point wins
+        surfaces.reverse();
+
+        let mut out = ModifierPaintReport {
+            modifiers: modifiers.len(),
+            ..Default::default()
+        };
+        if modifiers.is_empty() {
+            return Ok(out);
+        }
+
+        for comp in comps.iter() {
+            let subtype = self.part_subtype(object_id, comp.objectid);
+            if subtype.is_some_and(|s| s != "normal_part") {
+                continue;
+            }
+            let transform = self.component_world_transform(index, comp)?;
+            out.paint += self.component_mesh_mut(comp)?.paint_from_parts(
+                &transform,
+                &surfaces,
+                &options.part_options(),
+                true,
+            )?;
+        }
+        if out.paint.changed > 0 {
+            self.painted.insert(object_id, true);
+        }
+
+        if options.remove {
+            for &c in modifiers.iter().rev() {
+                self.replace_part(index, c, vec![])?;
+                out.removed += 1;
+            }
+        }
+
+        debug!("painted object {} from modifiers: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Paints the surface of the object at `index` inside each modifier volume that sets an
+    /// extruder, as PrusaSlicer `mmu_segmentation`
+    ///
+    /// See [`OrcaModel::paint_from_modifiers`].
+    pub fn paint_from_modifiers(
+        &mut self,
+        index: usize,
+        md: &mut PSMetadata,
+        options: &ModifierPaintOptions,
+    ) -> Result<ModifierPaintReport> {
+        let object_id = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let transform = self.item_transform(index)?;
+        let md_object = md
+            .object
+            .iter_mut()
+            .find(|o| o.id == object_id)
+            .with_context(|| format!("No volumes for object {}", object_id))?;
+        let mesh = self.object_mesh_mut(index)?;
+        ensure!(
+            md_object
+                .volume
+                .iter()
+                .all(|v| v.firstid <= v.lastid && v.lastid < mesh.triangles.triangle.len()),
+            "Volume ends past the last triangle"
+        );
+
+        let mut modifiers = vec![];
+        let mut surfaces = vec![];
+        for (v, volume) in md_object.volume.iter().enumerate() {
+            let is_modifier = volume.metadata.iter().any(|m| match m.key.as_deref() {
+                Some("volume_type") => m.value.as_deref() == Some("ParameterModifier"),
+                Some("modifier") => m.value.as_deref() == Some("1"),
+                _ => false,
+            });
+            let Some(extruder) = ps_extruder(&volume.metadata).filter(|_| is_modifier) else {
+                continue;
+            };
+            let part = mesh.sub_mesh(volume.firstid..=volume.lastid);
+            surfaces.push((extruder, Bvh::new(&part, &transform)));
+            modifiers.push(v);
+        }
+        surfaces.reverse();
+
+        let mut out = ModifierPaintReport {
+            modifiers: modifiers.len(),
+            ..Default::default()
+        };
+        if modifiers.is_empty() {
+            return Ok(out);
+        }
+
+        for volume in md_object.volume.iter().filter(|v| !ps_modifier(v)) {
+            let mut part = mesh.sub_mesh(volume.firstid..=volume.lastid);
+            out.paint +=
+                part.paint_from_parts(&transform, &surfaces, &options.part_options(), false)?;
+            for (t, painted) in mesh.triangles.triangle[volume.firstid..=volume.lastid]
+                .iter_mut()
+                .zip(part.triangles.triangle)
+            {
+                t.mmu_ps = painted.mmu_ps;
+                t.mmu_orca = painted.mmu_orca;
+            }
+        }
+
+        if options.remove {
+            let old = mesh.clone();
+            mesh.vertices.vertex.clear();
+            mesh.triangles.triangle.clear();
+            let mut volumes = vec![];
+            for (v, volume) in md_object.volume.iter().enumerate() {
+                if modifiers.contains(&v) {
+                    out.removed += 1;
+                    continue;
+                }
+                let mut volume = volume.clone();
+                let part = old.sub_mesh(volume.firstid..=volume.lastid);
+                volume.firstid = mesh.triangles.triangle.len();
+                mesh.merge(&part);
+                volume.lastid = mesh.triangles.triangle.len() - 1;
+                volumes.push(volume);
+            }
+            md_object.volume = volumes;
+        }
+
+        debug!("painted object {} from modifiers: {}", index, out);
+        Ok(out)
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::{Matrix4, Vector3};
+
+use crate::{
+    metadata::orca_metadata as orca,
+    model::{Component, Item, Object, ObjectData},
+    model_orca::OrcaModel,
+    utils::{to_transform_3mf, transform_3mf},
+};
+
+/// Writes the extruder a part prints with into its own settings, so it no longer depends on
+/// the object it is in
+fn pin_extruder(part: &mut orca::Part, extruder: u8) {
+    part.metadata
+        .retain(|m| m.key.as_deref() != Some("extruder"));
+    part.metadata.push(orca::Metadata {
+        key: Some("extruder".to_string()),
+        value: Some(extruder.to_string()),
+    });
+}
+
+fn comp_transform(comp: &Component) -> Matrix4<f64> {
+    comp.transform
+        .as_ref()
+        .map(transform_3mf)
+        .unwrap_or_else(Matrix4::identity)
+}
+
+impl OrcaModel {
+    /// Combines the objects at `indices` into one object named `name`, with their parts as its
+    /// parts, returning its index
+    ///
+    /// The first object takes in the others, keeping its settings and build item. Parts keep
+    /// their place, paint and extruder, the other objects' own settings are dropped.
+    pub fn merge_objects(&mut self, indices: &[usize], name: &str) -> Result<usize> {
+        let mut order: Vec<usize> = vec![];
+        for &i in indices {
+            ensure!(i < self.get_objects().len(), "No object at index {}", i);
+            if !order.contains(&i) {
+                order.push(i);
+            }
+        }
+        ensure!(order.len() > 1, "Merging needs at least two objects");
+        let target = order[0];
+        let target_id = self.get_objects()[target].id;
+        let to_target = self
+            .item_transform(target)?
+            .try_inverse()
+            .context("Item transform can't be inverted")?;
+
+        ensure!(
+            self.get_objects()[target].object.get_components().is_some(),
+            "Object {} is not a component",
+            target
+        );
+        ensure!(
+            self.md.get_object_by_id(target_id).is_some(),
+            "Object {} not found in metadata",
+            target_id
+        );
+
+        // every part is found first, so a missing one leaves the project as it was
+        let mut moved = vec![];
+        let mut painted = false;
+        for &j in order[1..].iter() {
+            let object_id = self.get_objects()[j].id;
+            let item = self.item_transform(j)?;
+            let md_object = self
+                .md
+                .get_object_by_id(object_id)
+                .with_context(|| format!("Object {} not found in metadata", object_id))?;
+            for comp in self.object_components(j)?.iter() {
+                let mut part = md_object
+                    .part
+                    .iter()
+                    .find(|p| p.id == comp.objectid)
+                    .with_context(|| format!("Part {} not found in metadata", comp.objectid))?
+                    .clone();
+                pin_extruder(&mut part, self.part_extruder(object_id, comp.objectid));
+                let transform = to_target * item * comp_transform(comp);
+                moved.push((part, comp.clone(), transform));
+            }
+            painted |= self.painted.get(&object_id).copied().unwrap_or(false);
+        }
+
+        for (part, comp, transform) in moved {
+            self.md
+                .object
+                .iter_mut()
+                .find(|o| o.id == target_id)
+                .with_context(|| format!("Object {} not found in metadata", target_id))?
+                .part
+                .push(part);
+
+            let components = self.model.resources.object[target]
+                .object
+                .get_components_mut()
+                .context("Object is not a component")?;
+            components.push(comp);
+            let k = components.len() - 1;
+            self.set_component_transform(target, k, &transform)?;
+        }
+        if painted {
+            self.painted.insert(target_id, true);
+        }
+
+        let mut removed = order[1..].to_vec();
+        removed.sort_unstable();
+        for &j in removed.iter().rev() {
+            self.drop_object(j);
+        }
+        let index = target - removed.iter().filter(|&&j| j < target).count();
+        self.rename_object(index, name)?;
+
+        debug!("merged {} objects into object {}", order.len(), index);
+        Ok(index)
+    }
+
+    /// Makes each normal part of the object at `index` an object of its own, returning the
+    /// indices of the new objects
+    ///
+    /// The first normal part stays at `index` with the modifiers. The new objects are named
+    /// after their parts and take the object's settings, with their origin at the part's
+    /// center.
+    pub fn explode_object(&mut self, index: usize) -> Result<Vec<usize>> {
+        let object = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .clone();
+        let md_object = self
+            .md
+            .get_object_by_id(object.id)
+            .with_context(|| format!("Object {} not found in metadata", object.id))?
+            .clone();
+        let comps = self.object_components(index)?.clone();
+        let normal = (0..comps.len())
+            .filter(|&c| {
+                self.part_subtype(object.id, comps[c].objectid)
+                    .is_none_or(|s| s == "normal_part")
+            })
+            .collect::<Vec<_>>();
+        ensure!(
+            normal.len() > 1,
+            "Object {} has a single part to keep",
+            index
+        );
+        let item = self.model.build.get_item_by_id(object.id).cloned();
+        let item_transform = self.item_transform(index)?;
+
+        let mut out = vec![];
+        for &c in normal[1..].iter() {
+            let comp = comps[c].clone();
+            let mut part = md_object
+                .part
+                .iter()
+                .find(|p| p.id == comp.objectid)
+                .with_context(|| format!("Part {} not found in metadata", comp.objectid))?
+                .clone();
+            pin_extruder(&mut part, self.part_extruder(object.id, comp.objectid));
+            let part_name = part.get_name();
+
+            let mesh = self.component_mesh(&comp)?;
+            let painted = mesh.triangles.triangle.iter().any(|t| t.paint().is_some());
+            let center = mesh
+                .bounding_box()
+                .map(|(min, max)| (Vector3::from(min) + Vector3::from(max)) / 2.)
+                .unwrap_or_else(Vector3::zeros);
+            let transform = comp_transform(&comp);
+            // the object's origin moves to the part
+            let offset = (transform * center.push(1.)).xyz();
+            let new_transform = Matrix4::new_translation(&-offset) * transform;
+
+            let id = self.next_object_id();
+            let mut metadata = md_object.metadata.clone();
+            if let Some(part_name) = part_name {
+                metadata.retain(|m| m.key.as_deref() != Some("name"));
+                metadata.insert(
+                    0,
+                    orca::Metadata {
+                        key: Some("name".to_string()),
+                        value: Some(part_name),
+                    },
+                );
+            }
+            self.md.object.push(orca::Object {
+                id,
+                metadata,
+                part: vec![part],
+            });
+            let component = Component {
+                transform: Some(to_transform_3mf(&new_transform)),
+                ..comp
+            };
+            self.model.resources.object.push(Object {
+                id,
+                uuid: None,
+                object: ObjectData::Components {
+                    component: vec![component.clone()],
+                },
+                ..object.clone()
+            });
+            if let Some(item) = item.as_ref() {
+                self.model.build.item.push(Item {
+                    objectid: id,
+                    transform: Some(to_transform_3mf(
+                        &(item_transform * Matrix4::new_translation(&offset)),
+                    )),
+                    ..item.clone()
+                });
+            }
+            self.sub_objects.push((id, vec![component]));
+            self.painted.insert(id, painted);
+
+            let new_index = self.model.resources.object.len() - 1;
+            self.set_component_transform(new_index, 0, &new_transform)?;
+            out.push(new_index);
+        }
+
+        // the parts now belong to the new objects, their meshes stay where they are
+        let components = self.model.resources.object[index]
+            .object
+            .get_components_mut()
+            .context("Object is not a component")?;
+        for &c in normal[1..].iter().rev() {
+            components.remove(c);
+        }
+        let components = components.clone();
+        let ids = normal[1..]
+            .iter()
+            .map(|&c| comps[c].objectid)
+            .collect::<Vec<_>>();
+        if let Some(md_object) = self.md.object.iter_mut().find(|o| o.id == object.id) {
+            md_object.part.retain(|p| !ids.contains(&p.id));
+        }
+        if let Some((_, c)) = self.sub_objects.iter_mut().find(|(id, _)| *id == object.id) {
+            *c = components;
+        }
+
+        debug!("exploded object {} into {} more", index, out.len());
+        Ok(out)
+    }
+
+    /// Removes the object at `index` from the project, leaving its meshes in the sub-models
+    fn drop_object(&mut self, index: usize) {
+        let object = self.model.resources.object.remove(index);
+        self.model.build.item.retain(|i| i.objectid != object.id);
+        self.md.object.retain(|o| o.id != object.id);
+        self.sub_objects.retain(|(id, _)| *id != object.id);
+        self.painted.remove(&object.id);
+    }
+}
@@ -0,0 +1,414 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    instancing::{quantize, triangle_corners, MATCH_TOLERANCE},
+    mesh::{Mesh, Triangle, Triangles, Vertex, Vertices},
+    metadata::ps_metadata::{self as ps, PSMetadata},
+    model::Model,
+    model_orca::{NewPart, OrcaModel},
+    paint_tree::{interpolate, PaintTree},
+    splitting::Vec3,
+};
+
+/// How far junctions are looked for along an edge, in halvings
+const MAX_JUNCTION_DEPTH: usize = 32;
+
+/// How baked paint is written back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum BakeOutput {
+    /// Every triangle painted with a single state
+    #[default]
+    Paint,
+    /// One part or volume per state, printed with that extruder
+    Shells,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BakeReport {
+    pub triangles_before: usize,
+    pub triangles_after: usize,
+    /// leaves split again to meet a finer neighbor without a gap
+    pub junctions: usize,
+}
+
+impl std::ops::AddAssign for BakeReport {
+    fn add_assign(&mut self, other: Self) {
+        self.triangles_before += other.triangles_before;
+        self.triangles_after += other.triangles_after;
+        self.junctions += other.junctions;
+    }
+}
+
+impl std::fmt::Display for BakeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} triangles, {} split at junctions",
+            self.triangles_before, self.triangles_after, self.junctions
+        )
+    }
+}
+
+/// Vertices of the baked mesh, welded by position
+struct Welder {
+    vertices: Vec<Vec3>,
+    index: HashMap<[i64; 3], usize>,
+}
+
+impl Welder {
+    fn get_or_insert(&mut self, p: &Vec3) -> usize {
+        *self.index.entry(quantize(p)).or_insert_with(|| {
+            self.vertices.push(*p);
+            self.vertices.len() - 1
+        })
+    }
+
+    /// Vertices lying on the edge from `a` to `b`, in order
+    ///
+    /// Paint only ever splits edges in half, so a vertex on an edge is at its midpoint or at
+    /// a midpoint of one of its halves.
+    fn on_edge(&self, a: &Vec3, b: &Vec3, depth: usize, out: &mut Vec<usize>) {
+        if depth == 0 || (b - a).norm() < 2. * MATCH_TOLERANCE {
+            return;
+        }
+        let m = (a + b) / 2.;
+        if let Some(&i) = self.index.get(&quantize(&m)) {
+            self.on_edge(a, &m, depth - 1, out);
+            out.push(i);
+            self.on_edge(&m, b, depth - 1, out);
+        }
+    }
+}
+
+impl Mesh {
+    /// Replaces each painted triangle with the leaves of its paint, painted a single state each
+    ///
+    /// Leaves are split again where a neighbor is subdivided more finely, so a closed mesh
+    /// stays closed.
+    pub fn bake_paint(&mut self, orca: bool) -> Result<BakeReport> {
+        Ok(self.bake(orca)?.0)
+    }
+
+    /// Like [`Mesh::bake_paint`], also returning how many triangles each triangle became
+    pub(crate) fn bake(&mut self, orca: bool) -> Result<(BakeReport, Vec<usize>)> {
+        let mut welder = Welder {
+            vertices: self
+                .vertices
+                .vertex
+                .iter()
+                .map(|v| Vec3::new(v.x, v.y, v.z))
+                .collect(),
+            index: HashMap::new(),
+        };
+        for (i, v) in welder.vertices.iter().enumerate() {
+            welder.index.entry(quantize(v)).or_insert(i);
+        }
+
+        // leaf corners as vertex indices, wound like their triangle
+        let mut leaves = vec![];
+        for (i, t) in self.triangles.triangle.iter().enumerate() {
+            let tree = PaintTree::from_paint(t.paint())?;
+            if let PaintTree::Leaf(state) = tree {
+                leaves.push(vec![([t.v1, t.v2, t.v3], state)]);
+                continue;
+            }
+            let corners = triangle_corners(self, i);
+            let mut out = vec![];
+            for leaf in tree.leaves() {
+                let mut v = leaf
+                    .corners
+                    .map(|c| welder.get_or_insert(&interpolate(&corners, &c)));
+                let e1 = leaf.corners[1] - leaf.corners[0];
+                let e2 = leaf.corners[2] - leaf.corners[0];
+                if e1.x * e2.y - e1.y * e2.x < 0. {
+                    v.swap(1, 2);
+                }
+                if v[0] != v[1] && v[1] != v[2] && v[0] != v[2] {
+                    out.push((v, leaf.state));
+                }
+            }
+            leaves.push(out);
+        }
+
+        let mut report = BakeReport {
+            triangles_before: self.triangles.triangle.len(),
+            ..Default::default()
+        };
+        let mut triangles = vec![];
+        let mut counts = vec![];
+        for tri_leaves in leaves {
+            let first = triangles.len();
+            for (v, state) in tri_leaves {
+                let paint = PaintTree::Leaf(state).to_paint();
+                let mut push = |v: [usize; 3]| {
+                    let mut t = Triangle {
+                        v1: v[0],
+                        v2: v[1],
+                        v3: v[2],
+                        mmu_ps: None,
+                        mmu_orca: None,
+                    };
+                    t.set_paint(paint.clone(), orca);
+                    triangles.push(t);
+                };
+
+                let edges = (0..3)
+                    .map(|k| {
+                        let mut out = vec![];
+                        let (a, b) = (welder.vertices[v[k]], welder.vertices[v[(k + 1) % 3]]);
+                        welder.on_edge(&a, &b, MAX_JUNCTION_DEPTH, &mut out);
+                        out
+                    })
+                    .collect::<Vec<_>>();
+                let split = edges.iter().filter(|e| !e.is_empty()).count();
+                if split == 0 {
+                    push(v);
+                    continue;
+                }
+                report.junctions += 1;
+
+                if split == 1 {
+                    // fan from the corner facing the only split edge
+                    let k = edges.iter().position(|e| !e.is_empty()).unwrap();
+                    let apex = v[(k + 2) % 3];
+                    let mut side = vec![v[k]];
+                    side.extend(&edges[k]);
+                    side.push(v[(k + 1) % 3]);
+                    for w in side.windows(2) {
+                        push([apex, w[0], w[1]]);
+                    }
+                } else {
+                    // fan from the centroid, which sees every edge
+                    let centroid = v.iter().map(|&i| welder.vertices[i]).sum::<Vec3>() / 3.;
+                    welder.vertices.push(centroid);
+                    let center = welder.vertices.len() - 1;
+                    let mut ring = vec![];
+                    for k in 0..3 {
+                        ring.push(v[k]);
+                        ring.extend(&edges[k]);
+                    }
+                    for j in 0..ring.len() {
+                        push([center, ring[j], ring[(j + 1) % ring.len()]]);
+                    }
+                }
+            }
+            counts.push(triangles.len() - first);
+        }
+
+        report.triangles_after = triangles.len();
+        self.vertices.vertex = welder
+            .vertices
+            .iter()
+            .map(|v| Vertex {
+                x: v.x,
+                y: v.y,
+                z: v.z,
+            })
+            .collect();
+        self.triangles.triangle = triangles;
+
+        debug!("baked paint: {}", report);
+        Ok((report, counts))
+    }
+
+    /// Splits the mesh into one unpainted shell per state, each with only the vertices it uses
+    ///
+    /// Meant for baked meshes, triangles that are still subdivided go by their majority state.
+    pub fn paint_shells(&self) -> Result<Vec<(u8, Mesh)>> {
+        let mut shells: BTreeMap<u8, (Mesh, HashMap<usize, usize>)> = BTreeMap::new();
+        for t in self.triangles.triangle.iter() {
+            let state = PaintTree::from_paint(t.paint())?.majority();
+            let (shell, remap) = shells.entry(state).or_insert_with(|| {
+                (
+                    Mesh {
+                        vertices: Vertices { vertex: vec![] },
+                        triangles: Triangles { triangle: vec![] },
+                    },
+                    HashMap::new(),
+                )
+            });
+            let mut v = |i: usize| {
+                *remap.entry(i).or_insert_with(|| {
+                    shell.vertices.vertex.push(self.vertices.vertex[i]);
+                    shell.vertices.vertex.len() - 1
+                })
+            };
+            let (v1, v2, v3) = (v(t.v1), v(t.v2), v(t.v3));
+            shell.triangles.triangle.push(Triangle {
+                v1,
+                v2,
+                v3,
+                mmu_ps: None,
+                mmu_orca: None,
+            });
+        }
+        Ok(shells.into_iter().map(|(s, (m, _))| (s, m)).collect())
+    }
+}
+
+impl OrcaModel {
+    /// Bakes the paint of every part of the object at `index`
+    ///
+    /// With [`BakeOutput::Shells`] each part is replaced by one part per extruder.
+    pub fn bake_paint(&mut self, index: usize, output: BakeOutput) -> Result<BakeReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+
+        // every part is baked aside first, so a failure leaves the object as it was
+        let comps = self.object_components(index)?.clone();
+        let mut out = BakeReport::default();
+        let mut baked = vec![];
+        for comp in comps.iter() {
+            let mut mesh = self.component_mesh(comp)?.clone();
+            out += mesh.bake_paint(true)?;
+            if output == BakeOutput::Paint {
+                baked.push((mesh, vec![]));
+                continue;
+            }
+
+            let shells = mesh.paint_shells()?;
+            let name = self
+                .part_name(object_id, comp.objectid)
+                .unwrap_or_else(|| format!("part {}", comp.objectid));
+            let default = self.part_extruder(object_id, comp.objectid);
+            let parts = shells
+                .into_iter()
+                .map(|(state, mesh)| {
+                    let extruder = if state == 0 { default } else { state };
+                    NewPart {
+                        name: format!("{} filament {}", name, extruder),
+                        mesh,
+                        extruder,
+                    }
+                })
+                .collect::<Vec<_>>();
+            baked.push((mesh, parts));
+        }
+
+        // backwards, so replacing a part doesn't move the ones still to do
+        for (c, (mesh, parts)) in baked.into_iter().enumerate().rev() {
+            if output == BakeOutput::Paint {
+                *self.component_mesh_mut(&comps[c])? = mesh;
+            } else {
+                self.replace_part(index, c, parts)?;
+            }
+        }
+
+        debug!("baked paint of object {}: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Bakes the paint of the object at `index`, as PrusaSlicer `mmu_segmentation`
+    ///
+    /// The volume ranges in `md` are updated to the new triangles. With
+    /// [`BakeOutput::Shells`] each volume is split into one volume per extruder, which needs
+    /// `md`.
+    pub fn bake_paint(
+        &mut self,
+        index: usize,
+        md: Option<&mut PSMetadata>,
+        output: BakeOutput,
+    ) -> Result<BakeReport> {
+        let object_id = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let mut md_object = md.and_then(|md| md.object.iter_mut().find(|o| o.id == object_id));
+        ensure!(
+            md_object.is_some() || output == BakeOutput::Paint,
+            "Splitting into volumes needs the object's volumes from Slic3r_PE_model.config"
+        );
+
+        // the mesh and volumes are rebuilt aside and only written back once everything worked
+        let mut mesh = self.object_mesh_mut(index)?.clone();
+        let n = mesh.triangles.triangle.len();
+        let mut volumes = md_object
+            .as_ref()
+            .map(|o| o.volume.clone())
+            .unwrap_or_default();
+        ensure!(
+            volumes
+                .iter()
+                .all(|v| v.firstid <= v.lastid && v.lastid < n),
+            "Volume ends past the last triangle"
+        );
+
+        let (report, counts) = mesh.bake(false)?;
+
+        let mut starts = vec![0];
+        for c in counts.iter() {
+            starts.push(starts.last().unwrap() + c);
+        }
+        for v in volumes.iter_mut() {
+            v.firstid = starts[v.firstid];
+            v.lastid = starts[v.lastid + 1].saturating_sub(1);
+        }
+
+        if output == BakeOutput::Shells {
+            let baked = std::mem::take(&mut mesh.triangles.triangle);
+            let mut split = vec![];
+            for volume in volumes.iter() {
+                let mut by_state: BTreeMap<u8, Vec<Triangle>> = BTreeMap::new();
+                for t in baked[volume.firstid..=volume.lastid].iter() {
+                    let state = PaintTree::from_paint(t.paint())?.majority();
+                    let mut t = t.clone();
+                    t.set_paint(None, false);
+                    by_state.entry(state).or_default().push(t);
+                }
+
+                let name = volume
+                    .metadata
+                    .iter()
+                    .find(|m| m.key.as_deref() == Some("name"))
+                    .and_then(|m| m.value.clone())
+                    .unwrap_or_else(|| "volume".to_string());
+                let single = by_state.len() == 1;
+                for (state, tris) in by_state {
+                    let mut v = volume.clone();
+                    v.firstid = mesh.triangles.triangle.len();
+                    mesh.triangles.triangle.extend(tris);
+                    v.lastid = mesh.triangles.triangle.len() - 1;
+                    if state != 0 {
+                        v.metadata.retain(|m| {
+                            !matches!(m.key.as_deref(), Some("name") | Some("extruder"))
+                        });
+                        v.metadata.push(ps::Metadata {
+                            ty: "volume".to_string(),
+                            key: Some("name".to_string()),
+                            value: Some(if single {
+                                name.clone()
+                            } else {
+                                format!("{} filament {}", name, state)
+                            }),
+                        });
+                        v.metadata.push(ps::Metadata {
+                            ty: "volume".to_string(),
+                            key: Some("extruder".to_string()),
+                            value: Some(state.to_string()),
+                        });
+                    }
+                    split.push(v);
+                }
+            }
+            volumes = split;
+        }
+
+        *self.object_mesh_mut(index)? = mesh;
+        if let Some(md_object) = md_object.as_mut() {
+            md_object.volume = volumes;
+        }
+
+        debug!("baked paint of object {}: {}", index, report);
+        Ok(report)
+    }
+}
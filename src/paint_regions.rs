@@ -0,0 +1,894 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    instancing::{quantize, triangle_corners},
+    mesh::Mesh,
+    model_orca::OrcaModel,
+    paint_tree::{interpolate, root_corners, split_corners, PaintTree, Sample, Vec2},
+    splitting::Vec3,
+    utils::closest_point_on_triangle,
+};
+
+/// Tolerance in the root frame of a triangle, leaf corners are exact there
+const EPS: f64 = 1e-9;
+
+/// A leaf of the paint of one triangle, as a node of the region graph
+#[derive(Debug, Clone)]
+pub struct RegionLeaf {
+    pub triangle: usize,
+    /// corners in the root frame of the triangle
+    pub corners: [Vec2; 3],
+    pub state: u8,
+    pub area: f64,
+    /// sum of the edge lengths
+    pub perimeter: f64,
+}
+
+/// Leaves of the same state connected through shared edges
+#[derive(Debug, Clone)]
+pub struct PaintRegion {
+    pub state: u8,
+    pub leaves: Vec<usize>,
+    pub area: f64,
+    /// length of the border with other regions and open mesh edges
+    pub perimeter: f64,
+    /// neighboring regions and the length of the border shared with each
+    pub neighbors: Vec<(usize, f64)>,
+}
+
+impl PaintRegion {
+    /// Approximate width, `2 * area / perimeter`
+    ///
+    /// This is the width of a long strip, and the radius of a round spot.
+    pub fn width(&self) -> f64 {
+        if self.perimeter > 0. {
+            2. * self.area / self.perimeter
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    /// The neighbor sharing the longest border
+    pub fn main_neighbor(&self) -> Option<usize> {
+        self.neighbors
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(n, _)| *n)
+    }
+}
+
+/// Connected paint regions of a mesh, across triangles and the sub-triangles of their paint
+///
+/// Triangles are connected through edges with the same corners, so meshes with unshared
+/// vertices work too. Leaves touch when their edges overlap, which handles a big sub-triangle
+/// next to several smaller ones.
+#[derive(Debug, Clone)]
+pub struct PaintRegions {
+    pub trees: Vec<PaintTree>,
+    /// leaves of triangle `i` are `leaves[first_leaf[i]..first_leaf[i + 1]]`
+    pub first_leaf: Vec<usize>,
+    pub leaves: Vec<RegionLeaf>,
+    /// leaves sharing an edge, with the length of the shared segment
+    pub adjacency: Vec<Vec<(usize, f64)>>,
+    pub region_of: Vec<usize>,
+    pub regions: Vec<PaintRegion>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SegmentKey {
+    /// a mesh edge, by its quantized end points
+    Edge([i64; 3], [i64; 3]),
+    /// a line inside a triangle's root frame
+    Line(usize, [i64; 3]),
+}
+
+/// A leaf edge, as an interval along its [`SegmentKey`]
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    start: f64,
+    end: f64,
+    leaf: usize,
+    /// model length per unit of the interval
+    scale: f64,
+}
+
+impl PaintRegions {
+    pub fn new(mesh: &Mesh) -> Result<Self> {
+        let mut trees = vec![];
+        let mut first_leaf = vec![];
+        let mut leaves = vec![];
+        let mut segments: HashMap<SegmentKey, Vec<Segment>> = HashMap::new();
+
+        for (t, tri) in mesh.triangles.triangle.iter().enumerate() {
+            let tree = PaintTree::from_paint(tri.paint())
+                .with_context(|| format!("Invalid paint on triangle {}", t))?;
+            let corners = triangle_corners(mesh, t);
+            let keys = corners.map(|p| quantize(&p));
+
+            first_leaf.push(leaves.len());
+            for leaf in tree.leaves() {
+                let id = leaves.len();
+                let p = leaf.corners.map(|c| interpolate(&corners, &c));
+                let mut perimeter = 0.;
+
+                for i in 0..3 {
+                    let (a, b) = (leaf.corners[i], leaf.corners[(i + 1) % 3]);
+                    let length = (p[(i + 1) % 3] - p[i]).norm();
+                    perimeter += length;
+                    if let Some((key, segment)) = leaf_segment(t, &corners, &keys, a, b, length, id)
+                    {
+                        segments.entry(key).or_default().push(segment);
+                    }
+                }
+
+                leaves.push(RegionLeaf {
+                    triangle: t,
+                    corners: leaf.corners,
+                    state: leaf.state,
+                    area: (p[1] - p[0]).cross(&(p[2] - p[0])).norm() / 2.,
+                    perimeter,
+                });
+            }
+            trees.push(tree);
+        }
+        first_leaf.push(leaves.len());
+
+        let mut adjacency = vec![vec![]; leaves.len()];
+        for group in segments.values_mut() {
+            for (a, b, length) in overlapping(group) {
+                adjacency[a].push((b, length));
+                adjacency[b].push((a, length));
+            }
+        }
+
+        let mut out = Self {
+            trees,
+            first_leaf,
+            leaves,
+            adjacency,
+            region_of: vec![],
+            regions: vec![],
+        };
+        out.regroup();
+        Ok(out)
+    }
+
+    /// Rebuilds the regions after leaf states changed
+    pub fn regroup(&mut self) {
+        let n = self.leaves.len();
+        let mut parent = (0..n).collect::<Vec<_>>();
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+
+        for a in 0..n {
+            for &(b, _) in &self.adjacency[a] {
+                if self.leaves[a].state == self.leaves[b].state {
+                    let (ra, rb) = (find(&mut parent, a), find(&mut parent, b));
+                    if ra != rb {
+                        parent[ra.max(rb)] = ra.min(rb);
+                    }
+                }
+            }
+        }
+
+        let mut index = HashMap::new();
+        self.region_of = vec![0; n];
+        self.regions = vec![];
+        for i in 0..n {
+            let root = find(&mut parent, i);
+            let r = *index.entry(root).or_insert_with(|| {
+                self.regions.push(PaintRegion {
+                    state: self.leaves[i].state,
+                    leaves: vec![],
+                    area: 0.,
+                    perimeter: 0.,
+                    neighbors: vec![],
+                });
+                self.regions.len() - 1
+            });
+            self.region_of[i] = r;
+            let region = &mut self.regions[r];
+            region.leaves.push(i);
+            region.area += self.leaves[i].area;
+            region.perimeter += self.leaves[i].perimeter;
+        }
+
+        let mut neighbors = vec![HashMap::<usize, f64>::new(); self.regions.len()];
+        for a in 0..n {
+            let ra = self.region_of[a];
+            for &(b, length) in &self.adjacency[a] {
+                let rb = self.region_of[b];
+                // every shared edge is seen from both sides
+                if ra == rb {
+                    self.regions[ra].perimeter -= length;
+                } else {
+                    *neighbors[ra].entry(rb).or_default() += length;
+                }
+            }
+        }
+        for (region, n) in self.regions.iter_mut().zip(neighbors) {
+            region.neighbors = n.into_iter().collect();
+            region.neighbors.sort_by_key(|(r, _)| *r);
+        }
+    }
+
+    /// Gives every leaf of a region a new state, call [`PaintRegions::regroup`] afterwards
+    pub fn set_region_state(&mut self, region: usize, state: u8) {
+        for &l in &self.regions[region].leaves {
+            self.leaves[l].state = state;
+        }
+    }
+
+    /// Number of distinct triangles a region touches
+    pub fn region_triangles(&self, region: usize) -> usize {
+        self.regions[region]
+            .leaves
+            .iter()
+            .map(|&l| self.leaves[l].triangle)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Writes the leaf states back into the paint of the mesh, returns how many triangles changed
+    pub fn write_back(&self, mesh: &mut Mesh, orca: bool) -> usize {
+        let mut changed = 0;
+        for (t, tri) in mesh.triangles.triangle.iter_mut().enumerate() {
+            let states = &self.leaves[self.first_leaf[t]..self.first_leaf[t + 1]];
+            let mut tree = self.trees[t].clone();
+            let mut i = 0;
+            tree.for_each_leaf_mut(&mut |_, state| {
+                *state = states[i].state;
+                i += 1;
+            });
+            tree.simplify();
+
+            let paint = tree.to_paint();
+            if paint.as_deref() != tri.paint() {
+                tri.set_paint(paint, orca);
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+/// The key and interval of a leaf edge from `a` to `b`
+///
+/// Edges on the border of the triangle are keyed by the mesh edge, so they meet the leaves of
+/// the neighboring triangle, other edges by their line in the root frame.
+fn leaf_segment(
+    triangle: usize,
+    corners: &[Vec3; 3],
+    keys: &[[i64; 3]; 3],
+    a: Vec2,
+    b: Vec2,
+    length: f64,
+    leaf: usize,
+) -> Option<(SegmentKey, Segment)> {
+    let d = b - a;
+    let norm = d.norm();
+    if norm <= EPS {
+        return None;
+    }
+
+    // parameter along each root edge, from corner `k` to corner `k + 1`
+    let on_edge = [
+        (a.y.abs() <= EPS && b.y.abs() <= EPS).then(|| (a.x, b.x)),
+        ((a.x + a.y - 1.).abs() <= EPS && (b.x + b.y - 1.).abs() <= EPS).then(|| (a.y, b.y)),
+        (a.x.abs() <= EPS && b.x.abs() <= EPS).then(|| (1. - a.y, 1. - b.y)),
+    ];
+    if let Some((k, (mut ta, mut tb))) = on_edge
+        .iter()
+        .enumerate()
+        .find_map(|(k, t)| t.map(|t| (k, t)))
+    {
+        let (mut p, mut q) = (keys[k], keys[(k + 1) % 3]);
+        if p == q {
+            return None;
+        }
+        if p > q {
+            std::mem::swap(&mut p, &mut q);
+            (ta, tb) = (1. - ta, 1. - tb);
+        }
+        let scale = (corners[(k + 1) % 3] - corners[k]).norm();
+        return Some((
+            SegmentKey::Edge(p, q),
+            Segment {
+                start: ta.min(tb),
+                end: ta.max(tb),
+                leaf,
+                scale,
+            },
+        ));
+    }
+
+    let mut dir = d / norm;
+    if dir.x < -EPS || (dir.x.abs() <= EPS && dir.y < 0.) {
+        dir = -dir;
+    }
+    let offset = a.x * dir.y - a.y * dir.x;
+    let key = [dir.x, dir.y, offset].map(|v| (v / EPS).round() as i64);
+    let (sa, sb) = (a.dot(&dir), b.dot(&dir));
+    Some((
+        SegmentKey::Line(triangle, key),
+        Segment {
+            start: sa.min(sb),
+            end: sa.max(sb),
+            leaf,
+            scale: length / norm,
+        },
+    ))
+}
+
+/// Pairs of leaves whose segments overlap, with the model length of the overlap
+fn overlapping(segments: &mut [Segment]) -> Vec<(usize, usize, f64)> {
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+    let mut out = vec![];
+    let mut active: Vec<usize> = vec![];
+    for (i, s) in segments.iter().enumerate() {
+        active.retain(|&j| segments[j].end > s.start + EPS);
+        for &j in &active {
+            let other = &segments[j];
+            let overlap = other.end.min(s.end) - s.start;
+            if overlap > EPS && other.leaf != s.leaf {
+                out.push((other.leaf, s.leaf, overlap * (other.scale + s.scale) / 2.));
+            }
+        }
+        active.push(i);
+    }
+    out
+}
+
+/// Which regions [`Mesh::remove_paint_islands`] treats as islands
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct IslandOptions {
+    /// regions smaller than this, in mm²
+    pub min_area: Option<f64>,
+    /// regions narrower than this, in mm, see [`PaintRegion::width`]
+    pub nozzle: Option<f64>,
+}
+
+impl Default for IslandOptions {
+    fn default() -> Self {
+        Self {
+            min_area: Some(1.),
+            nozzle: Some(0.4),
+        }
+    }
+}
+
+impl IslandOptions {
+    pub fn is_island(&self, region: &PaintRegion) -> bool {
+        // a region with nothing around it has no color to merge into
+        !region.neighbors.is_empty()
+            && (self.min_area.is_some_and(|a| region.area < a)
+                || self.nozzle.is_some_and(|w| region.width() < w))
+    }
+}
+
+/// A paint region found by [`Mesh::remove_paint_islands`]
+#[derive(Debug, Clone)]
+pub struct PaintIsland {
+    pub state: u8,
+    pub area: f64,
+    pub width: f64,
+    pub triangles: usize,
+    /// the surrounding state it was (or would be) merged into
+    pub merged_into: Option<u8>,
+}
+
+impl std::fmt::Display for PaintIsland {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {:.3} mm², ~{:.2} mm wide, {} triangle(s)",
+            state_name(self.state),
+            self.area,
+            self.width,
+            self.triangles
+        )?;
+        if let Some(s) = self.merged_into {
+            write!(f, " -> {}", state_name(s))?;
+        }
+        Ok(())
+    }
+}
+
+/// Paint state as shown in the slicer
+pub fn state_name(state: u8) -> String {
+    match state {
+        0 => "default filament".to_string(),
+        s => format!("filament {}", s),
+    }
+}
+
+/// Merges islands into the surrounding color, returns the islands found at the start
+///
+/// An island takes the state of the neighbor it shares the longest border with, preferring
+/// neighbors that aren't islands themselves, so a speck inside another speck ends up in the
+/// color around both.
+pub fn merge_islands(regions: &mut PaintRegions, options: &IslandOptions) -> Vec<PaintIsland> {
+    let found = (0..regions.regions.len())
+        .filter(|&r| options.is_island(&regions.regions[r]))
+        .collect::<Vec<_>>();
+
+    let mut in_island = vec![false; regions.leaves.len()];
+    let mut out = vec![];
+    for &r in &found {
+        let region = &regions.regions[r];
+        for &l in &region.leaves {
+            in_island[l] = true;
+        }
+        out.push((
+            region.leaves[0],
+            PaintIsland {
+                state: region.state,
+                area: region.area,
+                width: region.width(),
+                triangles: regions.region_triangles(r),
+                merged_into: None,
+            },
+        ));
+    }
+
+    // every merge joins two regions, so this ends
+    loop {
+        let islands = (0..regions.regions.len())
+            .filter(|&r| {
+                let region = &regions.regions[r];
+                options.is_island(region) && region.leaves.iter().all(|&l| in_island[l])
+            })
+            .collect::<HashSet<_>>();
+        if islands.is_empty() {
+            break;
+        }
+
+        let mut targets = vec![];
+        for &r in &islands {
+            let region = &regions.regions[r];
+            let target = region
+                .neighbors
+                .iter()
+                .filter(|(n, _)| !islands.contains(n))
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            if let Some((n, _)) = target {
+                targets.push((r, regions.regions[*n].state));
+            }
+        }
+        if targets.is_empty() {
+            // only islands around, merge the smallest one so states can't swap back and forth
+            let r = *islands
+                .iter()
+                .min_by(|a, b| {
+                    regions.regions[**a]
+                        .area
+                        .total_cmp(&regions.regions[**b].area)
+                })
+                .unwrap();
+            let n = regions.regions[r].main_neighbor().unwrap();
+            targets.push((r, regions.regions[n].state));
+        }
+
+        for (r, state) in targets {
+            regions.set_region_state(r, state);
+        }
+        regions.regroup();
+    }
+
+    out.into_iter()
+        .map(|(leaf, mut island)| {
+            let state = regions.leaves[leaf].state;
+            island.merged_into = (state != island.state).then_some(state);
+            island
+        })
+        .collect()
+}
+
+impl Mesh {
+    /// Finds small or thin paint regions and merges them into the surrounding color
+    ///
+    /// With `dry_run` the mesh is left as is, and the islands are reported with the state they
+    /// would get. Sizes are in the mesh's own units.
+    pub fn remove_paint_islands(
+        &mut self,
+        options: &IslandOptions,
+        dry_run: bool,
+        orca: bool,
+    ) -> Result<Vec<PaintIsland>> {
+        let mut regions = PaintRegions::new(self)?;
+        let islands = merge_islands(&mut regions, options);
+        if !dry_run && !islands.is_empty() {
+            let changed = regions.write_back(self, orca);
+            debug!(
+                "merged {} islands, {} triangles changed",
+                islands.len(),
+                changed
+            );
+        }
+        Ok(islands)
+    }
+}
+
+impl OrcaModel {
+    /// Removes paint islands from the object at `index`, see [`Mesh::remove_paint_islands`]
+    ///
+    /// Parts are measured as placed on the build plate, and paint regions continue across
+    /// touching parts.
+    pub fn remove_paint_islands(
+        &mut self,
+        index: usize,
+        options: &IslandOptions,
+        dry_run: bool,
+    ) -> Result<Vec<PaintIsland>> {
+        let tris = self.world_triangles(index)?;
+        let mut soup = tris.soup_mesh();
+        let islands = soup.remove_paint_islands(options, dry_run, true)?;
+        if !dry_run {
+            self.write_soup_paint(index, &tris, soup)?;
+        }
+        Ok(islands)
+    }
+}
+
+/// Faces turned further apart than this (the cosine of 120°) don't pass paint to each other,
+/// so it doesn't reach through thin walls
+const MIN_NORMAL_DOT: f64 = -0.5;
+
+/// Deepest subdivision added when refining paint along a new border
+const MAX_REFINE_DEPTH: usize = 10;
+
+/// Subdivisions needed for the edges of a triangle to get below `resolution`
+pub(crate) fn refine_depth(p: &[Vec3; 3], resolution: f64) -> usize {
+    let longest = (0..3)
+        .map(|i| (p[(i + 1) % 3] - p[i]).norm())
+        .fold(0., f64::max);
+    let depth = (longest / resolution).log2().ceil();
+    if depth.is_finite() && depth > 0. {
+        (depth as usize).min(MAX_REFINE_DEPTH)
+    } else {
+        0
+    }
+}
+
+/// Whether [`Mesh::morph_paint`] grows or shrinks the painted state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum MorphOp {
+    /// paints everything within the distance of the state
+    #[default]
+    Dilate,
+    /// gives the state's border band to the neighboring states
+    Erode,
+}
+
+/// Settings for [`Mesh::morph_paint`]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MorphOptions {
+    pub op: MorphOp,
+    pub state: u8,
+    /// in mm
+    pub distance: f64,
+    /// sub-triangles crossing the new border are split until their edges are shorter than
+    /// this, in mm
+    pub resolution: f64,
+}
+
+impl Default for MorphOptions {
+    fn default() -> Self {
+        Self {
+            op: MorphOp::Dilate,
+            state: 1,
+            distance: 0.4,
+            resolution: 0.1,
+        }
+    }
+}
+
+/// What [`Mesh::morph_paint`] changed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MorphReport {
+    pub state: u8,
+    pub triangles: usize,
+    pub changed: usize,
+    /// area covered by the state, in mm²
+    pub area_before: f64,
+    pub area_after: f64,
+}
+
+impl std::ops::AddAssign for MorphReport {
+    fn add_assign(&mut self, other: Self) {
+        self.triangles += other.triangles;
+        self.changed += other.changed;
+        self.area_before += other.area_before;
+        self.area_after += other.area_after;
+    }
+}
+
+impl std::fmt::Display for MorphReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {:.2} -> {:.2} mm², {} of {} triangles changed",
+            state_name(self.state),
+            self.area_before,
+            self.area_after,
+            self.changed,
+            self.triangles
+        )
+    }
+}
+
+/// A leaf on the border of the morphed state, paint spreads from these
+struct BorderLeaf {
+    corners: [Vec3; 3],
+    normal: Vec3,
+    state: u8,
+}
+
+impl rstar::RTreeObject for BorderLeaf {
+    type Envelope = rstar::AABB<[f64; 3]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        let points = self.corners.map(|p| [p.x, p.y, p.z]);
+        rstar::AABB::from_points(points.iter())
+    }
+}
+
+impl rstar::PointDistance for BorderLeaf {
+    fn distance_2(&self, point: &[f64; 3]) -> f64 {
+        let p = Vec3::new(point[0], point[1], point[2]);
+        (closest_point_on_triangle(&p, &self.corners) - p).norm_squared()
+    }
+}
+
+/// How a sub-triangle relates to the band within the distance of the border
+enum Band {
+    Outside,
+    Inside(u8),
+    /// crosses the edge of the band, with the new state at its centroid
+    Mixed(u8),
+}
+
+struct Morph<'a> {
+    options: &'a MorphOptions,
+    border: &'a rstar::RTree<BorderLeaf>,
+}
+
+impl Morph<'_> {
+    /// Whether leaves of this state can change
+    fn affects(&self, state: u8) -> bool {
+        match self.options.op {
+            MorphOp::Dilate => state != self.options.state,
+            MorphOp::Erode => state == self.options.state,
+        }
+    }
+
+    /// Distance to and state of the closest border leaf facing the same way, up to `max`
+    fn nearest(&self, p: &Vec3, normal: &Vec3, max: f64) -> Option<(f64, u8)> {
+        for (leaf, d2) in self
+            .border
+            .nearest_neighbor_iter_with_distance_2(&[p.x, p.y, p.z])
+        {
+            let d = d2.sqrt();
+            if d > max {
+                return None;
+            }
+            if leaf.normal.dot(normal) >= MIN_NORMAL_DOT {
+                return Some((d, leaf.state));
+            }
+        }
+        None
+    }
+
+    fn classify(&self, p: &[Vec3; 3], normal: &Vec3, state: u8) -> Band {
+        let distance = self.options.distance;
+        let c = (p[0] + p[1] + p[2]) / 3.;
+        let r = p.iter().map(|q| (q - c).norm()).fold(0., f64::max);
+
+        let Some((d, nearest)) = self.nearest(&c, normal, distance + r) else {
+            return Band::Outside;
+        };
+        let new = match self.options.op {
+            MorphOp::Dilate => self.options.state,
+            MorphOp::Erode => nearest,
+        };
+
+        let inside = d + r <= distance
+            && (self.options.op == MorphOp::Dilate
+                || p.iter().all(|q| {
+                    self.nearest(q, normal, distance)
+                        .is_some_and(|(_, s)| s == nearest)
+                }));
+        if inside {
+            Band::Inside(new)
+        } else if d <= distance {
+            Band::Mixed(new)
+        } else {
+            Band::Mixed(state)
+        }
+    }
+
+    /// The paint of `node` after morphing, keeping subtrees away from the border as they are
+    fn morph_node(
+        &self,
+        node: &PaintTree,
+        corners: [Vec2; 3],
+        triangle: &[Vec3; 3],
+        normal: &Vec3,
+    ) -> PaintTree {
+        let p = corners.map(|c| interpolate(triangle, &c));
+        match node {
+            PaintTree::Leaf(state) => {
+                if !self.affects(*state) {
+                    return node.clone();
+                }
+                match self.classify(&p, normal, *state) {
+                    Band::Outside => node.clone(),
+                    Band::Inside(new) => PaintTree::Leaf(new),
+                    Band::Mixed(_) => {
+                        PaintTree::build(refine_depth(&p, self.options.resolution), &mut |sub| {
+                            let q = sub.map(|c| interpolate(&p, &c));
+                            match self.classify(&q, normal, *state) {
+                                Band::Outside => Sample::Uniform(*state),
+                                Band::Inside(new) => Sample::Uniform(new),
+                                Band::Mixed(new) => Sample::Mixed(new),
+                            }
+                        })
+                    }
+                }
+            }
+            PaintTree::Split {
+                sides,
+                special_side,
+                children,
+            } => {
+                match self.classify(&p, normal, 0) {
+                    Band::Outside => return node.clone(),
+                    Band::Inside(new) if self.options.op == MorphOp::Dilate => {
+                        return PaintTree::Leaf(new)
+                    }
+                    _ => {}
+                }
+                PaintTree::Split {
+                    sides: *sides,
+                    special_side: *special_side,
+                    children: children
+                        .iter()
+                        .zip(split_corners(&corners, *sides, *special_side))
+                        .map(|(child, c)| self.morph_node(child, c, triangle, normal))
+                        .collect(),
+                }
+            }
+        }
+    }
+}
+
+impl Mesh {
+    /// Grows or shrinks the paint of one state by a distance, see [`MorphOptions`]
+    ///
+    /// Distances are straight-line distances to the border of the state, faces pointing
+    /// away from the border are skipped so paint doesn't show up on the other side of thin
+    /// walls. Sub-triangles crossing the new border are refined, paint further away is kept
+    /// as it is.
+    pub fn morph_paint(&mut self, options: &MorphOptions, orca: bool) -> Result<MorphReport> {
+        ensure!(options.distance >= 0., "Distance must not be negative");
+        ensure!(options.resolution > 0., "Resolution must be positive");
+
+        let regions = PaintRegions::new(self)?;
+        let corners = (0..self.triangles.triangle.len())
+            .map(|i| triangle_corners(self, i))
+            .collect::<Vec<_>>();
+        let normals = corners
+            .iter()
+            .map(|c| {
+                (c[1] - c[0])
+                    .cross(&(c[2] - c[0]))
+                    .try_normalize(f64::EPSILON)
+                    .unwrap_or_else(Vec3::zeros)
+            })
+            .collect::<Vec<_>>();
+
+        let border = regions
+            .leaves
+            .iter()
+            .zip(&regions.adjacency)
+            .filter(|(leaf, adjacent)| {
+                let is_state = leaf.state == options.state;
+                let source = match options.op {
+                    MorphOp::Dilate => is_state,
+                    MorphOp::Erode => !is_state,
+                };
+                source
+                    && adjacent
+                        .iter()
+                        .any(|(b, _)| (regions.leaves[*b].state == options.state) != is_state)
+            })
+            .map(|(leaf, _)| BorderLeaf {
+                corners: leaf
+                    .corners
+                    .map(|c| interpolate(&corners[leaf.triangle], &c)),
+                normal: normals[leaf.triangle],
+                state: leaf.state,
+            })
+            .collect::<Vec<_>>();
+        debug!("morphing paint from {} border leaves", border.len());
+
+        let border = rstar::RTree::bulk_load(border);
+        let morph = Morph {
+            options,
+            border: &border,
+        };
+
+        let reports = self
+            .triangles
+            .triangle
+            .par_iter_mut()
+            .zip(corners.par_iter())
+            .zip(normals.par_iter())
+            .zip(regions.trees.par_iter())
+            .map(|(((t, corners), normal), tree)| {
+                let area = (corners[1] - corners[0])
+                    .cross(&(corners[2] - corners[0]))
+                    .norm()
+                    / 2.;
+                let state_area = |tree: &PaintTree| {
+                    let areas = tree.state_areas();
+                    area * areas.get(options.state as usize).copied().unwrap_or(0.)
+                };
+
+                let mut new = if border.size() == 0 {
+                    tree.clone()
+                } else {
+                    morph.morph_node(tree, root_corners(), corners, normal)
+                };
+                new.simplify();
+
+                let paint = new.to_paint();
+                let changed = paint.as_deref() != t.paint();
+                if changed {
+                    t.set_paint(paint, orca);
+                }
+                MorphReport {
+                    state: options.state,
+                    triangles: 1,
+                    changed: changed as usize,
+                    area_before: state_area(tree),
+                    area_after: state_area(&new),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut out = MorphReport {
+            state: options.state,
+            ..Default::default()
+        };
+        for r in reports {
+            out += r;
+        }
+        Ok(out)
+    }
+}
+
+impl OrcaModel {
+    /// Grows or shrinks paint on the object at `index`, see [`Mesh::morph_paint`]
+    ///
+    /// Distances are measured as placed on the build plate.
+    pub fn morph_paint(&mut self, index: usize, options: &MorphOptions) -> Result<MorphReport> {
+        let tris = self.world_triangles(index)?;
+        let mut soup = tris.soup_mesh();
+        let report = soup.morph_paint(options, true)?;
+        self.write_soup_paint(index, &tris, soup)?;
+
+        debug!("morphed paint of object {}: {}", index, report);
+        Ok(report)
+    }
+}
@@ -1,10 +1,422 @@
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use tracing::{debug, error, info, trace, warn};
 
-use crate::{metadata::ps_metadata::PSMetadata, model::Model};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::{
+    instancing::{
+        match_triangles, matched_paint, pair_parts, triangle_corners, ComponentPairing,
+        MeshFingerprint, PaintMatchReport, PartInfo,
+    },
+    mesh::{Mesh, Vertex},
+    metadata::ps_metadata::{self as ps, PSMetadata},
+    model::Model,
+    model_orca::OrcaModel,
+    splitting::Vec3,
+};
 
 // pub fn save_3mf_no_verts(path: &str, model: &Model, metadata: &PSMetadata) -> Result<Vec<>> {
 //     Ok(())
 // }
 
-// pub fn apply_paint
+/// Bumped whenever the sidecar layout changes
+pub const PAINT_SIDECAR_VERSION: u32 = 1;
+
+/// Paint of one object, saved next to a project so it can be applied to the same model in
+/// another Orca or PrusaSlicer project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaintSidecar {
+    pub version: u32,
+    pub object_name: Option<String>,
+    pub parts: Vec<PaintSidecarPart>,
+}
+
+/// One part of the object, in part-local coordinates centered on its bounding box
+///
+/// Only painted triangles are kept. The fingerprint covers the rest of the mesh, so the paint
+/// is only applied to a part with the same vertex and triangle counts and the same shape.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaintSidecarPart {
+    pub name: Option<String>,
+    pub fingerprint: MeshFingerprint,
+    pub triangles: Vec<PaintedTriangle>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaintedTriangle {
+    pub corners: [[f64; 3]; 3],
+    pub paint: String,
+}
+
+impl PaintSidecarPart {
+    pub fn from_mesh(name: Option<String>, mesh: &Mesh) -> Self {
+        let mesh = centered(mesh);
+        let triangles = mesh
+            .triangles
+            .triangle
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| {
+                let paint = t.paint()?.to_string();
+                let corners = triangle_corners(&mesh, i).map(|c| [c.x, c.y, c.z]);
+                Some(PaintedTriangle { corners, paint })
+            })
+            .collect();
+
+        Self {
+            name,
+            fingerprint: mesh.fingerprint(),
+            triangles,
+        }
+    }
+}
+
+impl PaintSidecar {
+    /// Exports the paint of every part of an Orca object
+    pub fn from_orca(model: &OrcaModel, index: usize) -> Result<Self> {
+        let object = model
+            .get_objects()
+            .get(index)
+            .ok_or_else(|| anyhow!("Object index {} out of range", index))?;
+        let object_id = object.id;
+
+        let parts = model
+            .object_components(index)?
+            .iter()
+            .map(|c| {
+                let mesh = model.component_mesh(c)?;
+                Ok(PaintSidecarPart::from_mesh(
+                    model.part_name(object_id, c.objectid),
+                    mesh,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let object_name = model
+            .md
+            .get_object_by_id(object_id)
+            .and_then(|o| o.get_name())
+            .or_else(|| object.name.clone());
+
+        Ok(Self {
+            version: PAINT_SIDECAR_VERSION,
+            object_name,
+            parts,
+        })
+    }
+
+    /// Exports the paint of every volume of a PrusaSlicer object
+    pub fn from_ps(model: &Model, md: Option<&PSMetadata>, index: usize) -> Result<Self> {
+        let object = model
+            .resources
+            .object
+            .get(index)
+            .ok_or_else(|| anyhow!("Object index {} out of range", index))?;
+        let mesh = object
+            .object
+            .get_mesh()
+            .ok_or_else(|| anyhow!("Object {} has no mesh", index))?;
+        let md_object = md.and_then(|md| md.get_object_by_id(object.id));
+
+        let parts = ps_volumes(mesh, md_object)?
+            .into_iter()
+            .map(|v| PaintSidecarPart::from_mesh(v.name, &v.mesh))
+            .collect();
+
+        Ok(Self {
+            version: PAINT_SIDECAR_VERSION,
+            object_name: md_object
+                .and_then(|o| o.get_name())
+                .or_else(|| object.name.clone()),
+            parts,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let file = std::fs::File::create(path.as_ref())
+            .with_context(|| format!("Creating {:?}", path.as_ref()))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(path.as_ref())
+            .with_context(|| format!("Opening {:?}", path.as_ref()))?;
+        let sidecar: Self = serde_json::from_reader(std::io::BufReader::new(file))?;
+
+        ensure!(
+            sidecar.version == PAINT_SIDECAR_VERSION,
+            "Unsupported paint file version {}, expected {}",
+            sidecar.version,
+            PAINT_SIDECAR_VERSION
+        );
+        Ok(sidecar)
+    }
+
+    /// Pairs the saved parts with the parts of a target object, see [`pair_parts`]
+    pub fn pair(&self, to_parts: &[PartInfo]) -> ComponentPairing {
+        let from_parts = self
+            .parts
+            .iter()
+            .map(|p| (p.fingerprint, p.name.clone()))
+            .collect::<Vec<_>>();
+        pair_parts(&from_parts, to_parts)
+    }
+
+    /// Applies the paint to the matching parts of an Orca object
+    pub fn apply_orca(&self, model: &mut OrcaModel, index: usize) -> Result<Vec<PaintMatchReport>> {
+        let object_id = model
+            .get_objects()
+            .get(index)
+            .ok_or_else(|| anyhow!("Object index {} out of range", index))?
+            .id;
+        let comps = model.object_components(index)?.clone();
+
+        let to_parts = comps
+            .iter()
+            .map(|c| {
+                let mesh = centered(model.component_mesh(c)?);
+                Ok((mesh.fingerprint(), model.part_name(object_id, c.objectid)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let pairing = self.checked_pairing(&to_parts, index)?;
+
+        let mut paints = vec![];
+        let mut reports = vec![];
+        for pair in pairing.pairs.iter() {
+            debug!("Applying paint: {}", pair);
+
+            let (paint, report) = self
+                .part_paint(pair.from, model.component_mesh(&comps[pair.to])?)
+                .with_context(|| format!("Applying paint for {}", pair))?;
+            paints.push((pair.to, paint));
+            reports.push(report);
+        }
+
+        for (to, paint) in paints {
+            let mesh = model.component_mesh_mut(&comps[to])?;
+            for (t, p) in mesh.triangles.triangle.iter_mut().zip(paint) {
+                t.set_paint(p, true);
+            }
+        }
+
+        Ok(reports)
+    }
+
+    /// Applies the paint to the matching volumes of a PrusaSlicer object
+    pub fn apply_ps(
+        &self,
+        model: &mut Model,
+        md: Option<&PSMetadata>,
+        index: usize,
+    ) -> Result<Vec<PaintMatchReport>> {
+        let object = model
+            .resources
+            .object
+            .get(index)
+            .ok_or_else(|| anyhow!("Object index {} out of range", index))?;
+        let md_object = md.and_then(|md| md.get_object_by_id(object.id));
+        let mesh = object
+            .object
+            .get_mesh()
+            .ok_or_else(|| anyhow!("Object {} has no mesh", index))?;
+
+        let volumes = ps_volumes(mesh, md_object)?;
+        let to_parts = volumes
+            .iter()
+            .map(|v| (centered(&v.mesh).fingerprint(), v.name.clone()))
+            .collect::<Vec<_>>();
+
+        let pairing = self.checked_pairing(&to_parts, index)?;
+
+        let mut paints = vec![];
+        let mut reports = vec![];
+        for pair in pairing.pairs.iter() {
+            debug!("Applying paint: {}", pair);
+
+            let volume = &volumes[pair.to];
+            let (paint, report) = self
+                .part_paint(pair.from, &volume.mesh)
+                .with_context(|| format!("Applying paint for {}", pair))?;
+            paints.push((volume.triangles.clone(), paint));
+            reports.push(report);
+        }
+
+        // volume triangles keep their order, so the paint is written back by offset
+        let mesh = model.object_mesh_mut(index)?;
+        for (triangles, paint) in paints {
+            for (t, p) in mesh.triangles.triangle[triangles].iter_mut().zip(paint) {
+                t.set_paint(p, false);
+            }
+        }
+
+        Ok(reports)
+    }
+
+    fn checked_pairing(&self, to_parts: &[PartInfo], index: usize) -> Result<ComponentPairing> {
+        let pairing = self.pair(to_parts);
+
+        if pairing.pairs.is_empty() {
+            bail!(
+                "No part of object {} matches the paint file ({})",
+                index,
+                self.object_name.as_deref().unwrap_or("unnamed")
+            );
+        }
+        for (i, name) in pairing.unmatched_from.iter() {
+            warn!(
+                "No target part for saved part {} ({})",
+                i,
+                name.as_deref().unwrap_or("unnamed")
+            );
+        }
+        for (i, name) in pairing.unmatched_to.iter() {
+            warn!(
+                "No saved paint for part {} ({}) of object {}",
+                i,
+                name.as_deref().unwrap_or("unnamed"),
+                index
+            );
+        }
+
+        Ok(pairing)
+    }
+
+    /// The paint one saved part gives each triangle of `to`, matching in the same centered
+    /// frame
+    ///
+    /// Like [`crate::instancing::copy_paint_mesh`], this fails unless every saved triangle finds
+    /// a partner. Triangles without saved paint get `None`.
+    fn part_paint(
+        &self,
+        part: usize,
+        to: &Mesh,
+    ) -> Result<(Vec<Option<String>>, PaintMatchReport)> {
+        let part = &self.parts[part];
+        ensure!(
+            part.fingerprint == centered(to).fingerprint(),
+            "Part {} doesn't match the saved shape",
+            part.name.as_deref().unwrap_or("unnamed")
+        );
+
+        let from_corners = part
+            .triangles
+            .iter()
+            .map(|t| t.corners.map(|[x, y, z]| Vec3::new(x, y, z)))
+            .collect::<Vec<_>>();
+
+        let (matches, mut report) = match_triangles(&from_corners, &centered(to));
+        report.from_vertices = part.fingerprint.vertices;
+
+        if !report.is_complete() {
+            bail!("Meshes don't match: {}", report);
+        }
+
+        let mut paint = vec![None; to.triangles.triangle.len()];
+        for (t, p) in matched_paint(
+            part.triangles.iter().map(|t| Some(t.paint.as_str())),
+            &matches,
+        )? {
+            paint[t] = p;
+        }
+
+        debug!("matched paint: {}", report);
+
+        Ok((paint, report))
+    }
+}
+
+/// Translates a copy of the mesh so its bounding box is centered on the origin
+///
+/// Orca and PrusaSlicer place part-local origins differently, the bounding box center is the
+/// only reference both agree on.
+fn centered(mesh: &Mesh) -> Mesh {
+    let mut out = mesh.clone();
+    let Some((min, max)) = mesh.bounding_box() else {
+        return out;
+    };
+    let center = [0, 1, 2].map(|i| (min[i] + max[i]) / 2.);
+
+    for v in out.vertices.vertex.iter_mut() {
+        v.x -= center[0];
+        v.y -= center[1];
+        v.z -= center[2];
+    }
+    out
+}
+
+/// One volume of a PrusaSlicer object, cut out of the object mesh
+struct PsVolume {
+    name: Option<String>,
+    /// triangle range in the object mesh
+    triangles: Range<usize>,
+    /// in volume-local coordinates when the volume has a `matrix`
+    mesh: Mesh,
+}
+
+fn ps_volumes(mesh: &Mesh, md_object: Option<&ps::Object>) -> Result<Vec<PsVolume>> {
+    let n = mesh.triangles.triangle.len();
+
+    let Some(md_object) = md_object.filter(|o| !o.volume.is_empty()) else {
+        return Ok(vec![PsVolume {
+            name: None,
+            triangles: 0..n,
+            mesh: mesh.clone(),
+        }]);
+    };
+
+    md_object
+        .volume
+        .iter()
+        .map(|v| {
+            ensure!(
+                v.firstid <= v.lastid && v.lastid < n,
+                "Volume triangles {}..={} out of range, object has {} triangles",
+                v.firstid,
+                v.lastid,
+                n
+            );
+            let triangles = v.firstid..v.lastid + 1;
+
+            let find = |key: &str| {
+                v.metadata
+                    .iter()
+                    .find(|m| m.key.as_deref() == Some(key))
+                    .and_then(|m| m.value.clone())
+            };
+
+            let mut sub = mesh.sub_mesh(v.firstid..=v.lastid);
+
+            // PrusaSlicer stores volume vertices in object space, the matrix takes them back
+            if let Some(matrix) = find("matrix") {
+                let values = matrix
+                    .split_whitespace()
+                    .map(|s| s.parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Parsing volume matrix")?;
+                ensure!(values.len() == 16, "Volume matrix must be 16 elements");
+
+                let inv = nalgebra::Matrix4::from_row_slice(&values)
+                    .try_inverse()
+                    .ok_or_else(|| anyhow!("Volume matrix is not invertible"))?;
+                for v in sub.vertices.vertex.iter_mut() {
+                    let p = inv.transform_point(&nalgebra::Point3::new(v.x, v.y, v.z));
+                    *v = Vertex {
+                        x: p.x,
+                        y: p.y,
+                        z: p.z,
+                    };
+                }
+            }
+
+            Ok(PsVolume {
+                name: find("name"),
+                triangles,
+                mesh: sub,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,215 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::{Matrix4, Point3};
+use rayon::prelude::*;
+
+use crate::{
+    instancing::triangle_corners,
+    mesh::Mesh,
+    model_orca::OrcaModel,
+    paint_tree::{interpolate, PaintTree},
+    splitting::Vec3,
+};
+
+/// How [`Mesh::simplify_paint`] reduces paint detail
+#[derive(Debug, Clone, Copy, PartialEq, Default, serde::Serialize, serde::Deserialize)]
+pub enum SimplifyMode {
+    /// every triangle takes the state covering most of its area
+    #[default]
+    Majority,
+    /// sub-triangles are no longer split once all their edges are shorter than `min_edge`, or
+    /// below `max_depth` levels
+    Prune {
+        min_edge: Option<f64>,
+        max_depth: Option<usize>,
+    },
+}
+
+/// What a paint simplification changed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimplifyReport {
+    pub triangles: usize,
+    pub painted_triangles: usize,
+    /// triangles whose paint string changed
+    pub changed: usize,
+    /// total length of the paint strings, which dominates the size of painted files
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+    /// area not covered by state 0, in mm² when measured in world space
+    pub painted_area_before: f64,
+    pub painted_area_after: f64,
+}
+
+impl std::ops::AddAssign for SimplifyReport {
+    fn add_assign(&mut self, other: Self) {
+        self.triangles += other.triangles;
+        self.painted_triangles += other.painted_triangles;
+        self.changed += other.changed;
+        self.bytes_before += other.bytes_before;
+        self.bytes_after += other.bytes_after;
+        self.painted_area_before += other.painted_area_before;
+        self.painted_area_after += other.painted_area_after;
+    }
+}
+
+impl std::fmt::Display for SimplifyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = |before: f64, after: f64| {
+            if before > 0. {
+                (after - before) / before * 100.
+            } else {
+                0.
+            }
+        };
+        write!(
+            f,
+            "{} of {} painted triangles changed, paint {} -> {} bytes ({:+.1}%), painted area {:.2} -> {:.2} mm² ({:+.2}%)",
+            self.changed,
+            self.painted_triangles,
+            self.bytes_before,
+            self.bytes_after,
+            percent(self.bytes_before as f64, self.bytes_after as f64),
+            self.painted_area_before,
+            self.painted_area_after,
+            percent(self.painted_area_before, self.painted_area_after),
+        )
+    }
+}
+
+/// New paint for every triangle, `None` where a triangle is left as it is
+type SimplifiedPaint = Vec<Option<Option<String>>>;
+
+impl Mesh {
+    /// Reduces the detail of the paint on every triangle, see [`SimplifyMode`]
+    ///
+    /// Edge lengths and areas are measured with the mesh placed by `transform`. Nothing is
+    /// written if any paint can't be decoded.
+    pub fn simplify_paint(
+        &mut self,
+        mode: SimplifyMode,
+        transform: &Matrix4<f64>,
+        orca: bool,
+    ) -> Result<SimplifyReport> {
+        let (paint, report) = self.simplified_paint(mode, transform)?;
+        self.write_simplified(paint, orca);
+        Ok(report)
+    }
+
+    fn simplified_paint(
+        &self,
+        mode: SimplifyMode,
+        transform: &Matrix4<f64>,
+    ) -> Result<(SimplifiedPaint, SimplifyReport)> {
+        let corners = (0..self.triangles.triangle.len())
+            .map(|i| {
+                triangle_corners(self, i)
+                    .map(|p| transform.transform_point(&Point3::from(p)).coords)
+            })
+            .collect::<Vec<_>>();
+
+        let simplified = self
+            .triangles
+            .triangle
+            .par_iter()
+            .zip(corners.par_iter())
+            .map(|(t, corners)| {
+                let Some(paint) = t.paint() else {
+                    let report = SimplifyReport {
+                        triangles: 1,
+                        ..Default::default()
+                    };
+                    return Ok((None, report));
+                };
+
+                let mut tree = PaintTree::decode(paint)?;
+                let area = (corners[1] - corners[0])
+                    .cross(&(corners[2] - corners[0]))
+                    .norm()
+                    / 2.;
+                let painted = |tree: &PaintTree| {
+                    let areas = tree.state_areas();
+                    area * (1. - areas.first().copied().unwrap_or(0.))
+                };
+
+                let before = painted(&tree);
+                simplify_tree(&mut tree, corners, mode);
+                let after = painted(&tree);
+
+                let new_paint = tree.to_paint();
+                let report = SimplifyReport {
+                    triangles: 1,
+                    painted_triangles: 1,
+                    changed: (new_paint.as_deref() != Some(paint)) as usize,
+                    bytes_before: paint.len(),
+                    bytes_after: new_paint.as_ref().map_or(0, |p| p.len()),
+                    painted_area_before: before,
+                    painted_area_after: after,
+                };
+
+                Ok((Some(new_paint), report))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut paint = vec![];
+        let mut out = SimplifyReport::default();
+        for (p, r) in simplified {
+            paint.push(p);
+            out += r;
+        }
+        Ok((paint, out))
+    }
+
+    fn write_simplified(&mut self, paint: SimplifiedPaint, orca: bool) {
+        for (t, p) in self.triangles.triangle.iter_mut().zip(paint) {
+            if let Some(p) = p {
+                t.set_paint(p, orca);
+            }
+        }
+    }
+}
+
+fn simplify_tree(tree: &mut PaintTree, corners: &[Vec3; 3], mode: SimplifyMode) {
+    match mode {
+        SimplifyMode::Majority => *tree = PaintTree::Leaf(tree.majority()),
+        SimplifyMode::Prune {
+            min_edge,
+            max_depth,
+        } => tree.prune(&mut |sub, depth| {
+            if max_depth.is_some_and(|max| depth >= max) {
+                return false;
+            }
+            let Some(min_edge) = min_edge else {
+                return true;
+            };
+            let p = sub.map(|c| interpolate(corners, &c));
+            (0..3).any(|i| (p[(i + 1) % 3] - p[i]).norm() >= min_edge)
+        }),
+    }
+}
+
+impl OrcaModel {
+    /// Simplifies the paint of every part of the object at `index`, measuring in world space
+    pub fn simplify_paint(&mut self, index: usize, mode: SimplifyMode) -> Result<SimplifyReport> {
+        let comps = self.object_components(index)?.clone();
+
+        // every part is simplified before any is written, so a bad paint string changes nothing
+        let mut simplified = vec![];
+        for comp in comps.iter() {
+            let transform = self.component_world_transform(index, comp)?;
+            simplified.push(
+                self.component_mesh(comp)?
+                    .simplified_paint(mode, &transform)?,
+            );
+        }
+
+        let mut out = SimplifyReport::default();
+        for (comp, (paint, report)) in comps.iter().zip(simplified) {
+            self.component_mesh_mut(comp)?.write_simplified(paint, true);
+            out += report;
+        }
+
+        debug!("simplified paint of object {}: {}", index, out);
+        Ok(out)
+    }
+}
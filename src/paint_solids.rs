@@ -0,0 +1,398 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::Matrix4;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    mesh::{Mesh, Triangle, Triangles, Vertex, Vertices},
+    metadata::ps_metadata::{self as ps, PSMetadata},
+    model::Model,
+    model_orca::{NewPart, OrcaModel},
+    paint_tree::PaintTree,
+    splitting::Vec3,
+};
+
+/// Limits how far a vertex moves at a sharp corner, as a multiple of the depth
+const MAX_MITER: f64 = 2.;
+
+/// How painted regions are closed into solids
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SolidOptions {
+    /// how far each region reaches into the object, in mm
+    pub depth: f64,
+    /// keep the inside left between the regions as a part of its own
+    pub core: bool,
+}
+
+impl Default for SolidOptions {
+    fn default() -> Self {
+        Self {
+            depth: 1.,
+            core: true,
+        }
+    }
+}
+
+/// A painted mesh cut into closed bodies
+#[derive(Debug, Clone)]
+pub struct PaintSolids {
+    /// one closed shell per state, the skin of the object painted that state
+    pub regions: Vec<(u8, Mesh)>,
+    /// the object with the regions taken off, closed when the object is
+    pub core: Option<Mesh>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SolidsReport {
+    pub parts_before: usize,
+    pub parts_after: usize,
+    pub triangles: usize,
+}
+
+impl std::ops::AddAssign for SolidsReport {
+    fn add_assign(&mut self, other: Self) {
+        self.parts_before += other.parts_before;
+        self.parts_after += other.parts_after;
+        self.triangles += other.triangles;
+    }
+}
+
+impl std::fmt::Display for SolidsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} parts, {} triangles",
+            self.parts_before, self.parts_after, self.triangles
+        )
+    }
+}
+
+/// `depth` in mm as a distance in the coordinates of a mesh placed with `transform`
+fn local_depth(transform: &Matrix4<f64>, depth: f64) -> f64 {
+    let scale = transform
+        .fixed_view::<3, 3>(0, 0)
+        .determinant()
+        .abs()
+        .cbrt();
+    if scale > f64::EPSILON {
+        depth / scale
+    } else {
+        depth
+    }
+}
+
+fn unpainted(v: [usize; 3]) -> Triangle {
+    Triangle {
+        v1: v[0],
+        v2: v[1],
+        v3: v[2],
+        mmu_ps: None,
+        mmu_orca: None,
+    }
+}
+
+/// Builds a mesh from triangles indexing into `points`, keeping only the points used
+fn compact(points: &[Vec3], triangles: &[[usize; 3]]) -> Mesh {
+    let mut remap = HashMap::new();
+    let mut vertices = vec![];
+    let triangles = triangles
+        .iter()
+        .map(|t| {
+            unpainted(t.map(|i| {
+                *remap.entry(i).or_insert_with(|| {
+                    let p = points[i];
+                    vertices.push(Vertex {
+                        x: p.x,
+                        y: p.y,
+                        z: p.z,
+                    });
+                    vertices.len() - 1
+                })
+            }))
+        })
+        .collect();
+    Mesh {
+        vertices: Vertices { vertex: vertices },
+        triangles: Triangles {
+            triangle: triangles,
+        },
+    }
+}
+
+impl Mesh {
+    /// Whether any triangle has paint
+    pub fn is_painted(&self) -> bool {
+        self.triangles.triangle.iter().any(|t| t.paint().is_some())
+    }
+
+    /// Cuts the mesh along its paint boundaries into one closed body per state
+    ///
+    /// The paint is baked first. Each region is closed by a copy of itself moved `depth`
+    /// inwards along the vertex normals, and by walls along its boundary, so neighboring
+    /// regions meet without gaps. Regions thinner than twice the depth fold over themselves.
+    pub fn paint_solids(&self, depth: f64, core: bool) -> Result<PaintSolids> {
+        ensure!(depth > 0., "Depth must be positive");
+        let mut baked = self.clone();
+        baked.bake_paint(true)?;
+
+        let n = baked.vertices.vertex.len();
+        let mut points = baked
+            .vertices
+            .vertex
+            .iter()
+            .map(|v| Vec3::new(v.x, v.y, v.z))
+            .collect::<Vec<_>>();
+        let triangles = baked
+            .triangles
+            .triangle
+            .iter()
+            .map(|t| [t.v1, t.v2, t.v3])
+            .collect::<Vec<_>>();
+        ensure!(
+            triangles.iter().flatten().all(|&v| v < n),
+            "Triangle has a vertex out of range"
+        );
+
+        // angle weighted vertex normals
+        let mut normals = vec![Vec3::zeros(); n];
+        let mut face_normals = vec![];
+        for t in triangles.iter() {
+            let p = t.map(|i| points[i]);
+            let normal = (p[1] - p[0])
+                .cross(&(p[2] - p[0]))
+                .try_normalize(f64::EPSILON)
+                .unwrap_or_else(Vec3::zeros);
+            for k in 0..3 {
+                let e1 = p[(k + 1) % 3] - p[k];
+                let e2 = p[(k + 2) % 3] - p[k];
+                normals[t[k]] += normal * e1.angle(&e2);
+            }
+            face_normals.push(normal);
+        }
+        for n in normals.iter_mut() {
+            *n = n.try_normalize(f64::EPSILON).unwrap_or_else(Vec3::zeros);
+        }
+
+        // moved far enough that every face around a vertex moves by about `depth`
+        let mut miter = vec![1f64; n];
+        for (t, normal) in triangles.iter().zip(face_normals.iter()) {
+            for &i in t.iter() {
+                miter[i] = miter[i].min(normals[i].dot(normal));
+            }
+        }
+        for i in 0..n {
+            let scale = 1. / miter[i].max(1. / MAX_MITER);
+            points.push(points[i] - normals[i] * depth * scale);
+        }
+        let inner = |i: usize| n + i;
+
+        let mut by_state: BTreeMap<u8, Vec<[usize; 3]>> = BTreeMap::new();
+        for (t, tri) in triangles.iter().zip(baked.triangles.triangle.iter()) {
+            let state = PaintTree::from_paint(tri.paint())?.majority();
+            by_state.entry(state).or_default().push(*t);
+        }
+
+        let mut regions = vec![];
+        for (state, tris) in by_state {
+            let edges = tris
+                .iter()
+                .flat_map(|t| (0..3).map(move |k| (t[k], t[(k + 1) % 3])))
+                .collect::<HashSet<_>>();
+            let mut out = tris.clone();
+            out.extend(tris.iter().map(|t| [inner(t[0]), inner(t[2]), inner(t[1])]));
+            for &(a, b) in edges.iter() {
+                if !edges.contains(&(b, a)) {
+                    out.push([b, a, inner(a)]);
+                    out.push([b, inner(a), inner(b)]);
+                }
+            }
+            regions.push((state, compact(&points, &out)));
+        }
+
+        let core = core.then(|| {
+            let tris = triangles.iter().map(|t| t.map(inner)).collect::<Vec<_>>();
+            compact(&points, &tris)
+        });
+
+        Ok(PaintSolids { regions, core })
+    }
+}
+
+impl OrcaModel {
+    /// Replaces each painted part of the object at `index` with one closed part per extruder
+    ///
+    /// Parts without paint are kept as they are.
+    pub fn split_paint_solids(
+        &mut self,
+        index: usize,
+        options: &SolidOptions,
+    ) -> Result<SolidsReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+
+        let mut out = SolidsReport::default();
+        // every part's solids are built first, so a failure leaves the object as it was
+        let mut replaced = vec![];
+        for (c, comp) in self.object_components(index)?.clone().iter().enumerate() {
+            let transform = self.component_world_transform(index, comp)?;
+            let mesh = self.component_mesh(comp)?;
+            if !mesh.is_painted() {
+                continue;
+            }
+            let solids = mesh.paint_solids(local_depth(&transform, options.depth), options.core)?;
+
+            let name = self
+                .part_name(object_id, comp.objectid)
+                .unwrap_or_else(|| format!("part {}", comp.objectid));
+            let default = self.part_extruder(object_id, comp.objectid);
+            let mut parts = solids
+                .regions
+                .into_iter()
+                .map(|(state, mesh)| {
+                    let extruder = if state == 0 { default } else { state };
+                    NewPart {
+                        name: format!("{} filament {}", name, extruder),
+                        mesh,
+                        extruder,
+                    }
+                })
+                .collect::<Vec<_>>();
+            if let Some(mesh) = solids.core {
+                parts.push(NewPart {
+                    name: format!("{} core", name),
+                    mesh,
+                    extruder: default,
+                });
+            }
+
+            out += SolidsReport {
+                parts_before: 1,
+                parts_after: parts.len(),
+                triangles: parts.iter().map(|p| p.mesh.triangles.triangle.len()).sum(),
+            };
+            replaced.push((c, parts));
+        }
+        // backwards, so replacing a part doesn't move the ones still to do
+        for (c, parts) in replaced.into_iter().rev() {
+            self.replace_part(index, c, parts)?;
+        }
+
+        debug!("split paint of object {} into solids: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Replaces each painted volume of the object at `index` with one closed volume per
+    /// extruder, as PrusaSlicer `mmu_segmentation`
+    ///
+    /// Volumes without paint are kept as they are.
+    pub fn split_paint_solids(
+        &mut self,
+        index: usize,
+        md: &mut PSMetadata,
+        options: &SolidOptions,
+    ) -> Result<SolidsReport> {
+        let object_id = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let depth = local_depth(&self.item_transform(index)?, options.depth);
+        let md_object = md
+            .object
+            .iter_mut()
+            .find(|o| o.id == object_id)
+            .with_context(|| format!("No volumes for object {}", object_id))?;
+        let mesh = self.object_mesh_mut(index)?;
+        ensure!(
+            md_object
+                .volume
+                .iter()
+                .all(|v| v.firstid <= v.lastid && v.lastid < mesh.triangles.triangle.len()),
+            "Volume ends past the last triangle"
+        );
+
+        // built aside, so a failure leaves the object as it was
+        let mut new_mesh = Mesh {
+            vertices: Vertices { vertex: vec![] },
+            triangles: Triangles { triangle: vec![] },
+        };
+        let mut out = SolidsReport::default();
+        let mut volumes = vec![];
+        for volume in md_object.volume.iter() {
+            let part = mesh.sub_mesh(volume.firstid..=volume.lastid);
+
+            let mut push = |part: &Mesh, metadata: Vec<ps::Metadata>| {
+                let mut v = volume.clone();
+                v.firstid = new_mesh.triangles.triangle.len();
+                new_mesh.merge(part);
+                v.lastid = new_mesh.triangles.triangle.len() - 1;
+                v.metadata = metadata;
+                volumes.push(v);
+            };
+            if !part.is_painted() {
+                push(&part, volume.metadata.clone());
+                continue;
+            }
+
+            let name = volume
+                .metadata
+                .iter()
+                .find(|m| m.key.as_deref() == Some("name"))
+                .and_then(|m| m.value.clone())
+                .unwrap_or_else(|| "volume".to_string());
+            // unpainted regions and the core keep the extruder of the volume
+            let with = |name: String, extruder: Option<u8>| {
+                let mut metadata = volume.metadata.clone();
+                metadata.retain(|m| {
+                    m.key.as_deref() != Some("name")
+                        && (extruder.is_none() || m.key.as_deref() != Some("extruder"))
+                });
+                let entry = |key: &str, value: String| ps::Metadata {
+                    ty: "volume".to_string(),
+                    key: Some(key.to_string()),
+                    value: Some(value),
+                };
+                metadata.push(entry("name", name));
+                if let Some(extruder) = extruder {
+                    metadata.push(entry("extruder", extruder.to_string()));
+                }
+                metadata
+            };
+
+            let solids = part.paint_solids(depth, options.core)?;
+            for (state, region) in solids.regions.iter() {
+                let metadata = match state {
+                    0 => with(format!("{} unpainted", name), None),
+                    &s => with(format!("{} filament {}", name, s), Some(s)),
+                };
+                push(region, metadata);
+            }
+            if let Some(core) = solids.core.as_ref() {
+                push(core, with(format!("{} core", name), None));
+            }
+            out += SolidsReport {
+                parts_before: 1,
+                parts_after: solids.regions.len() + solids.core.iter().len(),
+                triangles: solids
+                    .regions
+                    .iter()
+                    .map(|(_, m)| m)
+                    .chain(solids.core.iter())
+                    .map(|m| m.triangles.triangle.len())
+                    .sum(),
+            };
+        }
+        *mesh = new_mesh;
+        md_object.volume = volumes;
+
+        debug!("split paint of object {} into solids: {}", index, out);
+        Ok(out)
+    }
+}
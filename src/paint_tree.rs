@@ -0,0 +1,791 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+/// A point inside a painted triangle, in the triangle's own frame
+///
+/// The root triangle has corners `(0, 0)`, `(1, 0)` and `(0, 1)`, so a point `p` inside
+/// triangle `(a, b, c)` lies at `a + (b - a) * p.x + (c - a) * p.y`. Every vertex created by
+/// subdividing is a midpoint, so these coordinates stay exact.
+pub type Vec2 = nalgebra::Vector2<f64>;
+
+const EPS: f64 = 1e-9;
+
+/// Extra levels allowed when paint has to be resampled onto splits that don't line up
+const RESAMPLE_EXTRA_DEPTH: usize = 2;
+
+/// Decoded paint of a single triangle
+///
+/// PrusaSlicer (`slic3rpe:mmu_segmentation`) and Orca (`paint_color`) both store the
+/// `TriangleSelector` bitstream as a hex string. Each node is one nibble:
+/// - 2 bits: number of split sides (0 = leaf)
+/// - leaf: 2 bits of state, or `0b11` followed by a second nibble holding `state - 3`
+/// - split: 2 bits of special side, followed by the children in reverse order
+///
+/// The string stores the first nibble as its last character.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaintTree {
+    Leaf(u8),
+    Split {
+        sides: u8,
+        special_side: u8,
+        children: Vec<PaintTree>,
+    },
+}
+
+/// A leaf of a [`PaintTree`], with its corners in the root triangle's frame
+#[derive(Debug, Clone)]
+pub struct PaintLeaf {
+    pub corners: [Vec2; 3],
+    pub state: u8,
+    pub depth: usize,
+}
+
+/// Result of sampling a region while building a [`PaintTree`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// The whole region has this state
+    Uniform(u8),
+    /// The region needs subdividing, the state is used if the depth limit is reached
+    Mixed(u8),
+}
+
+/// What [`PaintTree::repaint`] does with a region
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repaint {
+    /// The region isn't selected, its paint stays as it is
+    Keep,
+    /// The whole region gets this state
+    Paint(u8),
+    /// The region is partly selected, with the state to use if it can't be split further,
+    /// `None` to keep the existing one
+    Mixed(Option<u8>),
+}
+
+pub fn root_corners() -> [Vec2; 3] {
+    [Vec2::new(0., 0.), Vec2::new(1., 0.), Vec2::new(0., 1.)]
+}
+
+/// Splits a triangle the way `TriangleSelector::perform_split` does
+///
+/// Works on any point type, so the same split can be followed in the root frame or in
+/// model space.
+pub fn split_corners<T>(corners: &[T; 3], sides: u8, special_side: u8) -> Vec<[T; 3]>
+where
+    T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f64, Output = T>,
+{
+    let s = special_side as usize % 3;
+    let a = corners[s];
+    let b = corners[(s + 1) % 3];
+    let c = corners[(s + 2) % 3];
+    let mid = |p: T, q: T| (p + q) * 0.5;
+
+    match sides {
+        1 => {
+            let m = mid(b, c);
+            vec![[a, b, m], [m, c, a]]
+        }
+        2 => {
+            let m_ab = mid(a, b);
+            let m_ac = mid(a, c);
+            vec![[a, m_ab, m_ac], [m_ab, b, m_ac], [b, c, m_ac]]
+        }
+        _ => {
+            let m_ab = mid(a, b);
+            let m_bc = mid(b, c);
+            let m_ca = mid(c, a);
+            vec![
+                [a, m_ab, m_ca],
+                [m_ab, b, m_bc],
+                [m_bc, c, m_ca],
+                [m_ab, m_bc, m_ca],
+            ]
+        }
+    }
+}
+
+/// Maps a point in the root frame onto a triangle in any space
+pub fn interpolate<T>(corners: &[T; 3], p: &Vec2) -> T
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f64, Output = T>,
+{
+    corners[0] + (corners[1] - corners[0]) * p.x + (corners[2] - corners[0]) * p.y
+}
+
+pub fn area(corners: &[Vec2; 3]) -> f64 {
+    let e1 = corners[1] - corners[0];
+    let e2 = corners[2] - corners[0];
+    (e1.x * e2.y - e1.y * e2.x).abs() * 0.5
+}
+
+impl PaintTree {
+    /// Decodes a paint string, `None` or an empty string is an unpainted triangle
+    pub fn from_paint(paint: Option<&str>) -> Result<Self> {
+        match paint {
+            Some(s) if !s.is_empty() => Self::decode(s),
+            _ => Ok(PaintTree::Leaf(0)),
+        }
+    }
+
+    pub fn decode(s: &str) -> Result<Self> {
+        let nibbles = s
+            .chars()
+            .rev()
+            .map(|ch| {
+                ch.to_digit(16)
+                    .map(|d| d as u8)
+                    .with_context(|| format!("Invalid hex character in paint string: {:?}", ch))
+            })
+            .collect::<Result<Vec<u8>>>()?;
+
+        let mut pos = 0;
+        let tree = Self::decode_node(&nibbles, &mut pos)?;
+        if pos != nibbles.len() {
+            warn!(
+                "paint string has {} trailing nibbles: {}",
+                nibbles.len() - pos,
+                s
+            );
+        }
+        Ok(tree)
+    }
+
+    fn decode_node(nibbles: &[u8], pos: &mut usize) -> Result<Self> {
+        let mut next = || -> Result<u8> {
+            let n = *nibbles.get(*pos).context("Paint string ended early")?;
+            *pos += 1;
+            Ok(n)
+        };
+
+        let code = next()?;
+        let sides = code & 0b11;
+        if sides == 0 {
+            let state = if code & 0b1100 == 0b1100 {
+                next()? + 3
+            } else {
+                code >> 2
+            };
+            return Ok(PaintTree::Leaf(state));
+        }
+
+        /// children are stored last to first
+        let mut children = (0..=sides)
+            .map(|_| Self::decode_node(nibbles, pos))
+            .collect::<Result<Vec<_>>>()?;
+        children.reverse();
+
+        Ok(PaintTree::Split {
+            sides,
+            special_side: code >> 2,
+            children,
+        })
+    }
+
+    /// Encodes the tree, an unpainted triangle gives `None`
+    pub fn to_paint(&self) -> Option<String> {
+        match self {
+            PaintTree::Leaf(0) => None,
+            _ => Some(self.encode()),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut nibbles = vec![];
+        self.encode_node(&mut nibbles);
+        nibbles
+            .iter()
+            .rev()
+            .map(|n| {
+                std::char::from_digit(*n as u32, 16)
+                    .unwrap()
+                    .to_ascii_uppercase()
+            })
+            .collect()
+    }
+
+    fn encode_node(&self, out: &mut Vec<u8>) {
+        match self {
+            PaintTree::Leaf(state) if *state < 3 => out.push(state << 2),
+            PaintTree::Leaf(state) => {
+                out.push(0b1100);
+                out.push(state - 3);
+            }
+            PaintTree::Split {
+                sides,
+                special_side,
+                children,
+            } => {
+                out.push(sides | (special_side << 2));
+                for child in children.iter().rev() {
+                    child.encode_node(out);
+                }
+            }
+        }
+    }
+
+    pub fn is_split(&self) -> bool {
+        matches!(self, PaintTree::Split { .. })
+    }
+
+    pub fn depth(&self) -> usize {
+        match self {
+            PaintTree::Leaf(_) => 0,
+            PaintTree::Split { children, .. } => {
+                1 + children.iter().map(|c| c.depth()).max().unwrap_or(0)
+            }
+        }
+    }
+
+    /// All leaves in depth-first order
+    pub fn leaves(&self) -> Vec<PaintLeaf> {
+        let mut out = vec![];
+        self.collect_leaves(root_corners(), 0, &mut out);
+        out
+    }
+
+    fn collect_leaves(&self, corners: [Vec2; 3], depth: usize, out: &mut Vec<PaintLeaf>) {
+        match self {
+            PaintTree::Leaf(state) => out.push(PaintLeaf {
+                corners,
+                state: *state,
+                depth,
+            }),
+            PaintTree::Split {
+                sides,
+                special_side,
+                children,
+            } => {
+                let sub = split_corners(&corners, *sides, *special_side);
+                for (child, c) in children.iter().zip(sub) {
+                    child.collect_leaves(c, depth + 1, out);
+                }
+            }
+        }
+    }
+
+    /// Visits the leaves in the same order as [`PaintTree::leaves`]
+    pub fn for_each_leaf_mut<F: FnMut(&[Vec2; 3], &mut u8)>(&mut self, f: &mut F) {
+        self.leaf_mut_rec(root_corners(), f);
+    }
+
+    fn leaf_mut_rec<F: FnMut(&[Vec2; 3], &mut u8)>(&mut self, corners: [Vec2; 3], f: &mut F) {
+        match self {
+            PaintTree::Leaf(state) => f(&corners, state),
+            PaintTree::Split {
+                sides,
+                special_side,
+                children,
+            } => {
+                let sub = split_corners(&corners, *sides, *special_side);
+                for (child, c) in children.iter_mut().zip(sub) {
+                    child.leaf_mut_rec(c, f);
+                }
+            }
+        }
+    }
+
+    /// Fraction of the triangle's area covered by each state, indexed by state
+    pub fn state_areas(&self) -> Vec<f64> {
+        let mut out = vec![];
+        for leaf in self.leaves() {
+            let s = leaf.state as usize;
+            if out.len() <= s {
+                out.resize(s + 1, 0.);
+            }
+            out[s] += area(&leaf.corners) * 2.;
+        }
+        out
+    }
+
+    /// The state covering the largest area
+    pub fn majority(&self) -> u8 {
+        match self {
+            PaintTree::Leaf(state) => *state,
+            _ => {
+                let areas = self.state_areas();
+                let mut best = 0;
+                for (s, a) in areas.iter().enumerate() {
+                    if *a > areas[best] + EPS {
+                        best = s;
+                    }
+                }
+                best as u8
+            }
+        }
+    }
+
+    /// The state at a point in the root frame
+    pub fn state_at(&self, p: &Vec2) -> u8 {
+        self.state_at_in(root_corners(), p)
+    }
+
+    fn state_at_in(&self, corners: [Vec2; 3], p: &Vec2) -> u8 {
+        let mut node = self;
+        let mut corners = corners;
+        loop {
+            match node {
+                PaintTree::Leaf(state) => return *state,
+                PaintTree::Split {
+                    sides,
+                    special_side,
+                    children,
+                } => {
+                    let sub = split_corners(&corners, *sides, *special_side);
+                    let i = sub
+                        .iter()
+                        .position(|c| contains(c, p))
+                        .unwrap_or_else(|| closest_child(&sub, p));
+                    node = &children[i];
+                    corners = sub[i];
+                }
+            }
+        }
+    }
+
+    /// The state of a region in the root frame, if the whole region has a single state
+    pub fn uniform_state(&self, region: &[Vec2; 3]) -> Option<u8> {
+        self.uniform_state_in(root_corners(), region)
+    }
+
+    fn uniform_state_in(&self, corners: [Vec2; 3], region: &[Vec2; 3]) -> Option<u8> {
+        let mut found = None;
+        if self.uniform_rec(corners, region, &mut found) {
+            found
+        } else {
+            None
+        }
+    }
+
+    fn uniform_rec(&self, corners: [Vec2; 3], region: &[Vec2; 3], found: &mut Option<u8>) -> bool {
+        if !overlaps(&corners, region) {
+            return true;
+        }
+        match self {
+            PaintTree::Leaf(state) => match found {
+                Some(s) => s == state,
+                None => {
+                    *found = Some(*state);
+                    true
+                }
+            },
+            PaintTree::Split {
+                sides,
+                special_side,
+                children,
+            } => {
+                let sub = split_corners(&corners, *sides, *special_side);
+                children
+                    .iter()
+                    .zip(sub)
+                    .all(|(child, c)| child.uniform_rec(c, region, found))
+            }
+        }
+    }
+
+    /// Samples this tree over a region of the root frame
+    pub fn sample(&self, region: &[Vec2; 3]) -> Sample {
+        self.sample_in(root_corners(), region)
+    }
+
+    fn sample_in(&self, corners: [Vec2; 3], region: &[Vec2; 3]) -> Sample {
+        match self.uniform_state_in(corners, region) {
+            Some(state) => Sample::Uniform(state),
+            None => {
+                let c = (region[0] + region[1] + region[2]) / 3.;
+                Sample::Mixed(self.state_at_in(corners, &c))
+            }
+        }
+    }
+
+    /// Builds the paint of a triangle covering `region` of this one
+    ///
+    /// `region` is in the root frame, and its corners become the new tree's corners. Used
+    /// when paint is carried onto triangles that don't line up with the original splits.
+    pub fn resample(&self, region: &[Vec2; 3], max_depth: usize) -> Self {
+        self.resample_in(root_corners(), region, max_depth)
+    }
+
+    fn resample_in(&self, corners: [Vec2; 3], region: &[Vec2; 3], max_depth: usize) -> Self {
+        let mut sample = |sub: &[Vec2; 3]| {
+            let sub = [
+                interpolate(region, &sub[0]),
+                interpolate(region, &sub[1]),
+                interpolate(region, &sub[2]),
+            ];
+            self.sample_in(corners, &sub)
+        };
+        let mut tree = Self::build_rec(root_corners(), max_depth, true, &mut sample);
+        tree.simplify();
+        tree
+    }
+
+    /// Builds a tree by recursively splitting into four, down to `max_depth`
+    pub fn build<F: FnMut(&[Vec2; 3]) -> Sample>(max_depth: usize, sample: &mut F) -> Self {
+        let mut tree = Self::build_rec(root_corners(), max_depth, false, sample);
+        tree.simplify();
+        tree
+    }
+
+    /// With `bisect`, the one and two sided splits are tried before splitting into four,
+    /// which reproduces paint exactly when it follows those splits
+    fn build_rec<F: FnMut(&[Vec2; 3]) -> Sample>(
+        corners: [Vec2; 3],
+        depth_left: usize,
+        bisect: bool,
+        sample: &mut F,
+    ) -> Self {
+        match sample(&corners) {
+            Sample::Uniform(state) => return PaintTree::Leaf(state),
+            Sample::Mixed(state) if depth_left == 0 => return PaintTree::Leaf(state),
+            Sample::Mixed(_) => {}
+        }
+
+        if bisect {
+            for sides in 1..=2 {
+                for special_side in 0..3 {
+                    let children = split_corners(&corners, sides, special_side)
+                        .iter()
+                        .map(|c| match sample(c) {
+                            Sample::Uniform(state) => Some(PaintTree::Leaf(state)),
+                            Sample::Mixed(_) => None,
+                        })
+                        .collect::<Option<Vec<_>>>();
+                    if let Some(children) = children {
+                        return PaintTree::Split {
+                            sides,
+                            special_side,
+                            children,
+                        };
+                    }
+                }
+            }
+        }
+
+        PaintTree::Split {
+            sides: 3,
+            special_side: 0,
+            children: split_corners(&corners, 3, 0)
+                .into_iter()
+                .map(|c| Self::build_rec(c, depth_left - 1, bisect, sample))
+                .collect(),
+        }
+    }
+
+    /// Merges splits whose children all ended up with the same state
+    pub fn simplify(&mut self) {
+        if let PaintTree::Split { children, .. } = self {
+            for child in children.iter_mut() {
+                child.simplify();
+            }
+            let first = match children[0] {
+                PaintTree::Leaf(s) => s,
+                _ => return,
+            };
+            if children.iter().all(|c| *c == PaintTree::Leaf(first)) {
+                *self = PaintTree::Leaf(first);
+            }
+        }
+    }
+
+    /// Collapses every split `keep` rejects into the majority state below it
+    ///
+    /// `keep` gets the corners and depth of each split node, parents before children.
+    pub fn prune<F: FnMut(&[Vec2; 3], usize) -> bool>(&mut self, keep: &mut F) {
+        self.prune_rec(root_corners(), 0, keep);
+        self.simplify();
+    }
+
+    fn prune_rec<F: FnMut(&[Vec2; 3], usize) -> bool>(
+        &mut self,
+        corners: [Vec2; 3],
+        depth: usize,
+        keep: &mut F,
+    ) {
+        if !self.is_split() {
+            return;
+        }
+        if !keep(&corners, depth) {
+            *self = PaintTree::Leaf(self.majority());
+            return;
+        }
+        if let PaintTree::Split {
+            sides,
+            special_side,
+            children,
+        } = self
+        {
+            let sub = split_corners(&corners, *sides, *special_side);
+            for (child, c) in children.iter_mut().zip(sub) {
+                child.prune_rec(c, depth + 1, keep);
+            }
+        }
+    }
+
+    /// Paints over the regions `f` selects, keeping the existing paint elsewhere
+    ///
+    /// `f` gets the corners of each node, parents before children. Leaves that are only
+    /// partly selected are split into four down to `max_depth`.
+    pub fn repaint<F: FnMut(&[Vec2; 3]) -> Repaint>(&self, max_depth: usize, f: &mut F) -> Self {
+        let mut tree = self.repaint_rec(root_corners(), 0, max_depth, f);
+        tree.simplify();
+        tree
+    }
+
+    fn repaint_rec<F: FnMut(&[Vec2; 3]) -> Repaint>(
+        &self,
+        corners: [Vec2; 3],
+        depth: usize,
+        max_depth: usize,
+        f: &mut F,
+    ) -> Self {
+        let state = match f(&corners) {
+            Repaint::Keep => return self.clone(),
+            Repaint::Paint(state) => return PaintTree::Leaf(state),
+            Repaint::Mixed(state) => state,
+        };
+
+        match self {
+            PaintTree::Split {
+                sides,
+                special_side,
+                children,
+            } => PaintTree::Split {
+                sides: *sides,
+                special_side: *special_side,
+                children: children
+                    .iter()
+                    .zip(split_corners(&corners, *sides, *special_side))
+                    .map(|(child, c)| child.repaint_rec(c, depth + 1, max_depth, f))
+                    .collect(),
+            },
+            PaintTree::Leaf(current) if depth >= max_depth => {
+                PaintTree::Leaf(state.unwrap_or(*current))
+            }
+            PaintTree::Leaf(_) => PaintTree::Split {
+                sides: 3,
+                special_side: 0,
+                children: split_corners(&corners, 3, 0)
+                    .into_iter()
+                    .map(|c| self.repaint_rec(c, depth + 1, max_depth, f))
+                    .collect(),
+            },
+        }
+    }
+
+    /// Re-expresses the tree for the same triangle with its corners in a different order
+    ///
+    /// `corner_map[i]` is the current corner that becomes corner `i`. This covers both
+    /// rotated triangles and mirrored ones, where the winding is flipped.
+    pub fn remap_corners(&self, corner_map: [usize; 3]) -> Self {
+        if corner_map == [0, 1, 2] {
+            return self.clone();
+        }
+        let src = root_corners();
+        let dst = [src[corner_map[0]], src[corner_map[1]], src[corner_map[2]]];
+        self.remap_rec(&src, &dst)
+    }
+
+    fn remap_rec(&self, src: &[Vec2; 3], dst: &[Vec2; 3]) -> Self {
+        let PaintTree::Split {
+            sides,
+            special_side,
+            children,
+        } = self
+        else {
+            return self.clone();
+        };
+
+        /// keep the same corner special, so the split itself is identical
+        let dst_special = if *sides == 3 {
+            0
+        } else {
+            let p = src[*special_side as usize % 3];
+            (0..3).find(|&i| same_point(&dst[i], &p)).unwrap_or(0) as u8
+        };
+
+        let src_sub = split_corners(src, *sides, *special_side);
+        let dst_sub = split_corners(dst, *sides, dst_special);
+
+        /// a mirrored two-sided split cuts its quad along the other diagonal
+        let Some(order) = dst_sub
+            .iter()
+            .map(|d| src_sub.iter().position(|s| same_corners(s, d)))
+            .collect::<Option<Vec<_>>>()
+        else {
+            let depth = self.depth() + RESAMPLE_EXTRA_DEPTH;
+            return self.resample_in(*src, dst, depth);
+        };
+
+        let children = order
+            .iter()
+            .zip(dst_sub.iter())
+            .map(|(&i, d)| children[i].remap_rec(&src_sub[i], d))
+            .collect();
+
+        PaintTree::Split {
+            sides: *sides,
+            special_side: dst_special,
+            children,
+        }
+    }
+}
+
+fn same_point(a: &Vec2, b: &Vec2) -> bool {
+    (a - b).norm_squared() < EPS * EPS
+}
+
+fn same_corners(a: &[Vec2; 3], b: &[Vec2; 3]) -> bool {
+    a.iter().all(|p| b.iter().any(|q| same_point(p, q)))
+}
+
+fn cross(o: &Vec2, a: &Vec2, b: &Vec2) -> f64 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+/// Whether `p` lies inside or on the edge of a triangle
+pub fn contains(corners: &[Vec2; 3], p: &Vec2) -> bool {
+    let d0 = cross(&corners[0], &corners[1], p);
+    let d1 = cross(&corners[1], &corners[2], p);
+    let d2 = cross(&corners[2], &corners[0], p);
+    let neg = d0 < -EPS || d1 < -EPS || d2 < -EPS;
+    let pos = d0 > EPS || d1 > EPS || d2 > EPS;
+    !(neg && pos)
+}
+
+fn closest_child(sub: &[[Vec2; 3]], p: &Vec2) -> usize {
+    let mut best = (0, f64::MAX);
+    for (i, c) in sub.iter().enumerate() {
+        let centroid = (c[0] + c[1] + c[2]) / 3.;
+        let d = (centroid - p).norm_squared();
+        if d < best.1 {
+            best = (i, d);
+        }
+    }
+    best.0
+}
+
+/// Whether two triangles share some area, touching edges don't count
+pub fn overlaps(a: &[Vec2; 3], b: &[Vec2; 3]) -> bool {
+    for tri in [a, b] {
+        for i in 0..3 {
+            let p = tri[i];
+            let q = tri[(i + 1) % 3];
+            let axis = Vec2::new(q.y - p.y, p.x - q.x);
+            let (a_min, a_max) = project(a, &axis);
+            let (b_min, b_max) = project(b, &axis);
+            let tol = EPS * axis.norm().max(1.);
+            if a_max <= b_min + tol || b_max <= a_min + tol {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn project(tri: &[Vec2; 3], axis: &Vec2) -> (f64, f64) {
+    let mut min = f64::MAX;
+    let mut max = f64::MIN;
+    for p in tri {
+        let d = p.dot(axis);
+        min = min.min(d);
+        max = max.max(d);
+    }
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_paint_strings() {
+        // whole triangles, then splits into four, three and two children
+        for s in ["4", "8", "0C", "48043", "8042", "485", "4802C7"] {
+            let tree = PaintTree::decode(s).unwrap();
+            assert_eq!(tree.encode(), s);
+        }
+        assert_eq!(PaintTree::from_paint(None).unwrap(), PaintTree::Leaf(0));
+        assert_eq!(PaintTree::from_paint(Some("")).unwrap(), PaintTree::Leaf(0));
+        assert_eq!(PaintTree::Leaf(0).to_paint(), None);
+    }
+
+    #[test]
+    fn round_trips_escaped_states() {
+        for state in 3..=18 {
+            let s = PaintTree::Leaf(state).encode();
+            assert_eq!(s.len(), 2);
+            assert!(s.ends_with('C'));
+            assert_eq!(PaintTree::decode(&s).unwrap(), PaintTree::Leaf(state));
+        }
+        assert_eq!(PaintTree::Leaf(18).encode(), "FC");
+    }
+
+    #[test]
+    fn rejects_broken_strings() {
+        assert!(PaintTree::decode("XZ").is_err());
+        // a split with no children after it
+        assert!(PaintTree::decode("3").is_err());
+    }
+
+    #[test]
+    fn keeps_prusaslicer_child_order() {
+        // the children are stored last to first after the split nibble
+        let tree = PaintTree::decode("48043").unwrap();
+        assert_eq!(
+            tree,
+            PaintTree::Split {
+                sides: 3,
+                special_side: 0,
+                children: vec![
+                    PaintTree::Leaf(1),
+                    PaintTree::Leaf(2),
+                    PaintTree::Leaf(0),
+                    PaintTree::Leaf(1),
+                ],
+            }
+        );
+        // the first three children hold corners 0, 1 and 2, the last one the middle
+        assert_eq!(tree.state_at(&Vec2::new(0.05, 0.05)), 1);
+        assert_eq!(tree.state_at(&Vec2::new(0.9, 0.05)), 2);
+        assert_eq!(tree.state_at(&Vec2::new(0.05, 0.9)), 0);
+        assert_eq!(tree.state_at(&Vec2::new(0.3, 0.3)), 1);
+    }
+
+    #[test]
+    fn splits_like_prusaslicer() {
+        let corners = root_corners();
+        let one = split_corners(&corners, 1, 1);
+        assert_eq!(one[0], [corners[1], corners[2], Vec2::new(0., 0.5)]);
+        assert_eq!(one[1], [Vec2::new(0., 0.5), corners[0], corners[1]]);
+        for sides in 1..=3 {
+            for special in 0..3 {
+                let total = split_corners(&corners, sides, special)
+                    .iter()
+                    .map(area)
+                    .sum::<f64>();
+                assert!((total - 0.5).abs() < EPS);
+            }
+        }
+    }
+
+    #[test]
+    fn flips_with_swapped_corners() {
+        let tree = PaintTree::decode("4802C7").unwrap();
+        let flipped = tree.remap_corners([0, 2, 1]);
+        let back = flipped.remap_corners([0, 2, 1]);
+        // swapping corners 1 and 2 swaps the two coordinates
+        for i in 0..20 {
+            for j in 0..(20 - i) {
+                let p = Vec2::new(i as f64 / 20. + 0.01, j as f64 / 20. + 0.01);
+                if p.x + p.y >= 1. {
+                    continue;
+                }
+                assert_eq!(flipped.state_at(&Vec2::new(p.y, p.x)), tree.state_at(&p));
+                assert_eq!(back.state_at(&p), tree.state_at(&p));
+            }
+        }
+        assert_eq!(tree.remap_corners([0, 1, 2]), tree);
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::Matrix4;
+
+use crate::{
+    auto_paint::AutoPaintReport,
+    bvh::Bvh,
+    mesh::Mesh,
+    metadata::ps_metadata::{self as ps, PSMetadata},
+    model::Model,
+    model_orca::OrcaModel,
+    paint_tree::Repaint,
+    splitting::Vec3,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PartPaintOptions {
+    /// index of the part whose surface is kept, the first model part by default
+    pub primary: Option<usize>,
+    /// mm, surfaces closer than this count as coinciding
+    pub tolerance: f64,
+    /// mm, shortest edge sub-triangles are split to along the edges of other parts
+    pub resolution: f64,
+}
+
+impl Default for PartPaintOptions {
+    fn default() -> Self {
+        Self {
+            primary: None,
+            tolerance: 0.05,
+            resolution: 0.2,
+        }
+    }
+}
+
+/// Whether `p` lies inside a closed surface or within `tolerance` of it
+fn covers(surface: &Bvh, p: &Vec3, tolerance: f64) -> bool {
+    surface
+        .closest_point(p)
+        .is_some_and(|c| c.distance <= tolerance)
+        || surface.contains(p)
+}
+
+/// Which of `parts` covers `p`, the first one it lies inside or on
+fn covering(parts: &[(u8, Bvh)], p: &Vec3, tolerance: f64) -> Option<u8> {
+    parts
+        .iter()
+        .find_map(|(extruder, surface)| covers(surface, p, tolerance).then_some(*extruder))
+}
+
+impl Mesh {
+    /// Paints where the mesh, placed with `transform`, lies inside or on one of `parts`, with
+    /// that part's extruder
+    ///
+    /// The paint elsewhere is kept.
+    pub(crate) fn paint_from_parts(
+        &mut self,
+        transform: &Matrix4<f64>,
+        parts: &[(u8, Bvh)],
+        options: &PartPaintOptions,
+        orca: bool,
+    ) -> Result<AutoPaintReport> {
+        ensure!(options.resolution > 0., "Resolution must be positive");
+        let tolerance = options.tolerance;
+        self.repaint_with(transform, options.resolution, orca, |_, p| {
+            let centroid = (p[0] + p[1] + p[2]) / 3.;
+            let state = covering(parts, &centroid, tolerance);
+            if p.iter().any(|p| covering(parts, p, tolerance) != state) {
+                return Repaint::Mixed(state);
+            }
+            // a part crossing the middle of the triangle without reaching its corners
+            let radius = p.iter().map(|p| (p - centroid).norm()).fold(0., f64::max);
+            let crossed = parts.iter().any(|(_, s)| {
+                s.closest_point(&centroid)
+                    .is_some_and(|c| c.distance > tolerance && c.distance < radius)
+            });
+            match (state, crossed) {
+                (_, true) => Repaint::Mixed(state),
+                (Some(state), false) => Repaint::Paint(state),
+                (None, false) => Repaint::Keep,
+            }
+        })
+    }
+}
+
+impl OrcaModel {
+    /// Paints the primary part of the object at `index` with the extruders of the other model
+    /// parts, then removes them
+    ///
+    /// Only the surface of the primary part is kept, other parts reaching out of it are cut
+    /// off. Where parts overlap, the first one in the object wins. Modifiers are left alone.
+    pub fn paint_from_parts(
+        &mut self,
+        index: usize,
+        options: &PartPaintOptions,
+    ) -> Result<AutoPaintReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let comps = self.object_components(index)?.clone();
+        let model_parts = (0..comps.len())
+            .filter(|&c| {
+                self.part_subtype(object_id, comps[c].objectid)
+                    .is_none_or(|s| s == "normal_part")
+            })
+            .collect::<Vec<_>>();
+        let primary = match options.primary {
+            Some(c) => {
+                ensure!(model_parts.contains(&c), "Part {} is not a model part", c);
+                c
+            }
+            None => *model_parts
+                .first()
+                .with_context(|| format!("Object {} has no model parts", index))?,
+        };
+        ensure!(
+            model_parts.len() > 1,
+            "Object {} has a single model part",
+            index
+        );
+
+        let mut parts = vec![];
+        for &c in model_parts.iter().filter(|&&c| c != primary) {
+            let transform = self.component_world_transform(index, &comps[c])?;
+            let surface = Bvh::new(self.component_mesh(&comps[c])?, &transform);
+            parts.push((self.part_extruder(object_id, comps[c].objectid), surface));
+        }
+
+        let transform = self.component_world_transform(index, &comps[primary])?;
+        let out = self
+            .component_mesh_mut(&comps[primary])?
+            .paint_from_parts(&transform, &parts, options, true)?;
+
+        for &c in model_parts.iter().rev().filter(|&&c| c != primary) {
+            self.replace_part(index, c, vec![])?;
+        }
+        if out.changed > 0 {
+            self.painted.insert(object_id, true);
+        }
+
+        debug!("painted object {} from its parts: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Paints the primary volume of the object at `index` with the extruders of the other
+    /// model volumes, then removes them, as PrusaSlicer `mmu_segmentation`
+    ///
+    /// See [`OrcaModel::paint_from_parts`].
+    pub fn paint_from_parts(
+        &mut self,
+        index: usize,
+        md: &mut PSMetadata,
+        options: &PartPaintOptions,
+    ) -> Result<AutoPaintReport> {
+        let object_id = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let transform = self.item_transform(index)?;
+        let md_object = md
+            .object
+            .iter_mut()
+            .find(|o| o.id == object_id)
+            .with_context(|| format!("No volumes for object {}", object_id))?;
+        let mesh = self.object_mesh_mut(index)?;
+        ensure!(
+            md_object
+                .volume
+                .iter()
+                .all(|v| v.firstid <= v.lastid && v.lastid < mesh.triangles.triangle.len()),
+            "Volume ends past the last triangle"
+        );
+
+        let model_parts = (0..md_object.volume.len())
+            .filter(|&v| !ps_modifier(&md_object.volume[v]))
+            .collect::<Vec<_>>();
+        let primary = match options.primary {
+            Some(v) => {
+                ensure!(model_parts.contains(&v), "Volume {} is not a model part", v);
+                v
+            }
+            None => *model_parts
+                .first()
+                .with_context(|| format!("Object {} has no model parts", index))?,
+        };
+        ensure!(
+            model_parts.len() > 1,
+            "Object {} has a single model part",
+            index
+        );
+
+        let object_extruder = ps_extruder(&md_object.metadata).unwrap_or(1);
+        let parts = model_parts
+            .iter()
+            .filter(|&&v| v != primary)
+            .map(|&v| {
+                let volume = &md_object.volume[v];
+                let part = mesh.sub_mesh(volume.firstid..=volume.lastid);
+                let extruder = ps_extruder(&volume.metadata).unwrap_or(object_extruder);
+                (extruder, Bvh::new(&part, &transform))
+            })
+            .collect::<Vec<_>>();
+
+        let primary_volume = &md_object.volume[primary];
+        let mut painted = mesh.sub_mesh(primary_volume.firstid..=primary_volume.lastid);
+        let out = painted.paint_from_parts(&transform, &parts, options, false)?;
+
+        // the primary volume first, then the modifiers as they were
+        let old = std::mem::replace(mesh, painted);
+        let mut volumes = vec![];
+        for (v, volume) in md_object.volume.iter().enumerate() {
+            if v != primary && !ps_modifier(volume) {
+                continue;
+            }
+            let mut volume = volume.clone();
+            if v == primary {
+                volume.firstid = 0;
+                volume.lastid = mesh.triangles.triangle.len() - 1;
+                volumes.insert(0, volume);
+            } else {
+                let part = old.sub_mesh(volume.firstid..=volume.lastid);
+                volume.firstid = mesh.triangles.triangle.len();
+                mesh.merge(&part);
+                volume.lastid = mesh.triangles.triangle.len() - 1;
+                volumes.push(volume);
+            }
+        }
+        md_object.volume = volumes;
+
+        debug!("painted object {} from its volumes: {}", index, out);
+        Ok(out)
+    }
+}
+
+/// The extruder set in PrusaSlicer object or volume metadata
+pub(crate) fn ps_extruder(md: &[ps::Metadata]) -> Option<u8> {
+    md.iter()
+        .find(|m| m.key.as_deref() == Some("extruder"))
+        .and_then(|m| m.value.as_ref()?.parse::<u8>().ok())
+        .filter(|&e| e > 0)
+}
+
+/// Whether a PrusaSlicer volume is a modifier, blocker or enforcer rather than a model part
+pub(crate) fn ps_modifier(volume: &ps::Volume) -> bool {
+    volume.metadata.iter().any(|m| match m.key.as_deref() {
+        Some("volume_type") => m.value.as_deref() != Some("ModelPart"),
+        Some("modifier") => m.value.as_deref() == Some("1"),
+        _ => false,
+    })
+}
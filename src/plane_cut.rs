@@ -0,0 +1,916 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::collections::{HashMap, HashSet};
+
+use nalgebra::{Matrix4, Vector4};
+
+use crate::{
+    mesh::{Mesh, Triangle, Triangles, Vertex, Vertices},
+    metadata::ps_metadata::{self as ps, PSMetadata},
+    model::Model,
+    model_orca::{NewPart, OrcaModel},
+    paint_regions::refine_depth,
+    paint_tree::{root_corners, PaintTree, Vec2},
+    part_paint::ps_modifier,
+    splitting::Vec3,
+    topology::Topology,
+};
+
+/// mm, vertices closer to the plane than this are on it
+const PLANE_TOLERANCE: f64 = 1e-6;
+
+const CONNECTOR_SEGMENTS: usize = 32;
+
+/// A plane in world space, the side `normal` points to is the upper one
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CutPlane {
+    pub point: [f64; 3],
+    pub normal: [f64; 3],
+}
+
+impl CutPlane {
+    pub fn horizontal(z: f64) -> Self {
+        Self {
+            point: [0., 0., z],
+            normal: [0., 0., 1.],
+        }
+    }
+
+    fn normal(&self) -> Result<Vec3> {
+        Vec3::from(self.normal)
+            .try_normalize(0.)
+            .context("Cut plane has no normal")
+    }
+
+    /// The plane in the space `transform` takes to world space
+    fn local(&self, transform: &Matrix4<f64>) -> Result<Vector4<f64>> {
+        let normal = self.normal()?;
+        let plane = normal.push(-normal.dot(&Vec3::from(self.point)));
+        Ok(transform.transpose() * plane)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum ConnectorKind {
+    /// a pin on the lower half fitting a hole in the upper one
+    #[default]
+    Plug,
+    /// holes in both halves for a separate dowel
+    Dowel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Connector {
+    pub kind: ConnectorKind,
+    /// mm
+    pub radius: f64,
+    /// mm, how far a connector reaches into each half
+    pub depth: f64,
+    /// mm, added to holes around and beyond the connector
+    pub clearance: f64,
+}
+
+impl Default for Connector {
+    fn default() -> Self {
+        Self {
+            kind: ConnectorKind::Plug,
+            radius: 2.5,
+            depth: 5.,
+            clearance: 0.15,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CutOptions {
+    /// one connector in the middle of each cut face
+    pub connector: Option<Connector>,
+    /// mm, shortest edge sub-triangles are split to along the cut
+    pub resolution: f64,
+}
+
+impl Default for CutOptions {
+    fn default() -> Self {
+        Self {
+            connector: None,
+            resolution: 0.2,
+        }
+    }
+}
+
+/// The two halves of a [`Mesh::cut`], closed where the mesh was
+#[derive(Debug, Clone)]
+pub struct MeshCut {
+    pub upper: Mesh,
+    pub lower: Mesh,
+    /// triangles split by the plane
+    pub split: usize,
+    pub cap_triangles: usize,
+    /// the middle of each cut face
+    pub cap_centers: Vec<Vec3>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CutReport {
+    /// object indices of the halves
+    pub upper: usize,
+    pub lower: usize,
+    pub split: usize,
+    pub cap_triangles: usize,
+    pub connectors: usize,
+}
+
+impl std::fmt::Display for CutReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "objects {} and {}, {} triangles split, {} cap triangles, {} connectors",
+            self.upper, self.lower, self.split, self.cap_triangles, self.connectors
+        )
+    }
+}
+
+fn cross_2d(a: &Vec2, b: &Vec2, c: &Vec2) -> f64 {
+    let (u, v) = (b - a, c - b);
+    u.x * v.y - u.y * v.x
+}
+
+fn signed_area(polygon: &[usize], points: &[Vec2]) -> f64 {
+    (0..polygon.len())
+        .map(|i| {
+            let (a, b) = (points[polygon[i]], points[polygon[(i + 1) % polygon.len()]]);
+            a.x * b.y - a.y * b.x
+        })
+        .sum::<f64>()
+        / 2.
+}
+
+fn polygon_contains(polygon: &[usize], points: &[Vec2], p: &Vec2) -> bool {
+    let mut inside = false;
+    for i in 0..polygon.len() {
+        let (a, b) = (points[polygon[i]], points[polygon[(i + 1) % polygon.len()]]);
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) * (b.x - a.x) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn in_triangle(p: &Vec2, a: &Vec2, b: &Vec2, c: &Vec2) -> bool {
+    cross_2d(a, b, p) >= 0. && cross_2d(b, c, p) >= 0. && cross_2d(c, a, p) >= 0.
+}
+
+/// Joins holes into a counter-clockwise outline through a cut to each, as in Eberly's
+/// "Triangulation by Ear Clipping"
+fn bridge_holes(
+    mut outline: Vec<usize>,
+    mut holes: Vec<Vec<usize>>,
+    points: &[Vec2],
+) -> Vec<usize> {
+    let rightmost = |hole: &[usize]| {
+        (0..hole.len())
+            .max_by(|&a, &b| points[hole[a]].x.total_cmp(&points[hole[b]].x))
+            .unwrap_or(0)
+    };
+    holes.sort_by(|a, b| {
+        points[b[rightmost(b)]]
+            .x
+            .total_cmp(&points[a[rightmost(a)]].x)
+    });
+
+    for hole in holes {
+        let start = rightmost(&hole);
+        let m = points[hole[start]];
+
+        // the closest outline edge to the right of the hole
+        let mut hit: Option<(f64, usize)> = None;
+        for i in 0..outline.len() {
+            let (a, b) = (points[outline[i]], points[outline[(i + 1) % outline.len()]]);
+            if (a.y > m.y) == (b.y > m.y) {
+                continue;
+            }
+            let x = a.x + (m.y - a.y) * (b.x - a.x) / (b.y - a.y);
+            if x >= m.x && hit.is_none_or(|(best, _)| x < best) {
+                hit = Some((x, i));
+            }
+        }
+        let Some((x, i)) = hit else {
+            warn!("hole outside its outline, skipping it");
+            continue;
+        };
+        let next = (i + 1) % outline.len();
+        let mut target = if points[outline[i]].x > points[outline[next]].x {
+            i
+        } else {
+            next
+        };
+
+        // a vertex in the way sees the hole at the smallest angle
+        let (hit_point, p) = (Vec2::new(x, m.y), points[outline[target]]);
+        let (a, b, c) = if p.y < m.y {
+            (m, p, hit_point)
+        } else {
+            (m, hit_point, p)
+        };
+        let angle = |q: &Vec2| (q.y - m.y).abs().atan2(q.x - m.x);
+        let mut best = angle(&p);
+        for (j, &v) in outline.iter().enumerate() {
+            let q = points[v];
+            if j != target && q != m && in_triangle(&q, &a, &b, &c) && angle(&q) < best {
+                best = angle(&q);
+                target = j;
+            }
+        }
+
+        let mut joined = outline[..=target].to_vec();
+        joined.extend(hole[start..].iter().chain(&hole[..=start]));
+        joined.extend(&outline[target..]);
+        outline = joined;
+    }
+    outline
+}
+
+/// Triangulates a counter-clockwise polygon, duplicate vertices allowed
+fn ear_clip(polygon: Vec<usize>, points: &[Vec2]) -> Vec<[usize; 3]> {
+    let mut polygon = polygon;
+    let mut out = vec![];
+    let mut i = 0;
+    let mut misses = 0;
+    while polygon.len() > 3 {
+        let n = polygon.len();
+        let (a, b, c) = (
+            polygon[(i + n - 1) % n],
+            polygon[i % n],
+            polygon[(i + 1) % n],
+        );
+        let (pa, pb, pc) = (points[a], points[b], points[c]);
+        let ear = cross_2d(&pa, &pb, &pc) > 0.
+            && polygon.iter().all(|&v| {
+                let q = points[v];
+                q == pa || q == pb || q == pc || !in_triangle(&q, &pa, &pb, &pc)
+            });
+        // nothing clean is left after a full round, the rest is degenerate
+        if ear || misses > n {
+            out.push([a, b, c]);
+            polygon.remove(i % n);
+            i = (i + n - 1) % (n - 1);
+            misses = 0;
+        } else {
+            i = (i + 1) % n;
+            misses += 1;
+        }
+    }
+    if polygon.len() == 3 {
+        out.push([polygon[0], polygon[1], polygon[2]]);
+    }
+    out.retain(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0]);
+    out
+}
+
+/// Closes the openings left along the plane, from edges `(a, b)` of the mesh with no
+/// `(b, a)`, with faces pointing along `normal`
+///
+/// Returns the triangles of each face.
+fn caps(edges: &HashSet<(usize, usize)>, points: &[Vec3], normal: &Vec3) -> Vec<Vec<[usize; 3]>> {
+    let mut outgoing: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &(a, b) in edges.iter().filter(|(a, b)| !edges.contains(&(*b, *a))) {
+        outgoing.entry(b).or_default().push(a);
+    }
+    let mut starts = outgoing.keys().copied().collect::<Vec<_>>();
+    starts.sort_unstable();
+
+    let mut loops = vec![];
+    for start in starts {
+        while outgoing.get(&start).is_some_and(|o| !o.is_empty()) {
+            let mut current = start;
+            let mut polygon = vec![];
+            while let Some(next) = outgoing.get_mut(&current).and_then(Vec::pop) {
+                polygon.push(current);
+                current = next;
+                if current == start {
+                    break;
+                }
+            }
+            if current == start && polygon.len() >= 3 {
+                loops.push(polygon);
+            } else {
+                warn!("open cut outline with {} vertices", polygon.len());
+            }
+        }
+    }
+
+    // a frame on the plane in which faces along `normal` run counter-clockwise
+    let u = normal
+        .cross(&Vec3::x())
+        .try_normalize(1e-6)
+        .unwrap_or_else(|| normal.cross(&Vec3::y()).normalize());
+    let v = normal.cross(&u);
+    let flat = points
+        .iter()
+        .map(|p| Vec2::new(p.dot(&u), p.dot(&v)))
+        .collect::<Vec<_>>();
+
+    let (outlines, holes): (Vec<_>, Vec<_>) = loops
+        .into_iter()
+        .map(|l| (signed_area(&l, &flat), l))
+        .filter(|(area, _)| *area != 0.)
+        .partition(|(area, _)| *area > 0.);
+    let mut outline_holes = vec![vec![]; outlines.len()];
+    for (_, hole) in holes {
+        // the smallest outline around the hole
+        let around = (0..outlines.len())
+            .filter(|&o| polygon_contains(&outlines[o].1, &flat, &flat[hole[0]]))
+            .min_by(|&a, &b| outlines[a].0.total_cmp(&outlines[b].0));
+        match around {
+            Some(o) => outline_holes[o].push(hole),
+            None => warn!("cut outline inside nothing, skipping it"),
+        }
+    }
+
+    outlines
+        .into_iter()
+        .zip(outline_holes)
+        .map(|((_, outline), holes)| ear_clip(bridge_holes(outline, holes, &flat), &flat))
+        .collect()
+}
+
+/// A closed cylinder from `base` along `axis`
+fn cylinder(base: &Vec3, axis: &Vec3, radius: f64) -> Mesh {
+    let direction = axis.normalize();
+    let u = direction
+        .cross(&Vec3::x())
+        .try_normalize(1e-6)
+        .unwrap_or_else(|| direction.cross(&Vec3::y()).normalize());
+    let v = direction.cross(&u);
+
+    let n = CONNECTOR_SEGMENTS;
+    let mut points = vec![*base, base + axis];
+    for i in 0..n {
+        let angle = std::f64::consts::TAU * i as f64 / n as f64;
+        let p = base + (u * angle.cos() + v * angle.sin()) * radius;
+        points.extend([p, p + axis]);
+    }
+    let mut triangles = vec![];
+    for i in 0..n {
+        let (b0, t0) = (2 + 2 * i, 3 + 2 * i);
+        let (b1, t1) = (2 + 2 * ((i + 1) % n), 3 + 2 * ((i + 1) % n));
+        triangles.extend([[0, b1, b0], [1, t0, t1], [b0, b1, t1], [b0, t1, t0]]);
+    }
+    Mesh {
+        vertices: Vertices {
+            vertex: points
+                .iter()
+                .map(|p| Vertex {
+                    x: p.x,
+                    y: p.y,
+                    z: p.z,
+                })
+                .collect(),
+        },
+        triangles: Triangles {
+            triangle: triangles
+                .into_iter()
+                .map(|[v1, v2, v3]| Triangle {
+                    v1,
+                    v2,
+                    v3,
+                    mmu_ps: None,
+                    mmu_orca: None,
+                })
+                .collect(),
+        },
+    }
+}
+
+impl Mesh {
+    /// Cuts the mesh by `plane`, given as `(a, b, c, d)` for `ax + by + cz + d = 0` in the
+    /// mesh's space, closing both halves with flat caps
+    ///
+    /// Split triangles keep their paint, resampled onto the pieces down to `resolution`. The
+    /// caps are unpainted.
+    pub fn cut(&self, plane: &Vector4<f64>, resolution: f64, orca: bool) -> Result<MeshCut> {
+        ensure!(resolution > 0., "Resolution must be positive");
+        let normal = plane.xyz();
+        let length = normal.norm();
+        ensure!(length > 0., "Cut plane has no normal");
+        let (normal, offset) = (normal / length, plane.w / length);
+
+        let topology = Topology::new(self)?;
+        let mut points = self
+            .vertices
+            .vertex
+            .iter()
+            .map(|v| Vec3::new(v.x, v.y, v.z))
+            .collect::<Vec<_>>();
+        let side = points
+            .iter()
+            .map(|p| normal.dot(p) + offset)
+            .collect::<Vec<_>>();
+        let mut on_plane = side
+            .iter()
+            .map(|s| s.abs() <= PLANE_TOLERANCE)
+            .collect::<Vec<_>>();
+
+        // where each crossing edge meets the plane, as a vertex and a fraction along the edge
+        // from `from`
+        let mut crossings: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut cross =
+            |points: &mut Vec<Vec3>, on_plane: &mut Vec<bool>, from: usize, to: usize| {
+                let (a, b) = if side[from] > -PLANE_TOLERANCE {
+                    (from, to)
+                } else {
+                    (to, from)
+                };
+                if on_plane[a] {
+                    return (a, if a == from { 0. } else { 1. });
+                }
+                let t = (side[a] / (side[a] - side[b])).clamp(0., 1.);
+                let v = *crossings.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    points.push(points[a] + (points[b] - points[a]) * t);
+                    on_plane.push(true);
+                    points.len() - 1
+                });
+                (v, if a == from { t } else { 1. - t })
+            };
+
+        let mut upper: Vec<Triangle> = vec![];
+        let mut lower: Vec<Triangle> = vec![];
+        let mut split = 0;
+        let root = root_corners();
+        for (t, triangle) in self.triangles.triangle.iter().enumerate() {
+            let w = topology.triangle_vertices(self, t);
+            let up = w.map(|v| side[v] > -PLANE_TOLERANCE);
+            let whole = Triangle {
+                v1: w[0],
+                v2: w[1],
+                v3: w[2],
+                ..triangle.clone()
+            };
+            if up.iter().all(|&u| u) {
+                upper.push(whole);
+                continue;
+            }
+            if up.iter().all(|&u| !u) {
+                lower.push(whole);
+                continue;
+            }
+
+            // the corner alone on its side first
+            let lone = (0..3).find(|&k| up[k] != up[(k + 1) % 3] && up[k] != up[(k + 2) % 3]);
+            let k = lone.context("Triangle crossing the plane without a lone corner")?;
+            let (k1, k2) = ((k + 1) % 3, (k + 2) % 3);
+            let (p1, t1) = cross(&mut points, &mut on_plane, w[k], w[k1]);
+            let (p2, t2) = cross(&mut points, &mut on_plane, w[k], w[k2]);
+            let r1 = root[k] + (root[k1] - root[k]) * t1;
+            let r2 = root[k] + (root[k2] - root[k]) * t2;
+            let pieces = [
+                (up[k], [w[k], p1, p2], [root[k], r1, r2]),
+                (!up[k], [p1, w[k1], w[k2]], [r1, root[k1], root[k2]]),
+                (!up[k], [p1, w[k2], p2], [r1, root[k2], r2]),
+            ];
+
+            let tree = PaintTree::from_paint(triangle.paint())?;
+            let mut emitted = 0;
+            for (is_up, [v1, v2, v3], region) in pieces {
+                if v1 == v2 || v2 == v3 || v3 == v1 {
+                    continue;
+                }
+                emitted += 1;
+                let mut piece = Triangle {
+                    v1,
+                    v2,
+                    v3,
+                    ..triangle.clone()
+                };
+                if !matches!(tree, PaintTree::Leaf(_)) {
+                    let corners = [v1, v2, v3].map(|v| points[v]);
+                    let paint = tree.resample(&region, refine_depth(&corners, resolution));
+                    piece.set_paint(paint.to_paint(), orca);
+                }
+                if is_up {
+                    upper.push(piece);
+                } else {
+                    lower.push(piece);
+                }
+            }
+            if emitted > 1 {
+                split += 1;
+            }
+        }
+
+        // the cut outlines are the edges along the plane used one way only
+        let plane_edges = |triangles: &[Triangle]| {
+            triangles
+                .iter()
+                .flat_map(|t| [(t.v1, t.v2), (t.v2, t.v3), (t.v3, t.v1)])
+                .filter(|(a, b)| on_plane[*a] && on_plane[*b])
+                .collect::<HashSet<_>>()
+        };
+        let upper_caps = caps(&plane_edges(&upper), &points, &-normal);
+        let lower_caps = caps(&plane_edges(&lower), &points, &normal);
+
+        // the centroid of each face, or of its largest triangle when that is in a hole
+        let cap_centers = upper_caps
+            .iter()
+            .filter_map(|face| {
+                let triangles = face
+                    .iter()
+                    .map(|t| {
+                        let p = t.map(|v| points[v]);
+                        let normal = (p[1] - p[0]).cross(&(p[2] - p[0]));
+                        (p, normal, normal.norm())
+                    })
+                    .collect::<Vec<_>>();
+                let area = triangles.iter().map(|(_, _, a)| a).sum::<f64>();
+                if area <= 0. {
+                    return None;
+                }
+                let center = triangles
+                    .iter()
+                    .map(|(p, _, a)| (p[0] + p[1] + p[2]) / 3. * *a)
+                    .sum::<Vec3>()
+                    / area;
+                let on_face = triangles.iter().any(|(p, normal, _)| {
+                    (0..3).all(|k| {
+                        let edge = p[(k + 1) % 3] - p[k];
+                        edge.cross(&(center - p[k])).dot(normal) >= 0.
+                    })
+                });
+                if on_face {
+                    return Some(center);
+                }
+                triangles
+                    .iter()
+                    .max_by(|a, b| a.2.total_cmp(&b.2))
+                    .map(|(p, _, _)| (p[0] + p[1] + p[2]) / 3.)
+            })
+            .collect();
+        let mut cap_triangles = 0;
+        for (triangles, faces) in [(&mut upper, upper_caps), (&mut lower, lower_caps)] {
+            for [v1, v2, v3] in faces.into_iter().flatten() {
+                triangles.push(Triangle {
+                    v1,
+                    v2,
+                    v3,
+                    mmu_ps: None,
+                    mmu_orca: None,
+                });
+                cap_triangles += 1;
+            }
+        }
+
+        let build = |triangles: Vec<Triangle>| {
+            let mut remap = HashMap::new();
+            let mut vertices = vec![];
+            let triangle = triangles
+                .into_iter()
+                .map(|t| {
+                    let [v1, v2, v3] = [t.v1, t.v2, t.v3].map(|v| {
+                        *remap.entry(v).or_insert_with(|| {
+                            let p = points[v];
+                            vertices.push(Vertex {
+                                x: p.x,
+                                y: p.y,
+                                z: p.z,
+                            });
+                            vertices.len() - 1
+                        })
+                    });
+                    Triangle { v1, v2, v3, ..t }
+                })
+                .collect();
+            Mesh {
+                vertices: Vertices { vertex: vertices },
+                triangles: Triangles { triangle },
+            }
+        };
+
+        let out = MeshCut {
+            upper: build(upper),
+            lower: build(lower),
+            split,
+            cap_triangles,
+            cap_centers,
+        };
+        debug!(
+            "cut mesh: {} split, {} cap triangles, {} faces",
+            out.split,
+            out.cap_triangles,
+            out.cap_centers.len()
+        );
+        Ok(out)
+    }
+}
+
+/// Connector meshes, each with whether it is a hole
+type Connectors = Vec<(Mesh, bool)>;
+
+/// The connector meshes in world space for each cut face, for the upper and lower halves
+fn connector_meshes(
+    connector: &Connector,
+    centers: &[Vec3],
+    normal: &Vec3,
+) -> (Connectors, Connectors) {
+    let (mut upper, mut lower) = (vec![], vec![]);
+    let (depth, clearance) = (connector.depth, connector.clearance);
+    for center in centers {
+        let hole = |from: f64, to: f64| {
+            let base = center + normal * from;
+            (
+                cylinder(&base, &(normal * (to - from)), connector.radius + clearance),
+                true,
+            )
+        };
+        match connector.kind {
+            ConnectorKind::Plug => {
+                let base = center - normal * depth;
+                lower.push((
+                    cylinder(&base, &(normal * 2. * depth), connector.radius),
+                    false,
+                ));
+                upper.push(hole(-clearance, depth + clearance));
+            }
+            ConnectorKind::Dowel => {
+                lower.push(hole(-depth - clearance, clearance));
+                upper.push(hole(-clearance, depth + clearance));
+            }
+        }
+    }
+    (upper, lower)
+}
+
+fn transform_mesh(mesh: &mut Mesh, transform: &Matrix4<f64>) {
+    for v in mesh.vertices.vertex.iter_mut() {
+        let p = transform.transform_point(&nalgebra::Point3::new(v.x, v.y, v.z));
+        *v = Vertex {
+            x: p.x,
+            y: p.y,
+            z: p.z,
+        };
+    }
+}
+
+impl OrcaModel {
+    /// Cuts the object at `index` by `plane` into two objects, the upper half staying at
+    /// `index`
+    ///
+    /// Every part is cut and capped, parts left on one side only stay with that half. Both
+    /// halves keep their placement.
+    pub fn cut(
+        &mut self,
+        index: usize,
+        plane: &CutPlane,
+        options: &CutOptions,
+    ) -> Result<CutReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let name = self
+            .md
+            .get_object_by_id(object_id)
+            .and_then(|o| o.get_name())
+            .unwrap_or_else(|| "object".to_string());
+        let comps = self.object_components(index)?.clone();
+
+        let mut report = CutReport {
+            upper: index,
+            ..Default::default()
+        };
+        let mut halves = vec![];
+        let mut centers = vec![];
+        let mut model_parts = [false; 2];
+        for comp in comps.iter() {
+            let transform = self.component_world_transform(index, comp)?;
+            let cut = self.component_mesh(comp)?.cut(
+                &plane.local(&transform)?,
+                options.resolution,
+                true,
+            )?;
+            report.split += cut.split;
+            report.cap_triangles += cut.cap_triangles;
+            if self
+                .part_subtype(object_id, comp.objectid)
+                .is_none_or(|s| s == "normal_part")
+            {
+                model_parts[0] |= !cut.upper.triangles.triangle.is_empty();
+                model_parts[1] |= !cut.lower.triangles.triangle.is_empty();
+                centers.extend(
+                    cut.cap_centers
+                        .iter()
+                        .map(|c| transform.transform_point(&(*c).into()).coords),
+                );
+            }
+            halves.push((cut.upper, cut.lower));
+        }
+        ensure!(
+            model_parts == [true, true],
+            "The plane doesn't cut object {}",
+            index
+        );
+
+        report.lower = self.duplicate_object(index, &format!("{} lower", name))?;
+        self.rename_object(index, &format!("{} upper", name))?;
+        for (object, upper) in [(report.upper, true), (report.lower, false)] {
+            for (c, (u, l)) in halves.iter().enumerate() {
+                let comp = self.object_components(object)?[c].clone();
+                *self.component_mesh_mut(&comp)? = if upper { u.clone() } else { l.clone() };
+            }
+            for (c, (u, l)) in halves.iter().enumerate().rev() {
+                let empty = if upper { u } else { l }.triangles.triangle.is_empty();
+                if empty {
+                    self.replace_part(object, c, vec![])?;
+                }
+            }
+        }
+
+        if let Some(connector) = options.connector {
+            let (upper, lower) = connector_meshes(&connector, &centers, &plane.normal()?);
+            for (object, meshes) in [(report.upper, upper), (report.lower, lower)] {
+                let first = self.object_components(object)?[0].clone();
+                let extruder = self.part_extruder(self.get_objects()[object].id, first.objectid);
+                let to_part = self
+                    .component_world_transform(object, &first)?
+                    .try_inverse()
+                    .context("Part transform can't be inverted")?;
+                for (mut mesh, hole) in meshes {
+                    transform_mesh(&mut mesh, &to_part);
+                    let part = NewPart {
+                        name: format!("{} connector", name),
+                        mesh,
+                        extruder,
+                    };
+                    let subtype = if hole { "negative_part" } else { "normal_part" };
+                    self.add_part(object, part, subtype)?;
+                    report.connectors += 1;
+                }
+            }
+        }
+
+        debug!("cut object {}: {}", index, report);
+        Ok(report)
+    }
+}
+
+impl Model {
+    /// Cuts the object at `index` by `plane` into two objects, the upper half staying at
+    /// `index`, with each volume in `md` cut on its own
+    ///
+    /// Connectors are added as volumes, so they need the object in `md`. See
+    /// [`OrcaModel::cut`].
+    pub fn cut(
+        &mut self,
+        index: usize,
+        md: Option<&mut PSMetadata>,
+        plane: &CutPlane,
+        options: &CutOptions,
+    ) -> Result<CutReport> {
+        let mut md = md;
+        let object = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?;
+        let object_id = object.id;
+        let transform = self.item_transform(index)?;
+        let local = plane.local(&transform)?;
+        let mesh = self.object_mesh_mut(index)?.clone();
+
+        let md_object = md
+            .as_deref()
+            .and_then(|md| md.get_object_by_id(object_id))
+            .filter(|o| !o.volume.is_empty())
+            .cloned();
+        let name = md_object
+            .as_ref()
+            .and_then(|o| o.get_name())
+            .or_else(|| self.resources.object[index].name.clone())
+            .unwrap_or_else(|| "object".to_string());
+        let volumes = match md_object.as_ref() {
+            Some(o) => {
+                ensure!(
+                    o.volume
+                        .iter()
+                        .all(|v| v.firstid <= v.lastid && v.lastid < mesh.triangles.triangle.len()),
+                    "Volume ends past the last triangle"
+                );
+                o.volume.clone()
+            }
+            None => vec![],
+        };
+        // a hole is a NegativeVolume, which only the volumes in the metadata can hold
+        ensure!(
+            options.connector.is_none() || md_object.is_some(),
+            "Connectors need the volumes of object {} in the project metadata",
+            index
+        );
+        let to_object = transform
+            .try_inverse()
+            .context("Item transform can't be inverted")?;
+
+        let mut report = CutReport {
+            upper: index,
+            ..Default::default()
+        };
+        let mut halves = vec![];
+        let mut centers = vec![];
+        let mut model_parts = [false; 2];
+        let parts = if volumes.is_empty() {
+            vec![(mesh.clone(), true)]
+        } else {
+            volumes
+                .iter()
+                .map(|v| (mesh.sub_mesh(v.firstid..=v.lastid), !ps_modifier(v)))
+                .collect()
+        };
+        for (part, model_part) in parts {
+            let cut = part.cut(&local, options.resolution, false)?;
+            report.split += cut.split;
+            report.cap_triangles += cut.cap_triangles;
+            if model_part {
+                model_parts[0] |= !cut.upper.triangles.triangle.is_empty();
+                model_parts[1] |= !cut.lower.triangles.triangle.is_empty();
+                centers.extend(
+                    cut.cap_centers
+                        .iter()
+                        .map(|c| transform.transform_point(&(*c).into()).coords),
+                );
+            }
+            halves.push((cut.upper, cut.lower));
+        }
+        ensure!(
+            model_parts == [true, true],
+            "The plane doesn't cut object {}",
+            index
+        );
+
+        report.lower =
+            self.duplicate_object(index, md.as_deref_mut(), &format!("{} lower", name))?;
+        self.rename_object(index, md.as_deref_mut(), &format!("{} upper", name))?;
+
+        let connectors = match options.connector {
+            Some(connector) => connector_meshes(&connector, &centers, &plane.normal()?),
+            None => (vec![], vec![]),
+        };
+        for (object, upper, connectors) in [
+            (report.upper, true, connectors.0),
+            (report.lower, false, connectors.1),
+        ] {
+            let id = self.resources.object[object].id;
+            let mesh = self.object_mesh_mut(object)?;
+            *mesh = Mesh {
+                vertices: Vertices { vertex: vec![] },
+                triangles: Triangles { triangle: vec![] },
+            };
+            let mut new_volumes = vec![];
+            for (v, (u, l)) in halves.iter().enumerate() {
+                let half = if upper { u } else { l };
+                if half.triangles.triangle.is_empty() {
+                    continue;
+                }
+                let firstid = mesh.triangles.triangle.len();
+                mesh.merge(half);
+                if let Some(volume) = volumes.get(v) {
+                    new_volumes.push(ps::Volume {
+                        firstid,
+                        lastid: mesh.triangles.triangle.len() - 1,
+                        mesh: ps::Mesh::default(),
+                        ..volume.clone()
+                    });
+                }
+            }
+            for (mut connector, hole) in connectors {
+                transform_mesh(&mut connector, &to_object);
+                let firstid = mesh.triangles.triangle.len();
+                mesh.merge(&connector);
+                let entry = |key: &str, value: &str| ps::Metadata {
+                    ty: "volume".to_string(),
+                    key: Some(key.to_string()),
+                    value: Some(value.to_string()),
+                };
+                let ty = if hole { "NegativeVolume" } else { "ModelPart" };
+                new_volumes.push(ps::Volume {
+                    firstid,
+                    lastid: mesh.triangles.triangle.len() - 1,
+                    metadata: vec![
+                        entry("name", &format!("{} connector", name)),
+                        entry("volume_type", ty),
+                    ],
+                    mesh: ps::Mesh::default(),
+                });
+                report.connectors += 1;
+            }
+            let md_object = md
+                .as_deref_mut()
+                .and_then(|md| md.object.iter_mut().find(|o| o.id == id));
+            if let Some(md_object) = md_object {
+                md_object.volume = new_volumes;
+            }
+        }
+
+        debug!("cut object {}: {}", index, report);
+        Ok(report)
+    }
+}
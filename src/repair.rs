@@ -0,0 +1,381 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    mesh::{Mesh, Triangle},
+    metadata::{orca_metadata as orca, ps_metadata as ps, ps_metadata::PSMetadata},
+    model::Model,
+    model_orca::OrcaModel,
+    paint_tree::PaintTree,
+    splitting::Vec3,
+    topology::Topology,
+};
+
+/// What [`Mesh::repair`] fixed, the counters slicers keep as `mesh_stat`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RepairReport {
+    pub vertices_merged: usize,
+    /// open edges closed by welding
+    pub edges_fixed: usize,
+    /// triangles with two corners at the same vertex
+    pub degenerate_facets: usize,
+    /// degenerate and duplicate triangles
+    pub facets_removed: usize,
+    pub facets_reversed: usize,
+    /// edges whose two triangles ran along them the same way, before the winding was fixed
+    pub backwards_edges: usize,
+}
+
+impl std::ops::AddAssign for RepairReport {
+    fn add_assign(&mut self, other: Self) {
+        self.vertices_merged += other.vertices_merged;
+        self.edges_fixed += other.edges_fixed;
+        self.degenerate_facets += other.degenerate_facets;
+        self.facets_removed += other.facets_removed;
+        self.facets_reversed += other.facets_reversed;
+        self.backwards_edges += other.backwards_edges;
+    }
+}
+
+impl std::fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} vertices merged, {} edges fixed, {} degenerate, {} removed, {} reversed, {} backwards edges",
+            self.vertices_merged,
+            self.edges_fixed,
+            self.degenerate_facets,
+            self.facets_removed,
+            self.facets_reversed,
+            self.backwards_edges
+        )
+    }
+}
+
+impl RepairReport {
+    pub fn to_orca(&self) -> orca::MeshStat {
+        orca::MeshStat {
+            edges_fixed: self.edges_fixed,
+            degenerate_facets: self.degenerate_facets,
+            facets_removed: self.facets_removed,
+            facets_reversed: self.facets_reversed,
+            backwards_edges: self.backwards_edges,
+        }
+    }
+
+    pub fn to_ps(&self) -> ps::Mesh {
+        ps::Mesh {
+            edges_fixed: self.edges_fixed,
+            degenerate_facets: self.degenerate_facets,
+            facets_removed: self.facets_removed,
+            facets_reversed: self.facets_reversed,
+            backwards_edges: self.backwards_edges,
+        }
+    }
+}
+
+/// Edges used by a single triangle, by vertex index
+fn open_edges(mesh: &Mesh) -> usize {
+    let mut edges: HashMap<[usize; 2], usize> = HashMap::new();
+    for t in mesh.triangles.triangle.iter() {
+        let v = [t.v1, t.v2, t.v3];
+        for k in 0..3 {
+            let (a, b) = (v[k], v[(k + 1) % 3]);
+            if a != b {
+                *edges.entry([a.min(b), a.max(b)]).or_default() += 1;
+            }
+        }
+    }
+    edges.values().filter(|&&n| n == 1).count()
+}
+
+/// Turns a triangle over, keeping its paint on the same spots
+fn flip(t: &mut Triangle) -> Result<()> {
+    std::mem::swap(&mut t.v2, &mut t.v3);
+    if let Some(paint) = t.paint() {
+        let paint = PaintTree::decode(paint)?
+            .remap_corners([0, 2, 1])
+            .to_paint();
+        let orca = t.mmu_orca.is_some();
+        t.set_paint(paint, orca);
+    }
+    Ok(())
+}
+
+impl Mesh {
+    /// Welds vertices closer than `tolerance`, removes degenerate and duplicate triangles and
+    /// makes the winding consistent, outwards for closed shells
+    ///
+    /// Triangles that stay keep their paint, turned over with them where they are flipped.
+    /// Unused vertices are dropped.
+    pub fn repair(&mut self, tolerance: f64) -> Result<RepairReport> {
+        ensure!(tolerance > 0., "Tolerance must be positive");
+        let n = self.vertices.vertex.len();
+        ensure!(
+            self.triangles
+                .triangle
+                .iter()
+                .all(|t| t.v1 < n && t.v2 < n && t.v3 < n),
+            "Triangle has a vertex out of range"
+        );
+        let mut out = RepairReport::default();
+        let open_before = open_edges(self);
+
+        let mut welded = HashMap::new();
+        let vertex_map = self
+            .vertices
+            .vertex
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let key = [v.x, v.y, v.z].map(|x| (x / tolerance).round() as i64);
+                *welded.entry(key).or_insert(i)
+            })
+            .collect::<Vec<_>>();
+        out.vertices_merged = n - welded.len();
+
+        let mut kept: Vec<Triangle> = vec![];
+        let mut seen: HashMap<[usize; 3], usize> = HashMap::new();
+        for t in std::mem::take(&mut self.triangles.triangle) {
+            let t = Triangle {
+                v1: vertex_map[t.v1],
+                v2: vertex_map[t.v2],
+                v3: vertex_map[t.v3],
+                ..t
+            };
+            if t.v1 == t.v2 || t.v2 == t.v3 || t.v1 == t.v3 {
+                out.degenerate_facets += 1;
+                out.facets_removed += 1;
+                continue;
+            }
+            let mut key = [t.v1, t.v2, t.v3];
+            key.sort_unstable();
+            match seen.get(&key) {
+                // a painted copy wins over an unpainted one
+                Some(&k) => {
+                    if kept[k].paint().is_none() && t.paint().is_some() {
+                        kept[k] = t;
+                    }
+                    out.facets_removed += 1;
+                }
+                None => {
+                    seen.insert(key, kept.len());
+                    kept.push(t);
+                }
+            }
+        }
+        self.triangles.triangle = kept;
+
+        // unused vertices
+        let mut remap = vec![usize::MAX; n];
+        let mut vertices = vec![];
+        for t in self.triangles.triangle.iter_mut() {
+            for v in [&mut t.v1, &mut t.v2, &mut t.v3] {
+                if remap[*v] == usize::MAX {
+                    remap[*v] = vertices.len();
+                    vertices.push(self.vertices.vertex[*v]);
+                }
+                *v = remap[*v];
+            }
+        }
+        self.vertices.vertex = vertices;
+
+        let flipped = self.orient(&mut out)?;
+        for (t, flipped) in self.triangles.triangle.iter_mut().zip(flipped) {
+            if flipped {
+                flip(t)?;
+                out.facets_reversed += 1;
+            }
+        }
+
+        out.edges_fixed = open_before.saturating_sub(open_edges(self));
+        debug!("repaired mesh: {}", out);
+        Ok(out)
+    }
+
+    /// Which triangles to flip so neighbors agree, counting the edges where they didn't
+    fn orient(&self, out: &mut RepairReport) -> Result<Vec<bool>> {
+        let topology = Topology::new(self)?;
+        let count = self.triangles.triangle.len();
+        let vertices = (0..count)
+            .map(|t| topology.triangle_vertices(self, t))
+            .collect::<Vec<_>>();
+        // whether a triangle runs from `a` to `b`
+        let forward = |t: usize, a: usize, b: usize| {
+            let v = vertices[t];
+            (0..3).any(|k| v[k] == a && v[(k + 1) % 3] == b)
+        };
+
+        out.backwards_edges = topology
+            .edges
+            .iter()
+            .filter(|e| {
+                let [a, b] = e.vertices;
+                e.triangles.len() == 2
+                    && forward(e.triangles[0], a, b) == forward(e.triangles[1], a, b)
+            })
+            .count();
+
+        let mut flipped = vec![false; count];
+        let mut visited = vec![false; count];
+        for seed in 0..count {
+            if visited[seed] {
+                continue;
+            }
+            visited[seed] = true;
+            let mut component = vec![seed];
+            let mut closed = true;
+            let mut queue = VecDeque::from([seed]);
+            while let Some(t) = queue.pop_front() {
+                for e in topology.triangle_edges[t].iter().flatten() {
+                    let edge = &topology.edges[*e];
+                    if edge.triangles.len() != 2 {
+                        closed = false;
+                        continue;
+                    }
+                    let n = edge.triangles[if edge.triangles[0] == t { 1 } else { 0 }];
+                    if visited[n] {
+                        continue;
+                    }
+                    let [a, b] = edge.vertices;
+                    let same = forward(t, a, b) == forward(n, a, b);
+                    flipped[n] = flipped[t] ^ same;
+                    visited[n] = true;
+                    component.push(n);
+                    queue.push_back(n);
+                }
+            }
+
+            // a closed shell encloses a positive volume when its faces point out
+            if closed {
+                let volume = component
+                    .iter()
+                    .map(|&t| {
+                        let p = vertices[t].map(|v| {
+                            let v = self.vertices.vertex[v];
+                            Vec3::new(v.x, v.y, v.z)
+                        });
+                        let volume = p[0].dot(&p[1].cross(&p[2]));
+                        if flipped[t] {
+                            -volume
+                        } else {
+                            volume
+                        }
+                    })
+                    .sum::<f64>();
+                if volume < 0. {
+                    for &t in component.iter() {
+                        flipped[t] = !flipped[t];
+                    }
+                }
+            }
+        }
+        Ok(flipped)
+    }
+}
+
+impl OrcaModel {
+    /// Repairs every part of the object at `index`, storing the counts as its `mesh_stat`
+    pub fn repair(&mut self, index: usize, tolerance: f64) -> Result<RepairReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+
+        // every part is repaired aside first, so a failure leaves the object as it was
+        let comps = self.object_components(index)?.clone();
+        let repaired = comps
+            .iter()
+            .map(|comp| {
+                let mut mesh = self.component_mesh(comp)?.clone();
+                let report = mesh.repair(tolerance)?;
+                Ok((mesh, report))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut out = RepairReport::default();
+        for (comp, (mesh, report)) in comps.iter().zip(repaired) {
+            *self.component_mesh_mut(comp)? = mesh;
+            let part = self
+                .md
+                .object
+                .iter_mut()
+                .find(|o| o.id == object_id)
+                .and_then(|o| o.part.iter_mut().find(|p| p.id == comp.objectid));
+            match part {
+                Some(part) => part.mesh_stat = report.to_orca(),
+                None => warn!("Part {} not found in metadata", comp.objectid),
+            }
+            out += report;
+        }
+
+        debug!("repaired object {}: {}", index, out);
+        Ok(out)
+    }
+}
+
+impl Model {
+    /// Repairs the object at `index`
+    ///
+    /// With `md`, each volume is repaired on its own, its range updated and the counts stored
+    /// with it.
+    pub fn repair(
+        &mut self,
+        index: usize,
+        md: Option<&mut PSMetadata>,
+        tolerance: f64,
+    ) -> Result<RepairReport> {
+        let object_id = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let mesh = self.object_mesh_mut(index)?;
+
+        let md_object = md.and_then(|md| md.object.iter_mut().find(|o| o.id == object_id));
+        let Some(md_object) = md_object.filter(|o| !o.volume.is_empty()) else {
+            let mut repaired = mesh.clone();
+            let out = repaired.repair(tolerance)?;
+            *mesh = repaired;
+            debug!("repaired object {}: {}", index, out);
+            return Ok(out);
+        };
+        ensure!(
+            md_object
+                .volume
+                .iter()
+                .all(|v| v.firstid <= v.lastid && v.lastid < mesh.triangles.triangle.len()),
+            "Volume ends past the last triangle"
+        );
+
+        // built aside, so a failure leaves the object as it was
+        let mut new_mesh = Mesh {
+            vertices: crate::mesh::Vertices { vertex: vec![] },
+            triangles: crate::mesh::Triangles { triangle: vec![] },
+        };
+        let mut new_volumes = md_object.volume.clone();
+        let mut out = RepairReport::default();
+        for volume in new_volumes.iter_mut() {
+            let mut part = mesh.sub_mesh(volume.firstid..=volume.lastid);
+            let report = part.repair(tolerance)?;
+            ensure!(
+                !part.triangles.triangle.is_empty(),
+                "Nothing left of a volume after repair"
+            );
+            volume.firstid = new_mesh.triangles.triangle.len();
+            new_mesh.merge(&part);
+            volume.lastid = new_mesh.triangles.triangle.len() - 1;
+            volume.mesh = report.to_ps();
+            out += report;
+        }
+        *mesh = new_mesh;
+        md_object.volume = new_volumes;
+
+        debug!("repaired object {}: {}", index, out);
+        Ok(out)
+    }
+}
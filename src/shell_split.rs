@@ -0,0 +1,454 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::{Matrix4, Vector3};
+
+use crate::{
+    mesh::{Mesh, Triangles, Vertices},
+    metadata::ps_metadata::{self as ps, PSMetadata},
+    model::Model,
+    model_orca::{NewPart, OrcaModel},
+    part_paint::ps_modifier,
+    topology::Topology,
+};
+
+/// What [`OrcaModel::split_shells`] turns each shell into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum SplitTarget {
+    #[default]
+    Objects,
+    Parts,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SplitReport {
+    pub shells: usize,
+    /// indices of the objects made from shells, besides the one split
+    pub objects: Vec<usize>,
+}
+
+impl std::fmt::Display for SplitReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} shells, {} new objects",
+            self.shells,
+            self.objects.len()
+        )
+    }
+}
+
+impl Mesh {
+    /// The connected pieces of the mesh, with triangles and their paint unchanged
+    pub fn shells(&self) -> Result<Vec<Mesh>> {
+        let topology = Topology::new(self)?;
+        Ok(topology
+            .components()
+            .into_iter()
+            .map(|c| self.select(c))
+            .collect())
+    }
+}
+
+/// Moves `mesh` so its bounding box is centered on the origin, returning where the center was
+fn recenter(mesh: &mut Mesh) -> Vector3<f64> {
+    let Some((min, max)) = mesh.bounding_box() else {
+        return Vector3::zeros();
+    };
+    let center = (Vector3::from(min) + Vector3::from(max)) / 2.;
+    for v in mesh.vertices.vertex.iter_mut() {
+        v.x -= center.x;
+        v.y -= center.y;
+        v.z -= center.z;
+    }
+    center
+}
+
+fn translation(offset: &Vector3<f64>) -> Matrix4<f64> {
+    Matrix4::new_translation(offset)
+}
+
+/// The matrix a PrusaSlicer volume keeps in its metadata
+fn volume_matrix(volume: &ps::Volume) -> Result<Option<Matrix4<f64>>> {
+    let Some(matrix) = volume
+        .metadata
+        .iter()
+        .find(|m| m.key.as_deref() == Some("matrix"))
+        .and_then(|m| m.value.as_ref())
+    else {
+        return Ok(None);
+    };
+    let values = matrix
+        .split_whitespace()
+        .map(|s| s.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("Parsing volume matrix")?;
+    ensure!(values.len() == 16, "Volume matrix must be 16 elements");
+    Ok(Some(Matrix4::from_row_slice(&values)))
+}
+
+fn set_volume_matrix(volume: &mut ps::Volume, matrix: &Matrix4<f64>) {
+    let value = Some(crate::utils::matrix_string(matrix));
+    if let Some(m) = volume
+        .metadata
+        .iter_mut()
+        .find(|m| m.key.as_deref() == Some("matrix"))
+    {
+        m.value = value;
+    }
+}
+
+fn set_volume_name(volume: &mut ps::Volume, name: &str) {
+    volume.metadata.retain(|m| m.key.as_deref() != Some("name"));
+    volume.metadata.insert(
+        0,
+        ps::Metadata {
+            ty: "volume".to_string(),
+            key: Some("name".to_string()),
+            value: Some(name.to_string()),
+        },
+    );
+}
+
+impl OrcaModel {
+    /// Splits every normal part of the object at `index` into its connected shells, as parts of
+    /// the object or as objects of their own
+    ///
+    /// Paint comes along with each triangle. Every shell gets its origin at its center, with
+    /// placements changed to keep it where it was. Split into objects, the first shell stays
+    /// at `index` with the modifiers.
+    pub fn split_shells(&mut self, index: usize, target: SplitTarget) -> Result<SplitReport> {
+        let object_id = self
+            .get_objects()
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?
+            .id;
+        let name = self
+            .md
+            .get_object_by_id(object_id)
+            .and_then(|o| o.get_name())
+            .unwrap_or_else(|| "object".to_string());
+        let comps = self.object_components(index)?.clone();
+
+        // the shells of each normal part, centered, with their transform in the object
+        let mut parts = vec![];
+        for (c, comp) in comps.iter().enumerate() {
+            if self
+                .part_subtype(object_id, comp.objectid)
+                .is_some_and(|s| s != "normal_part")
+            {
+                continue;
+            }
+            let comp_transform = comp
+                .transform
+                .as_ref()
+                .map(crate::utils::transform_3mf)
+                .unwrap_or_else(Matrix4::identity);
+            let shells = self
+                .component_mesh(comp)?
+                .shells()?
+                .into_iter()
+                .map(|mut shell| {
+                    let center = recenter(&mut shell);
+                    (shell, comp_transform * translation(&center))
+                })
+                .collect::<Vec<_>>();
+            parts.push((c, shells));
+        }
+        let mut report = SplitReport {
+            shells: parts.iter().map(|(_, s)| s.len()).sum(),
+            ..Default::default()
+        };
+        let most = parts.iter().map(|(_, s)| s.len()).max().unwrap_or(0);
+        ensure!(
+            match target {
+                SplitTarget::Objects => report.shells > 1,
+                SplitTarget::Parts => most > 1,
+            },
+            "Object {} has nothing to split",
+            index
+        );
+
+        match target {
+            SplitTarget::Parts => {
+                for (c, shells) in parts.into_iter().rev().filter(|(_, s)| s.len() > 1) {
+                    let part_id = comps[c].objectid;
+                    let part_name = self
+                        .part_name(object_id, part_id)
+                        .unwrap_or_else(|| name.clone());
+                    let extruder = self.part_extruder(object_id, part_id);
+                    let transforms = shells.iter().map(|(_, t)| *t).collect::<Vec<_>>();
+                    let new_parts = shells
+                        .into_iter()
+                        .enumerate()
+                        .map(|(k, (mesh, _))| NewPart {
+                            name: format!("{} {}", part_name, k + 1),
+                            mesh,
+                            extruder,
+                        })
+                        .collect();
+                    self.replace_part(index, c, new_parts)?;
+                    for (k, transform) in transforms.iter().enumerate() {
+                        self.set_component_transform(index, c + k, transform)?;
+                    }
+                }
+            }
+            SplitTarget::Objects => {
+                let item = self.item_transform(index)?;
+                let mut shells = parts
+                    .iter()
+                    .flat_map(|(c, shells)| shells.iter().map(move |s| (*c, s)));
+                let (first_comp, (first, first_transform)) =
+                    shells.next().context("Object has no shells")?;
+
+                for (n, (c, (shell, transform))) in shells.enumerate() {
+                    let copy = self.duplicate_object(index, &format!("{} {}", name, n + 2))?;
+                    let comp = self.object_components(copy)?[c].clone();
+                    *self.component_mesh_mut(&comp)? = shell.clone();
+                    for other in (0..comps.len()).rev().filter(|&o| o != c) {
+                        self.replace_part(copy, other, vec![])?;
+                    }
+                    // the object's origin moves to the shell
+                    let offset = transform.column(3).xyz();
+                    self.set_item_transform(copy, &(item * translation(&offset)))?;
+                    self.set_component_transform(copy, 0, &(translation(&-offset) * transform))?;
+                    report.objects.push(copy);
+                }
+
+                let comp = comps[first_comp].clone();
+                *self.component_mesh_mut(&comp)? = first.clone();
+                self.set_component_transform(index, first_comp, first_transform)?;
+                for (c, _) in parts.iter().rev().filter(|(c, _)| *c != first_comp) {
+                    self.replace_part(index, *c, vec![])?;
+                }
+            }
+        }
+
+        debug!("split object {}: {}", index, report);
+        Ok(report)
+    }
+}
+
+impl Model {
+    /// Splits the object at `index` into its connected shells, with each volume in `md` split on
+    /// its own
+    ///
+    /// See [`OrcaModel::split_shells`]. Splitting into parts needs `md` to list them.
+    pub fn split_shells(
+        &mut self,
+        index: usize,
+        md: Option<&mut PSMetadata>,
+        target: SplitTarget,
+    ) -> Result<SplitReport> {
+        let mut md = md;
+        let object = self
+            .resources
+            .object
+            .get(index)
+            .with_context(|| format!("No object at index {}", index))?;
+        let object_id = object.id;
+        let mesh = self.object_mesh_mut(index)?.clone();
+        let md_object = md
+            .as_deref()
+            .and_then(|md| md.get_object_by_id(object_id))
+            .filter(|o| !o.volume.is_empty())
+            .cloned();
+        let name = md_object
+            .as_ref()
+            .and_then(|o| o.get_name())
+            .or_else(|| self.resources.object[index].name.clone())
+            .unwrap_or_else(|| "object".to_string());
+        ensure!(
+            md_object.is_some() || target == SplitTarget::Objects,
+            "Splitting object {} into parts needs its volumes",
+            index
+        );
+        let volumes = match md_object {
+            Some(o) => {
+                ensure!(
+                    o.volume
+                        .iter()
+                        .all(|v| v.firstid <= v.lastid && v.lastid < mesh.triangles.triangle.len()),
+                    "Volume ends past the last triangle"
+                );
+                o.volume
+            }
+            None => vec![],
+        };
+
+        // each volume with its shells, or whole when it is a modifier
+        let mut parts = vec![];
+        if volumes.is_empty() {
+            parts.push((None, mesh.shells()?));
+        }
+        for volume in volumes.iter() {
+            let part = mesh.sub_mesh(volume.firstid..=volume.lastid);
+            let shells = if ps_modifier(volume) {
+                vec![part]
+            } else {
+                part.shells()?
+            };
+            parts.push((Some(volume), shells));
+        }
+        let model_parts = parts
+            .iter()
+            .filter(|(v, _)| v.is_none_or(|v| !ps_modifier(v)))
+            .collect::<Vec<_>>();
+        let mut report = SplitReport {
+            shells: model_parts.iter().map(|(_, s)| s.len()).sum(),
+            ..Default::default()
+        };
+        let most = model_parts.iter().map(|(_, s)| s.len()).max().unwrap_or(0);
+        ensure!(
+            match target {
+                SplitTarget::Objects => report.shells > 1,
+                SplitTarget::Parts => most > 1,
+            },
+            "Object {} has nothing to split",
+            index
+        );
+
+        let empty = || Mesh {
+            vertices: Vertices { vertex: vec![] },
+            triangles: Triangles { triangle: vec![] },
+        };
+        // a volume for `shell` in an object mesh, with its matrix moved to the shell's center
+        let volume_for = |volume: &ps::Volume,
+                          shell: &Mesh,
+                          firstid: usize,
+                          offset: &Vector3<f64>,
+                          shell_name: Option<&str>|
+         -> Result<ps::Volume> {
+            let mut out = ps::Volume {
+                firstid,
+                lastid: firstid + shell.triangles.triangle.len() - 1,
+                mesh: ps::Mesh::default(),
+                ..volume.clone()
+            };
+            if let Some(matrix) = volume_matrix(volume)? {
+                let inverse = matrix
+                    .try_inverse()
+                    .context("Volume matrix can't be inverted")?;
+                let mut local = shell.clone();
+                for v in local.vertices.vertex.iter_mut() {
+                    let p = (inverse * nalgebra::Vector4::new(v.x, v.y, v.z, 1.)).xyz();
+                    (v.x, v.y, v.z) = (p.x, p.y, p.z);
+                }
+                let center = recenter(&mut local);
+                set_volume_matrix(
+                    &mut out,
+                    &(translation(&-offset) * matrix * translation(&center)),
+                );
+            }
+            if let Some(shell_name) = shell_name {
+                set_volume_name(&mut out, shell_name);
+            }
+            Ok(out)
+        };
+        let volume_name = |volume: &ps::Volume| {
+            volume
+                .metadata
+                .iter()
+                .find(|m| m.key.as_deref() == Some("name"))
+                .and_then(|m| m.value.clone())
+                .unwrap_or_else(|| name.clone())
+        };
+
+        match target {
+            SplitTarget::Parts => {
+                let mut new_mesh = empty();
+                let mut new_volumes = vec![];
+                for (volume, shells) in parts.iter() {
+                    let volume = volume.context("Volume missing")?;
+                    let split = shells.len() > 1;
+                    for (k, shell) in shells.iter().enumerate() {
+                        let shell_name = format!("{} {}", volume_name(volume), k + 1);
+                        let firstid = new_mesh.triangles.triangle.len();
+                        new_mesh.merge(shell);
+                        new_volumes.push(volume_for(
+                            volume,
+                            shell,
+                            firstid,
+                            &Vector3::zeros(),
+                            split.then_some(shell_name.as_str()),
+                        )?);
+                    }
+                }
+                *self.object_mesh_mut(index)? = new_mesh;
+                if let Some(md_object) = md
+                    .as_deref_mut()
+                    .and_then(|md| md.object.iter_mut().find(|o| o.id == object_id))
+                {
+                    md_object.volume = new_volumes;
+                }
+            }
+            SplitTarget::Objects => {
+                let item = self.item_transform(index)?;
+                let mut shells = parts
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (v, _))| v.is_none_or(|v| !ps_modifier(v)))
+                    .flat_map(|(p, (v, shells))| shells.iter().map(move |s| (p, *v, s)));
+                let (first_part, _, first) = shells.next().context("Object has no shells")?;
+
+                for (n, (_, volume, shell)) in shells.enumerate() {
+                    let mut centered = shell.clone();
+                    let offset = recenter(&mut centered);
+                    // the volume matrix is found from the shell where it was, then moved with it
+                    let volumes = match volume {
+                        Some(v) => vec![volume_for(v, shell, 0, &offset, None)?],
+                        None => vec![],
+                    };
+                    let copy = self.duplicate_object(
+                        index,
+                        md.as_deref_mut(),
+                        &format!("{} {}", name, n + 2),
+                    )?;
+                    let copy_id = self.resources.object[copy].id;
+                    self.set_item_transform(copy, &(item * translation(&offset)))?;
+                    if let Some(md_object) = md
+                        .as_deref_mut()
+                        .and_then(|md| md.object.iter_mut().find(|o| o.id == copy_id))
+                    {
+                        md_object.volume = volumes;
+                    }
+                    *self.object_mesh_mut(copy)? = centered;
+                    report.objects.push(copy);
+                }
+
+                // the first shell and the modifiers stay
+                let mut new_mesh = empty();
+                let mut new_volumes = vec![];
+                for (p, (volume, shells)) in parts.iter().enumerate() {
+                    let kept = if p == first_part {
+                        first
+                    } else if volume.is_some_and(ps_modifier) {
+                        &shells[0]
+                    } else {
+                        continue;
+                    };
+                    let firstid = new_mesh.triangles.triangle.len();
+                    new_mesh.merge(kept);
+                    if let Some(volume) = volume {
+                        new_volumes.push(volume_for(
+                            volume,
+                            kept,
+                            firstid,
+                            &Vector3::zeros(),
+                            None,
+                        )?);
+                    }
+                }
+                *self.object_mesh_mut(index)? = new_mesh;
+                if let Some(md_object) =
+                    md.and_then(|md| md.object.iter_mut().find(|o| o.id == object_id))
+                {
+                    md_object.volume = new_volumes;
+                }
+            }
+        }
+
+        debug!("split object {}: {}", index, report);
+        Ok(report)
+    }
+}
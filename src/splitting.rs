@@ -9,7 +9,7 @@ use crate::{model::Object, splitting::rvec3::RVec3};
 
 pub type Vec3 = nalgebra::Vector3<f64>;
 
-mod rvec3 {
+pub(crate) mod rvec3 {
     use std::collections::HashSet;
 
     use super::Vec3;
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{instancing::quantize, mesh::Mesh, splitting::Vec3};
+
+/// An edge between two welded vertices, with the triangles using it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    /// welded vertices, lower first
+    pub vertices: [usize; 2],
+    pub triangles: Vec<usize>,
+}
+
+/// Connectivity of a [`Mesh`], with vertices welded by position
+///
+/// Triangles keep their index in the mesh, so results can be written back into
+/// `mesh.triangles.triangle` with their paint in place.
+#[derive(Debug, Clone)]
+pub struct Topology {
+    /// the welded vertex of each mesh vertex, the first vertex at its position
+    pub vertex_map: Vec<usize>,
+    pub edges: Vec<Edge>,
+    /// edges from corner 1 to 2, 2 to 3 and 3 to 1 of each triangle, `None` where the corners
+    /// were welded together
+    pub triangle_edges: Vec<[Option<usize>; 3]>,
+}
+
+impl Topology {
+    pub fn new(mesh: &Mesh) -> Result<Self> {
+        let vertices = &mesh.vertices.vertex;
+        let mut welded = HashMap::new();
+        let vertex_map = vertices
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                *welded
+                    .entry(quantize(&Vec3::new(v.x, v.y, v.z)))
+                    .or_insert(i)
+            })
+            .collect::<Vec<_>>();
+
+        let mut edges: Vec<Edge> = vec![];
+        let mut index = HashMap::new();
+        let mut triangle_edges = vec![];
+        for (t, tri) in mesh.triangles.triangle.iter().enumerate() {
+            let v = [tri.v1, tri.v2, tri.v3];
+            ensure!(
+                v.iter().all(|&v| v < vertices.len()),
+                "Triangle {} has a vertex out of range",
+                t
+            );
+            let v = v.map(|v| vertex_map[v]);
+            let mut out = [None; 3];
+            for k in 0..3 {
+                let (a, b) = (v[k], v[(k + 1) % 3]);
+                if a == b {
+                    continue;
+                }
+                let key = [a.min(b), a.max(b)];
+                let e = *index.entry(key).or_insert_with(|| {
+                    edges.push(Edge {
+                        vertices: key,
+                        triangles: vec![],
+                    });
+                    edges.len() - 1
+                });
+                if !edges[e].triangles.contains(&t) {
+                    edges[e].triangles.push(t);
+                }
+                out[k] = Some(e);
+            }
+            triangle_edges.push(out);
+        }
+
+        Ok(Self {
+            vertex_map,
+            edges,
+            triangle_edges,
+        })
+    }
+
+    /// The welded vertices of triangle `t`
+    pub fn triangle_vertices(&self, mesh: &Mesh, t: usize) -> [usize; 3] {
+        let tri = &mesh.triangles.triangle[t];
+        [tri.v1, tri.v2, tri.v3].map(|v| self.vertex_map[v])
+    }
+
+    /// Triangles sharing an edge with triangle `t`
+    pub fn neighbors(&self, t: usize) -> Vec<usize> {
+        let mut out = vec![];
+        for e in self.triangle_edges[t].iter().flatten() {
+            for &n in self.edges[*e].triangles.iter() {
+                if n != t && !out.contains(&n) {
+                    out.push(n);
+                }
+            }
+        }
+        out
+    }
+
+    /// [`Topology::neighbors`] of every triangle
+    pub fn all_neighbors(&self) -> Vec<Vec<usize>> {
+        (0..self.triangle_edges.len())
+            .map(|t| self.neighbors(t))
+            .collect()
+    }
+
+    /// Edges with a single triangle, around holes and the rim of open surfaces
+    pub fn boundary_edges(&self) -> Vec<usize> {
+        (0..self.edges.len())
+            .filter(|&e| self.edges[e].triangles.len() == 1)
+            .collect()
+    }
+
+    /// Edges shared by more than two triangles
+    pub fn non_manifold_edges(&self) -> Vec<usize> {
+        (0..self.edges.len())
+            .filter(|&e| self.edges[e].triangles.len() > 2)
+            .collect()
+    }
+
+    /// Whether every edge has exactly two triangles
+    pub fn is_closed(&self) -> bool {
+        self.edges.iter().all(|e| e.triangles.len() == 2)
+    }
+
+    /// Triangles connected through shared edges, each group in ascending order and the groups
+    /// by their first triangle
+    pub fn components(&self) -> Vec<Vec<usize>> {
+        let mut component = vec![usize::MAX; self.triangle_edges.len()];
+        let mut out = vec![];
+        for seed in 0..component.len() {
+            if component[seed] != usize::MAX {
+                continue;
+            }
+            let id = out.len();
+            component[seed] = id;
+            let mut group = vec![seed];
+            let mut queue = VecDeque::from([seed]);
+            while let Some(t) = queue.pop_front() {
+                for n in self.neighbors(t) {
+                    if component[n] == usize::MAX {
+                        component[n] = id;
+                        group.push(n);
+                        queue.push_back(n);
+                    }
+                }
+            }
+            group.sort_unstable();
+            out.push(group);
+        }
+        out
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use tracing::{debug, error, info, trace, warn};
+
+use nalgebra::{Matrix4, Point3, Rotation3, Vector3};
+
+use crate::{
+    mesh::Mesh, metadata::ps_metadata::PSMetadata, model::Model, model_orca::OrcaModel,
+    part_paint::ps_modifier, utils::deg_to_rad,
+};
+
+/// A change of placement for whole objects, applied to their build items
+///
+/// Scaling and rotation happen around the object's origin along the world axes, then the
+/// object is moved. Part and volume matrices are relative to their object, so they stay as they
+/// are, and so does the paint.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ObjectTransform {
+    pub scale: [f64; 3],
+    /// degrees around X, then Y, then Z
+    pub rotate: [f64; 3],
+    /// mm
+    pub translate: [f64; 3],
+    /// puts the lowest point of the object at Z = 0 afterwards
+    pub drop_to_bed: bool,
+}
+
+impl Default for ObjectTransform {
+    fn default() -> Self {
+        Self {
+            scale: [1.; 3],
+            rotate: [0.; 3],
+            translate: [0.; 3],
+            drop_to_bed: false,
+        }
+    }
+}
+
+impl ObjectTransform {
+    /// The new item transform for an object placed by `item`, before dropping it to the bed
+    pub fn apply(&self, item: &Matrix4<f64>) -> Result<Matrix4<f64>> {
+        ensure!(
+            self.scale.iter().all(|s| s.is_finite() && *s != 0.),
+            "Scale factors must be non-zero"
+        );
+        let [x, y, z] = self.rotate.map(deg_to_rad);
+        let rotation = Rotation3::from_euler_angles(x, y, z).to_homogeneous();
+        let scale = Matrix4::new_nonuniform_scaling(&Vector3::from(self.scale));
+        let origin = item.column(3).xyz();
+
+        Ok(
+            Matrix4::new_translation(&(origin + Vector3::from(self.translate)))
+                * rotation
+                * scale
+                * Matrix4::new_translation(&-origin)
+                * item,
+        )
+    }
+}
+
+/// The lowest Z of `mesh` in world space, placed by `transform`
+fn lowest(mesh: &Mesh, transform: &Matrix4<f64>) -> Option<f64> {
+    mesh.vertices
+        .vertex
+        .iter()
+        .map(|v| transform.transform_point(&Point3::new(v.x, v.y, v.z)).z)
+        .min_by(f64::total_cmp)
+}
+
+impl OrcaModel {
+    /// Applies `transform` to the object at `index`
+    pub fn transform_object(&mut self, index: usize, transform: &ObjectTransform) -> Result<()> {
+        let item = transform.apply(&self.item_transform(index)?)?;
+        self.set_item_transform(index, &item)?;
+
+        if transform.drop_to_bed {
+            let object_id = self.get_objects()[index].id;
+            let mut z = None::<f64>;
+            for comp in self.object_components(index)?.iter() {
+                if self
+                    .part_subtype(object_id, comp.objectid)
+                    .is_some_and(|s| s != "normal_part")
+                {
+                    continue;
+                }
+                let world = self.component_world_transform(index, comp)?;
+                if let Some(low) = lowest(self.component_mesh(comp)?, &world) {
+                    z = Some(z.map_or(low, |z| z.min(low)));
+                }
+            }
+            let z = z.with_context(|| format!("Object {} has no model parts", index))?;
+            let item = Matrix4::new_translation(&Vector3::new(0., 0., -z)) * item;
+            self.set_item_transform(index, &item)?;
+        }
+
+        debug!("transformed object {}", index);
+        Ok(())
+    }
+}
+
+impl Model {
+    /// Applies `transform` to the object at `index`, leaving the modifiers in `md` out when
+    /// finding its lowest point
+    pub fn transform_object(
+        &mut self,
+        index: usize,
+        md: Option<&PSMetadata>,
+        transform: &ObjectTransform,
+    ) -> Result<()> {
+        let item = transform.apply(&self.item_transform(index)?)?;
+        self.set_item_transform(index, &item)?;
+
+        if transform.drop_to_bed {
+            let object_id = self.resources.object[index].id;
+            let mesh = self.object_mesh_mut(index)?;
+            let volumes = md
+                .and_then(|md| md.get_object_by_id(object_id))
+                .map(|o| o.volume.clone())
+                .unwrap_or_default();
+            let z = if volumes.is_empty() {
+                lowest(mesh, &item)
+            } else {
+                volumes
+                    .iter()
+                    .filter(|v| !ps_modifier(v) && v.lastid < mesh.triangles.triangle.len())
+                    .filter_map(|v| lowest(&mesh.sub_mesh(v.firstid..=v.lastid), &item))
+                    .min_by(f64::total_cmp)
+            };
+            let z = z.with_context(|| format!("Object {} has no model parts", index))?;
+            let item = Matrix4::new_translation(&Vector3::new(0., 0., -z)) * item;
+            self.set_item_transform(index, &item)?;
+        }
+
+        debug!("transformed object {}", index);
+        Ok(())
+    }
+}
@@ -14,7 +14,11 @@ use std::{
     time::Instant,
 };
 
-use crate::{model_orca::OrcaModel, paint_convert::PaintConvertInfo, ProcessingEvent};
+use crate::{
+    mirror::MirrorPlane, model_orca::OrcaModel, paint_convert::PaintConvertInfo,
+    paint_regions::MorphOp, paint_sharing::PaintSidecar, paint_simplify::SimplifyMode,
+    ProcessingEvent,
+};
 
 use self::ui_types::*;
 
@@ -57,6 +61,7 @@ impl eframe::App for App {
                     Tab::InstancePaint,
                     "Paint Instancing",
                 );
+                ui.selectable_value(&mut self.current_tab, Tab::PaintTools, "Paint Tools");
             });
             // ui.separator();
         });
@@ -65,6 +70,10 @@ impl eframe::App for App {
             egui::CentralPanel::default().show(ctx, |ui| {
                 self.show_instancing(ctx, ui);
             });
+        } else if self.current_tab == Tab::PaintTools {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                self.show_paint_tools(ctx, ui);
+            });
         } else if self.current_tab == Tab::ColorConvert {
             egui::CentralPanel::default().show(ctx, |ui| {
                 self.show_color_conversion(ctx, ui);
@@ -115,6 +124,7 @@ impl eframe::App for App {
                     Tab::Conversion => self.show_conversion(ctx, ui),
                     Tab::Splitting => self.show_splitting(ctx, ui),
                     Tab::InstancePaint => self.show_instancing(ctx, ui),
+                    Tab::PaintTools => self.show_paint_tools(ctx, ui),
                 }
             });
 
@@ -559,25 +569,171 @@ impl App {
                     });
             });
 
-            if let Some(from) = loaded.from_object {
-                let mut valid = loaded.to_objects.len() > 0;
-                let from_len = loaded.orca_model.sub_objects[from].1.len();
-                for (i, to) in loaded.to_objects.iter().enumerate() {
-                    if *to {
-                        if from_len != loaded.orca_model.sub_objects[i].1.len() {
-                            valid = false;
-                            break;
+            ui.horizontal(|ui| {
+                if let Some(from) = loaded.from_object {
+                    if ui.button("Export paint of source...").clicked() {
+                        if let Some(path) = rfd::FileDialog::new()
+                            .add_filter("paint", &["json"])
+                            .set_file_name(format!("{}.paint.json", loaded.objects[from].1))
+                            .save_file()
+                        {
+                            match PaintSidecar::from_orca(&loaded.orca_model, from) {
+                                Ok(sidecar) => {
+                                    if let Err(e) = sidecar.save(&path) {
+                                        error!("Error saving paint file: {:?}", e);
+                                    }
+                                }
+                                Err(e) => error!("Error exporting paint: {:?}", e),
+                            }
+                        }
+                    }
+                }
+
+                let any_to = loaded.to_objects.iter().any(|to| *to);
+                if any_to && ui.button("Apply paint file to selected...").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("paint", &["json"])
+                        .pick_file()
+                    {
+                        match PaintSidecar::load(&path) {
+                            Ok(sidecar) => {
+                                for (to, selected) in loaded.to_objects.iter().enumerate() {
+                                    if !*selected || Some(to) == loaded.from_object {
+                                        continue;
+                                    }
+                                    if let Err(e) = sidecar.apply_orca(&mut loaded.orca_model, to) {
+                                        error!("Error applying paint file: {:?}", e);
+                                    }
+                                }
+
+                                save_orca_output(
+                                    self.output_folder.as_ref(),
+                                    &loaded.path,
+                                    &loaded.orca_model,
+                                    "painted",
+                                );
+                            }
+                            Err(e) => error!("Error loading paint file: {:?}", e),
+                        }
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Export paint of PrusaSlicer project...")
+                    .clicked()
+                {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("3mf", &["3mf"])
+                        .pick_file()
+                    {
+                        export_ps_paint(self.output_folder.as_ref(), &path);
+                    }
+                }
+
+                if ui
+                    .button("Apply paint file to PrusaSlicer project...")
+                    .clicked()
+                {
+                    let paint = rfd::FileDialog::new()
+                        .add_filter("paint", &["json"])
+                        .pick_file();
+                    let project = paint.as_ref().and_then(|_| {
+                        rfd::FileDialog::new()
+                            .add_filter("3mf", &["3mf"])
+                            .pick_file()
+                    });
+                    if let (Some(paint), Some(project)) = (paint, project) {
+                        match PaintSidecar::load(&paint) {
+                            Ok(sidecar) => {
+                                apply_ps_paint(self.output_folder.as_ref(), &sidecar, &project)
+                            }
+                            Err(e) => error!("Error loading paint file: {:?}", e),
                         }
                     }
                 }
+            });
+
+            if let Some(from) = loaded.from_object {
+                let valid = loaded
+                    .to_objects
+                    .iter()
+                    .enumerate()
+                    .any(|(i, to)| *to && i != from);
+
+                /// the preview is stale once the selection changes
+                let previewed = loaded
+                    .pairing_preview
+                    .as_ref()
+                    .map(|p| p.from == from && p.to == loaded.to_objects)
+                    .unwrap_or(false);
+
+                if !valid {
+                    ui.label("Invalid selection");
+                } else if ui.button("Preview pairing").clicked() {
+                    let pairings = loaded
+                        .to_objects
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, to)| **to && *i != from)
+                        .map(|(to, _)| {
+                            let pairing = loaded
+                                .orca_model
+                                .pair_components(from, to)
+                                .map_err(|e| format!("{:?}", e));
+                            (to, pairing)
+                        })
+                        .collect();
+
+                    loaded.pairing_preview = Some(PairingPreview {
+                        from,
+                        to: loaded.to_objects.clone(),
+                        pairings,
+                    });
+                }
 
-                if valid && ui.button("Apply").clicked() {
-                    for (to, &b) in loaded.to_objects.iter().enumerate() {
-                        if b {
-                            if let Err(e) = loaded.orca_model.copy_paint(from, to) {
-                                error!("Error copying paint: {:?}", e);
+                if let Some(preview) = loaded.pairing_preview.as_ref().filter(|_| previewed) {
+                    ui.group(|ui| {
+                        for (to, pairing) in preview.pairings.iter() {
+                            ui.label(format!("{}:", loaded.objects[*to].1));
+                            match pairing {
+                                Ok(pairing) => {
+                                    for pair in pairing.pairs.iter() {
+                                        ui.monospace(format!("  {}", pair));
+                                    }
+                                    for (i, name) in pairing.unmatched_to.iter() {
+                                        ui.monospace(format!(
+                                            "  no source for {} ({})",
+                                            name.as_deref().unwrap_or("unnamed"),
+                                            i
+                                        ));
+                                    }
+                                    for (i, name) in pairing.unmatched_from.iter() {
+                                        ui.monospace(format!(
+                                            "  no target for {} ({}), its paint is left out",
+                                            name.as_deref().unwrap_or("unnamed"),
+                                            i
+                                        ));
+                                    }
+                                }
+                                Err(e) => {
+                                    ui.monospace(format!("  {}", e));
+                                }
                             }
                         }
+                    });
+                }
+
+                if previewed && ui.button("Apply").clicked() {
+                    let preview = loaded.pairing_preview.take().unwrap();
+                    for (to, pairing) in preview.pairings.iter() {
+                        let Ok(pairing) = pairing else {
+                            continue;
+                        };
+                        if let Err(e) = loaded.orca_model.copy_paint_paired(from, *to, pairing) {
+                            error!("Error copying paint: {:?}", e);
+                        }
                     }
 
                     let path = self.input_files_instancing[0].clone();
@@ -598,9 +754,55 @@ impl App {
                     debug!("Saving to: {:?}", output_file_path);
 
                     crate::save_load::save_orca_3mf(&output_file_path, &loaded.orca_model).unwrap();
-                } else if !valid {
-                    ui.label("Invalid selection");
                 }
+
+                ui.horizontal(|ui| {
+                    let axes = ["X", "Y", "Z"];
+                    egui::ComboBox::from_label("Mirror plane")
+                        .selected_text(loaded.mirror_axis.map_or("Detect", |a| axes[a]))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut loaded.mirror_axis, None, "Detect");
+                            for (i, name) in axes.iter().enumerate() {
+                                ui.selectable_value(&mut loaded.mirror_axis, Some(i), *name);
+                            }
+                        });
+
+                    if ui.button("Mirror within source").clicked() {
+                        match loaded.orca_model.mirror_paint_symmetric(
+                            from,
+                            loaded.mirror_axis,
+                            None,
+                        ) {
+                            Ok(report) => info!("Object {}: {}", from, report),
+                            Err(e) => error!("Error mirroring paint: {:?}", e),
+                        }
+                        save_orca_output(
+                            self.output_folder.as_ref(),
+                            &loaded.path,
+                            &loaded.orca_model,
+                            "symmetric",
+                        );
+                    }
+
+                    if valid && ui.button("Apply mirrored").clicked() {
+                        let plane = loaded.mirror_axis.map(MirrorPlane::axis);
+                        for (to, selected) in loaded.to_objects.iter().enumerate() {
+                            if !*selected || to == from {
+                                continue;
+                            }
+                            match loaded.orca_model.mirror_paint(from, to, plane) {
+                                Ok(report) => info!("Object {}: {}", to, report),
+                                Err(e) => error!("Error mirroring paint: {:?}", e),
+                            }
+                        }
+                        save_orca_output(
+                            self.output_folder.as_ref(),
+                            &loaded.path,
+                            &loaded.orca_model,
+                            "mirrored",
+                        );
+                    }
+                });
             }
 
             // ui.add(egui::Image::new("file://preview.png"));
@@ -664,6 +866,308 @@ impl App {
         //
     }
 
+    fn show_paint_tools(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Choose output folder..").clicked() {
+                if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                    self.output_folder = Some(path);
+                }
+            }
+
+            if let Some(path) = &self.output_folder {
+                ui.monospace(path.display().to_string());
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Load file...").clicked() {
+                let mut picker = rfd::FileDialog::new().add_filter("filter", &["3mf"]);
+                if let Some(path) = picker.pick_file() {
+                    self.current_input_files_mut().clear();
+                    self.current_input_files_mut().push(path);
+                }
+            }
+
+            if let Some(path) = self.current_input_files().first() {
+                ui.monospace(path.display().to_string());
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if let Some(path) = self.current_input_files().first() {
+                if ui.button("Load input file").clicked() {
+                    match crate::save_load::load_3mf_orca_noconvert(path) {
+                        Ok(model) => {
+                            self.loaded_paint_tools_file =
+                                Some(LoadedPaintToolsFile::new(path.clone(), model));
+                        }
+                        Err(e) => error!("Error loading {:?}: {:?}", path, e),
+                    }
+                }
+            }
+
+            if let Some(loaded) = self.loaded_paint_tools_file.as_ref() {
+                ui.label("Loaded:");
+                ui.monospace(loaded.path.display().to_string());
+            }
+        });
+
+        let Some(loaded) = self.loaded_paint_tools_file.as_mut() else {
+            return;
+        };
+
+        ui.group(|ui| {
+            TableBuilder::new(ui)
+                .striped(true)
+                .column(Column::auto().at_least(20.))
+                .column(Column::auto().at_least(30.))
+                .column(Column::auto().at_least(250.))
+                .column(Column::auto().at_least(20.))
+                .header(20., |mut header| {
+                    header.col(|ui| {
+                        ui.label("Process");
+                    });
+                    header.col(|ui| {
+                        ui.label("Parts");
+                    });
+                    header.col(|ui| {
+                        ui.label("Name");
+                    });
+                    header.col(|ui| {
+                        ui.label("Is painted");
+                    });
+                })
+                .body(|mut body| {
+                    for (id, selected) in loaded.selected.iter_mut().enumerate() {
+                        body.row(20., |mut row| {
+                            row.col(|ui| {
+                                ui.add(egui::Checkbox::without_text(selected));
+                            });
+                            row.col(|ui| {
+                                ui.label(format!("{}", loaded.orca_model.sub_objects[id].1.len()));
+                            });
+                            row.col(|ui| {
+                                ui.label(loaded.objects[id].1.clone());
+                            });
+                            row.col(|ui| {
+                                if loaded.objects[id].2 {
+                                    ui.label("painted");
+                                }
+                            });
+                        });
+                    }
+                });
+        });
+
+        ui.group(|ui| {
+            ui.label("Simplify paint");
+
+            let is_prune = matches!(self.simplify_mode, SimplifyMode::Prune { .. });
+            if ui.radio(!is_prune, "Majority color per triangle").clicked() {
+                self.simplify_mode = SimplifyMode::Majority;
+            }
+            if ui.radio(is_prune, "Prune small subdivisions").clicked() && !is_prune {
+                self.simplify_mode = SimplifyMode::Prune {
+                    min_edge: Some(0.4),
+                    max_depth: None,
+                };
+            }
+
+            if let SimplifyMode::Prune {
+                min_edge,
+                max_depth,
+            } = &mut self.simplify_mode
+            {
+                ui.horizontal(|ui| {
+                    let mut enabled = min_edge.is_some();
+                    let mut value = min_edge.unwrap_or(0.4);
+                    ui.checkbox(&mut enabled, "Min edge length (mm)");
+                    ui.add_enabled(
+                        enabled,
+                        egui::DragValue::new(&mut value)
+                            .speed(0.05)
+                            .range(0.01..=100.),
+                    );
+                    *min_edge = enabled.then_some(value);
+                });
+                ui.horizontal(|ui| {
+                    let mut enabled = max_depth.is_some();
+                    let mut value = max_depth.unwrap_or(4);
+                    ui.checkbox(&mut enabled, "Max depth");
+                    ui.add_enabled(enabled, egui::DragValue::new(&mut value).range(0..=16));
+                    *max_depth = enabled.then_some(value);
+                });
+            }
+
+            let any_selected = loaded.selected.iter().any(|s| *s);
+            if ui
+                .add_enabled(any_selected, egui::Button::new("Simplify selected"))
+                .clicked()
+            {
+                loaded.reports.clear();
+                for (i, selected) in loaded.selected.iter().enumerate() {
+                    if !*selected {
+                        continue;
+                    }
+                    let name = &loaded.objects[i].1;
+                    match loaded.orca_model.simplify_paint(i, self.simplify_mode) {
+                        Ok(report) => loaded.reports.push(format!("{}: {}", name, report)),
+                        Err(e) => {
+                            error!("Error simplifying paint: {:?}", e);
+                            loaded.reports.push(format!("{}: {}", name, e));
+                        }
+                    }
+                }
+
+                save_orca_output(
+                    self.output_folder.as_ref(),
+                    &loaded.path,
+                    &loaded.orca_model,
+                    "simplified",
+                );
+            }
+        });
+
+        ui.group(|ui| {
+            ui.label("Remove paint islands");
+
+            let options = &mut self.island_options;
+            ui.horizontal(|ui| {
+                let mut enabled = options.min_area.is_some();
+                let mut value = options.min_area.unwrap_or(1.);
+                ui.checkbox(&mut enabled, "Smaller than (mm²)");
+                ui.add_enabled(
+                    enabled,
+                    egui::DragValue::new(&mut value)
+                        .speed(0.1)
+                        .range(0.01..=1000.),
+                );
+                options.min_area = enabled.then_some(value);
+            });
+            ui.horizontal(|ui| {
+                let mut enabled = options.nozzle.is_some();
+                let mut value = options.nozzle.unwrap_or(0.4);
+                ui.checkbox(&mut enabled, "Narrower than nozzle (mm)");
+                ui.add_enabled(
+                    enabled,
+                    egui::DragValue::new(&mut value)
+                        .speed(0.05)
+                        .range(0.01..=10.),
+                );
+                options.nozzle = enabled.then_some(value);
+            });
+
+            let any_selected = loaded.selected.iter().any(|s| *s);
+            ui.horizontal(|ui| {
+                let find = ui
+                    .add_enabled(any_selected, egui::Button::new("Find islands"))
+                    .clicked();
+                let remove = ui
+                    .add_enabled(any_selected, egui::Button::new("Remove islands"))
+                    .clicked();
+                if !find && !remove {
+                    return;
+                }
+
+                loaded.reports.clear();
+                for (i, selected) in loaded.selected.iter().enumerate() {
+                    if !*selected {
+                        continue;
+                    }
+                    let name = &loaded.objects[i].1;
+                    match loaded
+                        .orca_model
+                        .remove_paint_islands(i, &self.island_options, find)
+                    {
+                        Ok(islands) => {
+                            if islands.is_empty() {
+                                loaded.reports.push(format!("{}: no islands", name));
+                            }
+                            for island in islands {
+                                loaded.reports.push(format!("{}: {}", name, island));
+                            }
+                        }
+                        Err(e) => {
+                            error!("Error removing paint islands: {:?}", e);
+                            loaded.reports.push(format!("{}: {}", name, e));
+                        }
+                    }
+                }
+
+                if remove {
+                    save_orca_output(
+                        self.output_folder.as_ref(),
+                        &loaded.path,
+                        &loaded.orca_model,
+                        "cleaned",
+                    );
+                }
+            });
+        });
+
+        ui.group(|ui| {
+            ui.label("Grow or shrink paint");
+
+            let options = &mut self.morph_options;
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut options.op, MorphOp::Dilate, "Grow");
+                ui.radio_value(&mut options.op, MorphOp::Erode, "Shrink");
+            });
+            ui.horizontal(|ui| {
+                ui.label("Filament");
+                ui.add(egui::DragValue::new(&mut options.state).range(1..=16));
+                ui.label("by (mm)");
+                ui.add(
+                    egui::DragValue::new(&mut options.distance)
+                        .speed(0.05)
+                        .range(0.01..=50.),
+                );
+                ui.label("detail (mm)");
+                ui.add(
+                    egui::DragValue::new(&mut options.resolution)
+                        .speed(0.01)
+                        .range(0.02..=5.),
+                );
+            });
+
+            let any_selected = loaded.selected.iter().any(|s| *s);
+            if ui
+                .add_enabled(any_selected, egui::Button::new("Apply to selected"))
+                .clicked()
+            {
+                loaded.reports.clear();
+                for (i, selected) in loaded.selected.iter().enumerate() {
+                    if !*selected {
+                        continue;
+                    }
+                    let name = &loaded.objects[i].1;
+                    match loaded.orca_model.morph_paint(i, &self.morph_options) {
+                        Ok(report) => loaded.reports.push(format!("{}: {}", name, report)),
+                        Err(e) => {
+                            error!("Error growing or shrinking paint: {:?}", e);
+                            loaded.reports.push(format!("{}: {}", name, e));
+                        }
+                    }
+                }
+
+                let suffix = match self.morph_options.op {
+                    MorphOp::Dilate => "grown",
+                    MorphOp::Erode => "shrunk",
+                };
+                save_orca_output(
+                    self.output_folder.as_ref(),
+                    &loaded.path,
+                    &loaded.orca_model,
+                    suffix,
+                );
+            }
+        });
+
+        for report in loaded.reports.iter() {
+            ui.monospace(report);
+        }
+    }
+
     fn show_conversion(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
         let button = if self.processing_rx.is_some() {
             let _ = ui.button("Processing...");
@@ -752,6 +1256,113 @@ impl App {
     }
 }
 
+/// Saves a modified model as `<input name>_<suffix>.3mf` in the output folder
+fn save_orca_output(
+    output_folder: Option<&PathBuf>,
+    input: &Path,
+    model: &OrcaModel,
+    suffix: &str,
+) {
+    let Some(output_folder) = output_folder else {
+        error!("No output folder selected");
+        return;
+    };
+
+    let file_name = input
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("model");
+    let output_file_path = output_folder.join(format!("{}_{}.3mf", file_name, suffix));
+
+    debug!("Saving to: {:?}", output_file_path);
+    if let Err(e) = crate::save_load::save_orca_3mf(&output_file_path, model) {
+        error!("Error saving: {:?}", e);
+    }
+}
+
+/// Saves the paint of every painted object of a PrusaSlicer project as
+/// `<object>.paint.json` in the output folder
+fn export_ps_paint(output_folder: Option<&PathBuf>, input: &Path) {
+    let Some(output_folder) = output_folder else {
+        error!("No output folder selected");
+        return;
+    };
+    let (models, md) = match crate::save_load::load_3mf_ps(input) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Error loading {:?}: {:?}", input, e);
+            return;
+        }
+    };
+
+    for model in models.iter() {
+        for (i, object) in model.resources.object.iter().enumerate() {
+            let painted = object
+                .object
+                .get_mesh()
+                .is_some_and(|m| m.triangles.triangle.iter().any(|t| t.paint().is_some()));
+            if !painted {
+                continue;
+            }
+            match PaintSidecar::from_ps(model, md.as_ref(), i) {
+                Ok(sidecar) => {
+                    let name = sidecar
+                        .object_name
+                        .clone()
+                        .unwrap_or_else(|| format!("object_{}", i));
+                    let path = output_folder.join(format!("{}.paint.json", name));
+                    debug!("Saving paint to: {:?}", path);
+                    if let Err(e) = sidecar.save(&path) {
+                        error!("Error saving paint file: {:?}", e);
+                    }
+                }
+                Err(e) => error!("Error exporting paint of object {}: {:?}", i, e),
+            }
+        }
+    }
+}
+
+/// Applies a paint file to every matching object of a PrusaSlicer project, saving the result
+/// as `<name>_painted.3mf` in the output folder
+fn apply_ps_paint(output_folder: Option<&PathBuf>, sidecar: &PaintSidecar, input: &Path) {
+    let Some(output_folder) = output_folder else {
+        error!("No output folder selected");
+        return;
+    };
+    let (mut models, md) = match crate::save_load::load_3mf_ps(input) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Error loading {:?}: {:?}", input, e);
+            return;
+        }
+    };
+
+    let mut applied = 0;
+    for model in models.iter_mut() {
+        for i in 0..model.resources.object.len() {
+            match sidecar.apply_ps(model, md.as_ref(), i) {
+                Ok(_) => applied += 1,
+                Err(e) => debug!("Object {} not painted: {:?}", i, e),
+            }
+        }
+    }
+    if applied == 0 {
+        error!("No object in {:?} matches the paint file", input);
+        return;
+    }
+
+    let file_name = input
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("model");
+    let output_file_path = output_folder.join(format!("{}_painted.3mf", file_name));
+
+    debug!("Saving to: {:?}", output_file_path);
+    if let Err(e) = crate::save_load::save_ps_3mf(&models, md.as_ref(), &output_file_path) {
+        error!("Error saving: {:?}", e);
+    }
+}
+
 /// Preview hovering files:
 fn preview_files_being_dropped(ctx: &egui::Context) {
     use egui::*;
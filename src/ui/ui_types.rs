@@ -4,7 +4,13 @@ use anyhow::{anyhow, bail, ensure, Context, Result};
 use egui::{ColorImage, TextureHandle, Vec2};
 use tracing::{debug, error, info, trace, warn};
 
-use crate::{model_orca::OrcaModel, paint_convert::PaintConvertInfo};
+use crate::{
+    instancing::ComponentPairing,
+    model_orca::OrcaModel,
+    paint_convert::PaintConvertInfo,
+    paint_regions::{IslandOptions, MorphOptions},
+    paint_simplify::SimplifyMode,
+};
 
 #[derive(Default, serde::Deserialize, serde::Serialize)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
@@ -21,6 +27,7 @@ pub struct App {
     pub(super) input_files_splitting: Vec<PathBuf>,
     pub(super) input_files_conversion: Vec<PathBuf>,
     pub(super) input_files_instancing: Vec<PathBuf>,
+    pub(super) input_files_paint_tools: Vec<PathBuf>,
     pub(super) output_folder: Option<PathBuf>,
     #[serde(skip)]
     pub(super) processing_rx: Option<crossbeam_channel::Receiver<crate::ProcessingEvent>>,
@@ -30,6 +37,12 @@ pub struct App {
     pub(super) start_time: Option<Instant>,
     #[serde(skip)]
     pub(super) loaded_instance_file: Option<LoadedInstanceFile>,
+
+    pub(super) simplify_mode: SimplifyMode,
+    pub(super) island_options: IslandOptions,
+    pub(super) morph_options: MorphOptions,
+    #[serde(skip)]
+    pub(super) loaded_paint_tools_file: Option<LoadedPaintToolsFile>,
 }
 
 #[derive(Clone)]
@@ -48,6 +61,17 @@ pub struct LoadedInstanceFile {
     pub(super) preview_imgs: Vec<(usize, ColorImage)>,
     pub(super) preview_texture_handles: Vec<(usize, TextureHandle)>,
     pub(super) preview_size: Vec2,
+    pub(super) pairing_preview: Option<PairingPreview>,
+    /// mirror plane axis for mirrored instances, `None` to detect it
+    pub(super) mirror_axis: Option<usize>,
+}
+
+/// Part pairings for the current selection, shown before paint is copied
+#[derive(Clone)]
+pub struct PairingPreview {
+    pub(super) from: usize,
+    pub(super) to: Vec<bool>,
+    pub(super) pairings: Vec<(usize, Result<ComponentPairing, String>)>,
 }
 
 impl LoadedInstanceFile {
@@ -79,23 +103,7 @@ impl LoadedInstanceFile {
             })
             .collect();
 
-        let objects: Vec<_> = orca_model
-            .get_objects()
-            .iter()
-            .enumerate()
-            .map(|(i, ob)| {
-                let name = orca_model
-                    .md
-                    .get_object_by_id(ob.id)
-                    .unwrap()
-                    .get_name()
-                    .unwrap();
-
-                let painted = *orca_model.painted.get(&ob.id).unwrap_or(&false);
-
-                (i, name, painted)
-            })
-            .collect();
+        let objects = object_rows(&orca_model);
 
         let to_objects = vec![false; objects.len()];
 
@@ -113,6 +121,54 @@ impl LoadedInstanceFile {
             preview_imgs,
             preview_texture_handles: vec![],
             preview_size,
+            pairing_preview: None,
+            mirror_axis: None,
+        }
+    }
+}
+
+/// Index, name and whether it's painted, for each object in the model
+fn object_rows(orca_model: &OrcaModel) -> Vec<(usize, String, bool)> {
+    orca_model
+        .get_objects()
+        .iter()
+        .enumerate()
+        .map(|(i, ob)| {
+            let name = orca_model
+                .md
+                .get_object_by_id(ob.id)
+                .and_then(|o| o.get_name())
+                .unwrap_or_else(|| format!("Object {}", ob.id));
+
+            let painted = *orca_model.painted.get(&ob.id).unwrap_or(&false);
+
+            (i, name, painted)
+        })
+        .collect()
+}
+
+/// A model loaded in the paint tools tab, with the objects to work on
+#[derive(Clone)]
+pub struct LoadedPaintToolsFile {
+    pub(super) path: PathBuf,
+    pub(super) orca_model: OrcaModel,
+    pub(super) objects: Vec<(usize, String, bool)>,
+    pub(super) selected: Vec<bool>,
+    /// results of the last operation, one line per object
+    pub(super) reports: Vec<String>,
+}
+
+impl LoadedPaintToolsFile {
+    pub fn new(path: PathBuf, orca_model: OrcaModel) -> Self {
+        let objects = object_rows(&orca_model);
+        let selected = vec![false; objects.len()];
+
+        Self {
+            path,
+            orca_model,
+            objects,
+            selected,
+            reports: vec![],
         }
     }
 }
@@ -124,6 +180,7 @@ impl App {
             Tab::Conversion => &self.input_files_conversion,
             Tab::Splitting => &self.input_files_splitting,
             Tab::InstancePaint => &self.input_files_instancing,
+            Tab::PaintTools => &self.input_files_paint_tools,
         }
     }
 
@@ -133,6 +190,7 @@ impl App {
             Tab::Conversion => &mut self.input_files_conversion,
             Tab::Splitting => &mut self.input_files_splitting,
             Tab::InstancePaint => &mut self.input_files_instancing,
+            Tab::PaintTools => &mut self.input_files_paint_tools,
         }
     }
 }
@@ -143,6 +201,7 @@ pub enum Tab {
     Conversion,
     Splitting,
     InstancePaint,
+    PaintTools,
 }
 
 impl Default for Tab {
@@ -35,3 +35,77 @@ pub(crate) use print_matrix;
 //         eprintln!();
 //     }
 // }
+
+/// A 3MF `transform` attribute as a 4x4 matrix acting on column vectors
+///
+/// 3MF lists the 4x3 matrix row by row for row vectors, so it is transposed here.
+pub fn transform_3mf(t: &[f64; 12]) -> na::Matrix4<f64> {
+    let mut m = na::Matrix4x3::from_row_slice(t).insert_column(3, 0.);
+    // the implied last column of the 3MF matrix, without it composed transforms lose
+    // their translation
+    m[(3, 3)] = 1.;
+    m.transpose()
+}
+
+/// The 3MF form of `m`, the inverse of [`transform_3mf`]
+pub fn to_transform_3mf(m: &na::Matrix4<f64>) -> [f64; 12] {
+    std::array::from_fn(|k| m[(k % 3, k / 3)])
+}
+
+/// `m` row by row, the way slicers keep part matrices in their metadata
+pub fn matrix_string(m: &na::Matrix4<f64>) -> String {
+    m.transpose()
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Closest point to `p` on the triangle `t`, from Ericson's Real-Time Collision Detection
+pub fn closest_point_on_triangle(
+    p: &na::Vector3<f64>,
+    t: &[na::Vector3<f64>; 3],
+) -> na::Vector3<f64> {
+    let (a, b, c) = (t[0], t[1], t[2]);
+    let (ab, ac) = (b - a, c - a);
+
+    let ap = p - a;
+    let (d1, d2) = (ab.dot(&ap), ac.dot(&ap));
+    if d1 <= 0. && d2 <= 0. {
+        return a;
+    }
+
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(&bp), ac.dot(&bp));
+    if d3 >= 0. && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0. && d1 >= 0. && d3 <= 0. {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(&cp), ac.dot(&cp));
+    if d6 >= 0. && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0. && d2 >= 0. && d6 <= 0. {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0. && d4 - d3 >= 0. && d5 - d6 >= 0. {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = va + vb + vc;
+    if denom.abs() <= f64::EPSILON {
+        // degenerate triangle
+        return a;
+    }
+    a + ab * (vb / denom) + ac * (vc / denom)
+}